@@ -20,7 +20,8 @@ rustPlatform.buildRustPackage rec {
 
   src = ./.;
 
-  cargoHash = "sha256-5lfLAy/rxUMde2ffvcFNBa9AbMfi61eS6INKWk+qe7o=";
+  # Vendored from the lock file, so adding or updating a crate doesn't need a new hash here
+  cargoLock.lockFile = ./Cargo.lock;
 
   nativeBuildInputs = [
     pkg-config
@@ -19,17 +19,40 @@ in
       type = types.port;
       default = 7870;
       example = 46264;
-      description = "The port to listen on. Currently ignored and always uses 3030";
+      description = "The port to listen on";
+    };
+    socket = mkOption {
+      type = types.nullOr types.str;
+      default = null;
+      example = "/run/remote-text-server/server.sock";
+      description = "A Unix domain socket (or address, see ListenStream in systemd.socket(5)) to listen on through systemd socket activation instead of the port";
+    };
+    socketGroup = mkOption {
+      type = types.str;
+      default = "root";
+      example = "nginx";
+      description = "The group allowed to connect to the socket, such as that of a reverse proxy";
     };
   };
 
   config = mkIf cfg.enable {
+    systemd.sockets.remote-text-server = mkIf (cfg.socket != null) {
+      description = "RemoteText Server socket";
+      listenStreams = [ cfg.socket ];
+      socketConfig = {
+        SocketMode = "0660";
+        SocketGroup = cfg.socketGroup;
+      };
+      wantedBy = [ "sockets.target" ];
+    };
+
     systemd.services.remote-text-server = {
       description = "RemoteText Server";
 
+      # `exec` so the server keeps the PID systemd gave the socket to (LISTEN_PID)
       script = ''
         cd $STATE_DIRECTORY
-        ${cfg.package}/bin/remote-text-server --port ${toString cfg.port}
+        exec ${cfg.package}/bin/remote-text-server --port ${toString cfg.port}
       '';
 
       serviceConfig = {
@@ -49,6 +72,7 @@ in
       };
 
       wantedBy = [ "multi-user.target" ];
+      requires = mkIf (cfg.socket != null) [ "remote-text-server.socket" ];
       after = [ "network-online.target" ];
       wants = [ "network-online.target" ];
     };
@@ -0,0 +1,212 @@
+use std::fs;
+use std::path::PathBuf;
+
+use clap::Subcommand;
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::error::{ApiError, ErrorCode};
+use crate::{files, fsck, migrate};
+
+/// Offline maintenance commands, which work directly on the files directory
+///
+/// These don't coordinate with a running server, so stop it before changing anything.
+#[derive(Subcommand)]
+pub(crate) enum AdminCommand {
+    /// List every file with its name, creation time and last edit time
+    ///
+    /// Files that can't be read are reported on stderr, with the reason
+    List {
+        /// Print the list as JSON, in the same format as the listFiles endpoint
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show every commit of a file, with its parent and the branches pointing at it
+    History {
+        id: Uuid,
+        /// Print the history as JSON, in the same format as the getHistory endpoint
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print the content of a file at a commit
+    Cat {
+        id: Uuid,
+        hash: String,
+    },
+    /// Delete a file, its history and its previews
+    Delete {
+        id: Uuid,
+    },
+    /// Copy a file's repository, with every branch and commit, to a new directory
+    Export {
+        id: Uuid,
+        /// Where to create the copy. Must not exist, or be an empty directory
+        dest: PathBuf,
+    },
+    /// Check every repository and preview directory for problems
+    ///
+    /// Exits with a failure status when errors are found (and not repaired)
+    Fsck {
+        /// Move repositories with errors into the quarantine directory, and remove orphaned previews
+        #[arg(long)]
+        repair: bool,
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Convert every repository into a bare repository, keeping its history and branches
+    ///
+    /// Only the working trees, which the server never reads, are removed. Run again to finish a
+    ///   migration that was interrupted. Exits with a failure status if any repository couldn't be
+    ///   converted
+    MigrateBare {
+        /// Print what was done as JSON
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+pub(crate) fn run(command: AdminCommand, config: &Config) -> Result<(), String> {
+    match command {
+        AdminCommand::List { json } => list(config, json),
+        AdminCommand::History { id, json } => history(config, id, json),
+        AdminCommand::Cat { id, hash } => cat(config, id, hash),
+        AdminCommand::Delete { id } => delete(config, id),
+        AdminCommand::Export { id, dest } => export(config, id, dest),
+        AdminCommand::Fsck { repair, json } => fsck(config, repair, json),
+        AdminCommand::MigrateBare { json } => migrate_bare(config, json),
+    }
+}
+
+fn list(config: &Config, json: bool) -> Result<(), String> {
+    let repositories = files::repos(config);
+    let list = files::list_files(&repositories);
+    if json {
+        let output = serde_json::to_string_pretty(&list).map_err(|err| err.to_string())?;
+        println!("{output}");
+        return Ok(());
+    }
+    for summary in list.files {
+        println!("{}\t{}\t{}\t{}", summary.id, summary.created_time.format("%Y-%m-%dT%H:%M:%SZ"), summary.edited_time.format("%Y-%m-%dT%H:%M:%SZ"), summary.name);
+    }
+    for error in list.errors {
+        eprintln!("{}\terror\t{}", error.id, error.reason);
+    }
+    return Ok(());
+}
+
+fn history(config: &Config, id: Uuid, json: bool) -> Result<(), String> {
+    let repositories = files::repos(config);
+    let history = files::with_repo(&id, &repositories, |repo| files::get_history(&id, repo)).map_err(|code| describe_error(code, &id))?;
+    if json {
+        let output = serde_json::to_string_pretty(&history).map_err(|err| err.to_string())?;
+        println!("{output}");
+        return Ok(());
+    }
+    for commit in &history.commits {
+        let branches = history.refs.iter()
+            .filter(|git_ref| git_ref.hash == commit.hash)
+            .map(|git_ref| git_ref.name.as_str())
+            .collect::<Vec<_>>();
+        println!("{}\t{}\t{}", commit.hash, commit.parent.as_deref().unwrap_or("-"), branches.join(","));
+    }
+    return Ok(());
+}
+
+fn cat(config: &Config, id: Uuid, hash: String) -> Result<(), String> {
+    let repositories = files::repos(config);
+    let (_, content) = files::with_repo(&id, &repositories, |repo| files::get_file_contents(&id, &hash, repo)).map_err(|code| describe_error(code, &id))?;
+    print!("{content}");
+    return Ok(());
+}
+
+fn delete(config: &Config, id: Uuid) -> Result<(), String> {
+    let repositories = files::repos(config);
+    files::delete_file(&id, &repositories, config).map_err(|code| describe_error(code, &id))?;
+    let _ = fs::remove_dir_all(config.previews_dir.join(id.to_string()));
+    eprintln!("Deleted {id}");
+    return Ok(());
+}
+
+fn export(config: &Config, id: Uuid, dest: PathBuf) -> Result<(), String> {
+    let repositories = files::repos(config);
+    let exported = files::with_repo(&id, &repositories, |repo| Ok(files::export_file(&id, repo, &dest)))
+        .map_err(|code| describe_error(code, &id))?;
+    exported.map_err(|msg| format!("Unable to export {id}: {msg}"))?;
+    eprintln!("Exported {id} to {}", dest.display());
+    return Ok(());
+}
+
+fn fsck(config: &Config, repair: bool, json: bool) -> Result<(), String> {
+    let report = fsck::check(config);
+    let repaired = if repair { Some(fsck::repair(&report, config)) } else { None };
+    if json {
+        let output = serde_json::json!({ "report": report, "repair": repaired });
+        println!("{}", serde_json::to_string_pretty(&output).map_err(|err| err.to_string())?);
+    } else {
+        for issue in &report.issues {
+            let severity = match issue.severity {
+                fsck::Severity::Info => "info",
+                fsck::Severity::Warning => "warning",
+                fsck::Severity::Error => "error",
+            };
+            println!("{severity}\t{}\t{}", issue.path.display(), issue.problem);
+        }
+        if let Some(repaired) = &repaired {
+            for (from, to) in &repaired.quarantined {
+                println!("quarantined\t{}\t{}", from.display(), to.display());
+            }
+            for path in &repaired.removed {
+                println!("removed\t{}", path.display());
+            }
+            for (path, msg) in &repaired.failed {
+                println!("failed\t{}\t{msg}", path.display());
+            }
+        }
+    }
+
+    let errors = report.errors().count();
+    let unrepaired = match &repaired {
+        Some(repaired) => repaired.failed.len(),
+        None => errors,
+    };
+    eprintln!("{}", report.summary());
+    if unrepaired > 0 && repair {
+        return Err(format!("{unrepaired} problem(s) could not be repaired"));
+    }
+    if unrepaired > 0 {
+        return Err(format!("{unrepaired} error(s) found; run with --repair to quarantine broken repositories"));
+    }
+    return Ok(());
+}
+
+fn migrate_bare(config: &Config, json: bool) -> Result<(), String> {
+    let migration = migrate::to_bare(config);
+    if json {
+        println!("{}", serde_json::to_string_pretty(&migration).map_err(|err| err.to_string())?);
+    } else {
+        for id in &migration.converted {
+            println!("converted\t{id}");
+        }
+        for id in &migration.skipped {
+            println!("bare\t{id}");
+        }
+        for (id, msg) in &migration.failed {
+            println!("failed\t{id}\t{msg}");
+        }
+    }
+    eprintln!("{} converted, {} already bare, {} failed", migration.converted.len(), migration.skipped.len(), migration.failed.len());
+    if !migration.failed.is_empty() {
+        return Err(format!("{} repository(s) could not be converted", migration.failed.len()));
+    }
+    return Ok(());
+}
+
+// The shared file functions report errors as the response the API would send
+fn describe_error(err: ApiError, id: &Uuid) -> String {
+    match err.code {
+        ErrorCode::FileNotFound => format!("No file with ID {id}"),
+        ErrorCode::InvalidHash => "Invalid or unknown commit hash".to_string(),
+        _ => format!("Unable to access {id} ({}); run with --log-level info for details", err.message),
+    }
+}
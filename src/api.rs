@@ -1,12 +1,13 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 use uuid::Uuid;
 
 mod normal_date_format {
-    use chrono::{DateTime, Utc, TimeZone};
+    use chrono::{DateTime, NaiveDateTime, Utc};
     use serde::{self, Deserialize, Serializer, Deserializer};
 
-    const FORMAT: &'static str = "%Y-%m-%dT%H:%M:%SZ";
+    const FORMAT: &str = "%Y-%m-%dT%H:%M:%SZ";
     // 2023-04-14T06:29:29Z
 
     // The signature of a serialize_with function must follow the pattern:
@@ -41,36 +42,59 @@ mod normal_date_format {
         D: Deserializer<'de>,
     {
         let s = String::deserialize(deserializer)?;
-        Utc.datetime_from_str(&s, FORMAT).map_err(serde::de::Error::custom)
+        NaiveDateTime::parse_from_str(&s, FORMAT)
+            .map(|date| date.and_utc())
+            .map_err(serde::de::Error::custom)
     }
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
 pub(crate) struct File {
     pub(crate) name: String,
     pub(crate) id: Uuid,
     pub(crate) content: String
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
 pub(crate) struct FileSummary {
     pub(crate) name: String,
     pub(crate) id: Uuid,
     #[serde(with = "normal_date_format")]
+    #[schema(value_type = String, format = DateTime)]
     pub(crate) edited_time: DateTime<Utc>,
     #[serde(with = "normal_date_format")]
+    #[schema(value_type = String, format = DateTime)]
     pub(crate) created_time: DateTime<Utc>
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+/// The response to listFiles. Files that can't be read are listed in `errors` instead of `files`
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
+pub(crate) struct FileList {
+    pub(crate) files: Vec<FileSummary>,
+    pub(crate) errors: Vec<FileListError>,
+    /// How many files match the query, on this page and every other
+    pub(crate) total: usize,
+    /// The `cursor` for the next page, or `null` on the last one
+    pub(crate) next: Option<String>
+}
+
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
+pub(crate) struct FileListError {
+    pub(crate) id: Uuid,
+    pub(crate) reason: String
+}
+
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
 pub(crate) struct CreateFileResult {
     pub(crate) name: String,
     pub(crate) id: Uuid,
     pub(crate) hash: String,
     #[serde(with = "normal_date_format")]
+    #[schema(value_type = String, format = DateTime)]
     pub(crate) created_time: DateTime<Utc>
 }
 
+#[allow(dead_code)]
 #[derive(Serialize, Deserialize, Clone)]
 pub(crate) struct PreviewDetail {
     pub(crate) name: String,
@@ -78,30 +102,31 @@ pub(crate) struct PreviewDetail {
     pub(crate) r#type: PreviewDetailType,
     pub(crate) data: String
 }
+#[allow(dead_code)]
 #[derive(Serialize, Deserialize, Clone)]
 pub(crate) enum PreviewDetailType {
     PDF,
     HTML
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
 pub(crate) struct GitCommit {
     pub(crate) hash: String,
     pub(crate) parent: Option<String>
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
 pub(crate) struct GitRef {
     pub(crate) name: String,
     pub(crate) hash: String
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
 pub(crate) struct CompilationOutput {
     pub(crate) state: CompilationState,
     pub(crate) log: String
 }
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
 pub(crate) enum CompilationState {
     SUCCESS,
     FAILURE
@@ -109,13 +134,14 @@ pub(crate) enum CompilationState {
 
 //***
 
+#[allow(dead_code)]
 #[derive(Serialize, Deserialize, Clone)]
 pub(crate) struct FileIDAndOptionalGitHash {
     pub(crate) id: Uuid,
     pub(crate) hash: Option<String>
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
 pub(crate) struct GitHistory {
     pub(crate) commits: Vec<GitCommit>,
     pub(crate) refs: Vec<GitRef>
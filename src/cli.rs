@@ -0,0 +1,96 @@
+use clap::{Args, Parser, Subcommand};
+use serde_json::{Map, Value};
+
+use crate::admin::AdminCommand;
+use crate::config::ConfigArgs;
+
+// The command line of the server binary. Running without a subcommand is the same as `serve`,
+//   so `remote-text-server --port 7870` (as used by module.nix) keeps working
+#[derive(Parser)]
+#[command(version, about = "The server-side software for Remote Text", args_conflicts_with_subcommands = true)]
+pub(crate) struct Cli {
+    #[command(subcommand)]
+    pub(crate) command: Option<Command>,
+    #[command(flatten)]
+    pub(crate) serve: ServeArgs,
+}
+
+#[derive(Subcommand)]
+pub(crate) enum Command {
+    /// Run the server (the default when no subcommand is given)
+    Serve(ServeArgs),
+    /// Print version information
+    Version(VersionArgs),
+    /// Inspect and manage stored files without starting the server
+    Admin(AdminArgs),
+}
+
+#[derive(Args, Clone, Debug, Default)]
+pub(crate) struct ServeArgs {
+    #[command(flatten)]
+    pub(crate) config: ConfigArgs,
+}
+
+#[derive(Args)]
+pub(crate) struct VersionArgs {
+    /// Print every build detail as JSON
+    #[arg(short, long)]
+    pub(crate) verbose: bool,
+}
+
+#[derive(Args)]
+pub(crate) struct AdminArgs {
+    #[command(flatten)]
+    pub(crate) config: ConfigArgs,
+    #[command(subcommand)]
+    pub(crate) command: AdminCommand,
+}
+
+/// Build details captured by vergen at compile time (see `build.rs`)
+const BUILD_INFO: &[(&str, &str)] = &[
+    ("build_date", env!("VERGEN_BUILD_DATE")),
+    ("build_timestamp", env!("VERGEN_BUILD_TIMESTAMP")),
+    ("cargo_debug", env!("VERGEN_CARGO_DEBUG")),
+    ("cargo_features", env!("VERGEN_CARGO_FEATURES")),
+    ("cargo_opt_level", env!("VERGEN_CARGO_OPT_LEVEL")),
+    ("cargo_target_triple", env!("VERGEN_CARGO_TARGET_TRIPLE")),
+    ("git_branch", env!("VERGEN_GIT_BRANCH")),
+    ("git_commit_author_email", env!("VERGEN_GIT_COMMIT_AUTHOR_EMAIL")),
+    ("git_commit_author_name", env!("VERGEN_GIT_COMMIT_AUTHOR_NAME")),
+    ("git_commit_count", env!("VERGEN_GIT_COMMIT_COUNT")),
+    ("git_commit_date", env!("VERGEN_GIT_COMMIT_DATE")),
+    ("git_commit_message", env!("VERGEN_GIT_COMMIT_MESSAGE")),
+    ("git_commit_timestamp", env!("VERGEN_GIT_COMMIT_TIMESTAMP")),
+    ("git_describe", env!("VERGEN_GIT_DESCRIBE")),
+    ("git_sha", env!("VERGEN_GIT_SHA")),
+    ("rustc_channel", env!("VERGEN_RUSTC_CHANNEL")),
+    ("rustc_commit_date", env!("VERGEN_RUSTC_COMMIT_DATE")),
+    ("rustc_commit_hash", env!("VERGEN_RUSTC_COMMIT_HASH")),
+    ("rustc_host_triple", env!("VERGEN_RUSTC_HOST_TRIPLE")),
+    ("rustc_llvm_version", env!("VERGEN_RUSTC_LLVM_VERSION")),
+    ("rustc_semver", env!("VERGEN_RUSTC_SEMVER")),
+    ("sysinfo_cpu_brand", env!("VERGEN_SYSINFO_CPU_BRAND")),
+    ("sysinfo_cpu_core_count", env!("VERGEN_SYSINFO_CPU_CORE_COUNT")),
+    ("sysinfo_cpu_frequency", env!("VERGEN_SYSINFO_CPU_FREQUENCY")),
+    ("sysinfo_cpu_name", env!("VERGEN_SYSINFO_CPU_NAME")),
+    ("sysinfo_cpu_vendor", env!("VERGEN_SYSINFO_CPU_VENDOR")),
+    ("sysinfo_name", env!("VERGEN_SYSINFO_NAME")),
+    ("sysinfo_os_version", env!("VERGEN_SYSINFO_OS_VERSION")),
+    ("sysinfo_total_memory", env!("VERGEN_SYSINFO_TOTAL_MEMORY")),
+    ("sysinfo_user", env!("VERGEN_SYSINFO_USER")),
+];
+
+/// A one-line version string, e.g. `remote-text-server 0.1.3 (v0.1.3-4-g3c217fc)`
+pub(crate) fn short_version() -> String {
+    format!("{} {} ({})", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"), env!("VERGEN_GIT_DESCRIBE"))
+}
+
+/// Every vergen field, plus the package version, as a JSON object
+pub(crate) fn verbose_version() -> Value {
+    let mut info = Map::new();
+    info.insert("version".to_string(), Value::from(env!("CARGO_PKG_VERSION")));
+    for (key, value) in BUILD_INFO {
+        info.insert(key.to_string(), Value::from(*value));
+    }
+    Value::Object(info)
+}
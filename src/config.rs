@@ -0,0 +1,204 @@
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use clap::{Args, ValueEnum};
+use serde::Deserialize;
+
+/// Runtime configuration for the server
+///
+/// Values are resolved in increasing order of priority: built-in defaults, the TOML config file
+/// (if one is given), environment variables, and finally command-line flags.
+#[derive(Clone, Debug)]
+pub(crate) struct Config {
+    /// The IPv4 or IPv6 address to bind to
+    pub(crate) address: IpAddr,
+    pub(crate) port: u16,
+    /// A Unix domain socket to listen on instead of `address` and `port`
+    pub(crate) unix_socket: Option<PathBuf>,
+    /// Where files and their histories are kept
+    pub(crate) storage: StorageKind,
+    /// The directory holding one git repository per file
+    pub(crate) files_dir: PathBuf,
+    /// The directory holding compiled previews, keyed by file ID and commit hash
+    pub(crate) previews_dir: PathBuf,
+    /// Where `fsck --repair` moves repositories that can't be served
+    pub(crate) quarantine_dir: PathBuf,
+    /// Whether to quarantine broken repositories at startup, rather than only skipping them
+    pub(crate) fsck_repair: bool,
+    /// How many repositories are kept open at once. Others are opened when they're next used
+    pub(crate) open_repositories: usize,
+    /// The level used for `remote_text_server` logs when `RUST_LOG` is not set
+    pub(crate) log_level: String,
+    pub(crate) log_format: LogFormat,
+    /// PEM certificate chain and private key. When both are set, the server only accepts HTTPS
+    pub(crate) tls: Option<TlsConfig>,
+    /// How long running requests get to finish after SIGTERM/SIGINT before the server exits anyway
+    pub(crate) shutdown_timeout: Duration,
+}
+
+/// Where files and their histories are kept
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum StorageKind {
+    /// A git repository per file, in the files directory
+    #[default]
+    Git,
+    /// A bare git repository per file, in the files directory. Repositories made before switching
+    ///   are still served; `admin migrate-bare` converts them
+    Bare,
+    /// In memory only, so everything is lost when the server stops
+    Memory,
+}
+
+/// How log records are written to standard error
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum LogFormat {
+    /// Colorful, human-readable lines
+    #[default]
+    Pretty,
+    /// One JSON object per line, for log collectors
+    Json,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct TlsConfig {
+    pub(crate) cert_path: PathBuf,
+    pub(crate) key_path: PathBuf,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            address: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            port: 3030,
+            unix_socket: None,
+            files_dir: Path::new(".").join("files"),
+            previews_dir: Path::new(".").join("previews"),
+            quarantine_dir: Path::new(".").join("quarantine"),
+            fsck_repair: false,
+            open_repositories: 64,
+            storage: StorageKind::Git,
+            log_level: "warn".to_string(),
+            log_format: LogFormat::Pretty,
+            tls: None,
+            shutdown_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+/// The contents of a TOML config file. Every key is optional
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    address: Option<IpAddr>,
+    port: Option<u16>,
+    unix_socket: Option<PathBuf>,
+    files_dir: Option<PathBuf>,
+    previews_dir: Option<PathBuf>,
+    quarantine_dir: Option<PathBuf>,
+    fsck_repair: Option<bool>,
+    open_repositories: Option<usize>,
+    storage: Option<StorageKind>,
+    log_level: Option<String>,
+    log_format: Option<LogFormat>,
+    tls_cert: Option<PathBuf>,
+    tls_key: Option<PathBuf>,
+    shutdown_timeout: Option<u64>,
+}
+
+/// Configuration flags, each of which can also be set through an environment variable
+#[derive(Args, Clone, Debug, Default)]
+pub(crate) struct ConfigArgs {
+    /// Path to a TOML config file
+    #[arg(long, env = "REMOTE_TEXT_CONFIG", value_name = "FILE")]
+    pub(crate) config: Option<PathBuf>,
+    /// Address to listen on (IPv4 or IPv6) [default: 0.0.0.0]
+    #[arg(long, env = "REMOTE_TEXT_ADDRESS")]
+    pub(crate) address: Option<IpAddr>,
+    /// Port to listen on [default: 3030]
+    #[arg(short, long, env = "REMOTE_TEXT_PORT")]
+    pub(crate) port: Option<u16>,
+    /// Listen on this Unix domain socket instead of a TCP port
+    #[arg(long, env = "REMOTE_TEXT_UNIX_SOCKET", value_name = "PATH")]
+    pub(crate) unix_socket: Option<PathBuf>,
+    /// Directory where file repositories are stored [default: ./files]
+    #[arg(long, env = "REMOTE_TEXT_FILES_DIR", value_name = "DIR")]
+    pub(crate) files_dir: Option<PathBuf>,
+    /// Directory where previews are stored [default: ./previews]
+    #[arg(long, env = "REMOTE_TEXT_PREVIEWS_DIR", value_name = "DIR")]
+    pub(crate) previews_dir: Option<PathBuf>,
+    /// Directory where broken repositories are moved by the repair [default: ./quarantine]
+    #[arg(long, env = "REMOTE_TEXT_QUARANTINE_DIR", value_name = "DIR")]
+    pub(crate) quarantine_dir: Option<PathBuf>,
+    /// Quarantine broken repositories found by the startup check, instead of only skipping them
+    #[arg(long, env = "REMOTE_TEXT_FSCK_REPAIR")]
+    pub(crate) fsck_repair: bool,
+    /// How many repositories to keep open at once; the least recently used are closed [default: 64]
+    #[arg(long, env = "REMOTE_TEXT_OPEN_REPOSITORIES", value_name = "COUNT")]
+    pub(crate) open_repositories: Option<usize>,
+    /// Where to keep files: git repositories in --files-dir, bare ones, or in memory [default: git]
+    #[arg(long, env = "REMOTE_TEXT_STORAGE", value_name = "KIND", value_enum)]
+    pub(crate) storage: Option<StorageKind>,
+    /// Log level, used when RUST_LOG is not set [default: warn]
+    #[arg(long, env = "REMOTE_TEXT_LOG_LEVEL", value_name = "LEVEL")]
+    pub(crate) log_level: Option<String>,
+    /// Log format [default: pretty]
+    #[arg(long, env = "REMOTE_TEXT_LOG_FORMAT", value_name = "FORMAT", value_enum)]
+    pub(crate) log_format: Option<LogFormat>,
+    /// PEM certificate chain to serve HTTPS with. Reloaded on SIGHUP
+    #[arg(long, env = "REMOTE_TEXT_TLS_CERT", value_name = "FILE", requires = "tls_key")]
+    pub(crate) tls_cert: Option<PathBuf>,
+    /// PEM private key matching --tls-cert. Reloaded on SIGHUP
+    #[arg(long, env = "REMOTE_TEXT_TLS_KEY", value_name = "FILE", requires = "tls_cert")]
+    pub(crate) tls_key: Option<PathBuf>,
+    /// Seconds that running requests get to finish when shutting down [default: 30]
+    #[arg(long, env = "REMOTE_TEXT_SHUTDOWN_TIMEOUT", value_name = "SECONDS")]
+    pub(crate) shutdown_timeout: Option<u64>,
+}
+
+impl Config {
+    /// Builds the configuration from the defaults, the config file named in `args` and `args` itself
+    pub(crate) fn load(args: &ConfigArgs) -> Result<Config, String> {
+        let file = match &args.config {
+            Some(path) => {
+                let contents = fs::read_to_string(path)
+                    .map_err(|err| format!("Unable to read config file {:?}: {err}", path))?;
+                toml::from_str::<ConfigFile>(&contents)
+                    .map_err(|err| format!("Invalid config file {:?}: {err}", path))?
+            },
+            None => ConfigFile::default()
+        };
+        let defaults = Config::default();
+        let tls = match (args.tls_cert.clone().or(file.tls_cert), args.tls_key.clone().or(file.tls_key)) {
+            (Some(cert_path), Some(key_path)) => Some(TlsConfig { cert_path, key_path }),
+            (None, None) => None,
+            _ => return Err("Both a TLS certificate and a TLS key are required to enable TLS".to_string())
+        };
+        let open_repositories = args.open_repositories.or(file.open_repositories).unwrap_or(defaults.open_repositories);
+        if open_repositories == 0 {
+            return Err("At least one repository must be allowed to be open".to_string());
+        }
+        Ok(Config {
+            address: args.address.or(file.address).unwrap_or(defaults.address),
+            port: args.port.or(file.port).unwrap_or(defaults.port),
+            unix_socket: args.unix_socket.clone().or(file.unix_socket),
+            files_dir: args.files_dir.clone().or(file.files_dir).unwrap_or(defaults.files_dir),
+            previews_dir: args.previews_dir.clone().or(file.previews_dir).unwrap_or(defaults.previews_dir),
+            quarantine_dir: args.quarantine_dir.clone().or(file.quarantine_dir).unwrap_or(defaults.quarantine_dir),
+            fsck_repair: args.fsck_repair || file.fsck_repair.unwrap_or(defaults.fsck_repair),
+            open_repositories,
+            storage: args.storage.or(file.storage).unwrap_or(defaults.storage),
+            log_level: args.log_level.clone().or(file.log_level).unwrap_or(defaults.log_level),
+            log_format: args.log_format.or(file.log_format).unwrap_or(defaults.log_format),
+            tls,
+            shutdown_timeout: args.shutdown_timeout.or(file.shutdown_timeout).map(Duration::from_secs).unwrap_or(defaults.shutdown_timeout),
+        })
+    }
+
+    pub(crate) fn socket_addr(&self) -> SocketAddr {
+        SocketAddr::new(self.address, self.port)
+    }
+}
@@ -0,0 +1,175 @@
+use std::convert::Infallible;
+use std::error::Error;
+
+use serde::Serialize;
+use serde_json::{json, Value};
+use utoipa::ToSchema;
+use uuid::Uuid;
+use warp::http::StatusCode;
+use warp::reply::Response;
+use warp::Rejection;
+
+/// What went wrong, as a stable code clients can match on. Serialized in `SCREAMING_SNAKE_CASE`,
+///   e.g. `FILE_NOT_FOUND`
+///
+/// New codes may be added, but existing ones keep their name and status.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub(crate) enum ErrorCode {
+    /// 404: no file has the given ID
+    FileNotFound,
+    /// 409: the branch has moved on since the save's parent; `details.head` is the commit it points
+    ///   to now. Saving with `force` moves it anyway
+    BranchConflict,
+    /// 400: the hash isn't a valid git hash, or isn't a commit of the file
+    InvalidHash,
+    /// 400: the file name is one git can't store: empty, `.`, `..` or `.git`, or containing `/`
+    InvalidFileName,
+    /// 400: the branch name is empty or not a valid git branch name
+    InvalidBranchName,
+    /// 400: the file name has no extension, so there's no way to tell how to preview it
+    MissingFileExtension,
+    /// 404: the commit was never previewed
+    PreviewNotFound,
+    /// 404: the commit was previewed, but compiling it failed, so there's no output. The log is
+    ///   in previewFile's response
+    PreviewFailed,
+    /// 500: the compiler (latexmk or pandoc) couldn't be run
+    CompilerUnavailable,
+    /// 500: the file's repository (or other storage) couldn't be read or written
+    StorageError,
+    /// 500: the previews directory couldn't be read or written
+    PreviewStorageError,
+    /// 400: the request body isn't JSON, or doesn't have the fields the endpoint expects
+    InvalidBody,
+    /// 400: the query string doesn't have the parameters the endpoint expects
+    InvalidQuery,
+    /// 400: a required header is missing or invalid
+    InvalidHeader,
+    /// 411: the request has a body but no `Content-Length`
+    LengthRequired,
+    /// 413: the request body is over the endpoint's limit (4KiB, or 16MiB for createFile and saveFile)
+    BodyTooLarge,
+    /// 415: the request body isn't `application/json`
+    UnsupportedMediaType,
+    /// 404: there's no endpoint at this path
+    NotFound,
+    /// 405: the endpoint exists, but not for this method
+    MethodNotAllowed,
+    /// 503: the server is shutting down
+    ShuttingDown,
+    /// 500: anything else, including a request that panicked
+    InternalError,
+}
+
+impl ErrorCode {
+    pub(crate) fn status(&self) -> StatusCode {
+        match self {
+            ErrorCode::FileNotFound | ErrorCode::PreviewNotFound | ErrorCode::PreviewFailed | ErrorCode::NotFound => StatusCode::NOT_FOUND,
+            ErrorCode::InvalidHash | ErrorCode::InvalidFileName | ErrorCode::InvalidBranchName | ErrorCode::MissingFileExtension
+                | ErrorCode::InvalidBody | ErrorCode::InvalidQuery | ErrorCode::InvalidHeader => StatusCode::BAD_REQUEST,
+            ErrorCode::BranchConflict => StatusCode::CONFLICT,
+            ErrorCode::LengthRequired => StatusCode::LENGTH_REQUIRED,
+            ErrorCode::BodyTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            ErrorCode::UnsupportedMediaType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ErrorCode::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
+            ErrorCode::ShuttingDown => StatusCode::SERVICE_UNAVAILABLE,
+            ErrorCode::CompilerUnavailable | ErrorCode::StorageError | ErrorCode::PreviewStorageError | ErrorCode::InternalError => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// An error response: `{"code": ..., "message": ..., "details": ...}`, with the status for `code`
+///
+/// `message` is for people and may change; `details` is `null` or an object with whatever the
+///   client sent that caused the error, such as the file `id` or `hash`.
+#[derive(Serialize, Clone, Debug, ToSchema)]
+pub(crate) struct ApiError {
+    pub(crate) code: ErrorCode,
+    pub(crate) message: String,
+    pub(crate) details: Option<Value>,
+}
+
+impl ApiError {
+    pub(crate) fn new(code: ErrorCode, message: impl Into<String>) -> ApiError {
+        return ApiError { code, message: message.into(), details: None };
+    }
+
+    pub(crate) fn with_details(mut self, details: Value) -> ApiError {
+        self.details = Some(details);
+        return self;
+    }
+
+    pub(crate) fn file_not_found(id: &Uuid) -> ApiError {
+        return ApiError::new(ErrorCode::FileNotFound, "No file with this ID").with_details(json!({ "id": id }));
+    }
+
+    pub(crate) fn invalid_hash(hash: &str) -> ApiError {
+        return ApiError::new(ErrorCode::InvalidHash, "Not a commit of this file").with_details(json!({ "hash": hash }));
+    }
+
+    pub(crate) fn invalid_file_name(name: &str) -> ApiError {
+        return ApiError::new(ErrorCode::InvalidFileName, "Invalid file name").with_details(json!({ "name": name }));
+    }
+
+    pub(crate) fn invalid_branch_name(branch: &str) -> ApiError {
+        return ApiError::new(ErrorCode::InvalidBranchName, "Invalid branch name").with_details(json!({ "branch": branch }));
+    }
+
+    pub(crate) fn branch_conflict(branch: &str, head: &str) -> ApiError {
+        return ApiError::new(ErrorCode::BranchConflict, "Branch has moved on from the parent").with_details(json!({ "branch": branch, "head": head }));
+    }
+
+    pub(crate) fn storage(message: impl Into<String>) -> ApiError {
+        return ApiError::new(ErrorCode::StorageError, message);
+    }
+
+    pub(crate) fn status(&self) -> StatusCode {
+        self.code.status()
+    }
+}
+
+// So filters can reject with an error of their own, which `handle_rejection` sends as it is
+impl warp::reject::Reject for ApiError {}
+
+impl warp::Reply for ApiError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+        return warp::reply::with_status(warp::reply::json(&self), status).into_response();
+    }
+}
+
+/// Answers every rejection in the same format as the handlers' errors, so clients only need to
+///   handle one. Used with `Filter::recover` around all routes
+pub(crate) async fn handle_rejection(rejection: Rejection) -> Result<ApiError, Infallible> {
+    if rejection.is_not_found() {
+        return Ok(ApiError::new(ErrorCode::NotFound, "No such endpoint"));
+    }
+    if let Some(err) = rejection.find::<ApiError>() {
+        log::info!(target: "remote_text_server::error", "Rejected request: {}", err.message);
+        return Ok(err.clone());
+    }
+    let error = if let Some(err) = rejection.find::<warp::filters::body::BodyDeserializeError>() {
+        let reason = err.source().map(|source| source.to_string()).unwrap_or_else(|| err.to_string());
+        ApiError::new(ErrorCode::InvalidBody, "Request body is not valid for this endpoint").with_details(json!({ "reason": reason }))
+    } else if let Some(err) = rejection.find::<warp::reject::InvalidQuery>() {
+        ApiError::new(ErrorCode::InvalidQuery, "Query string is not valid for this endpoint").with_details(json!({ "reason": err.to_string() }))
+    } else if let Some(err) = rejection.find::<warp::reject::MissingHeader>() {
+        ApiError::new(ErrorCode::InvalidHeader, "Missing request header").with_details(json!({ "header": err.name() }))
+    } else if let Some(err) = rejection.find::<warp::reject::InvalidHeader>() {
+        ApiError::new(ErrorCode::InvalidHeader, "Invalid request header").with_details(json!({ "header": err.name() }))
+    } else if rejection.find::<warp::reject::LengthRequired>().is_some() {
+        ApiError::new(ErrorCode::LengthRequired, "Content-Length is required")
+    } else if rejection.find::<warp::reject::PayloadTooLarge>().is_some() {
+        ApiError::new(ErrorCode::BodyTooLarge, "Request body is too large")
+    } else if rejection.find::<warp::reject::UnsupportedMediaType>().is_some() {
+        ApiError::new(ErrorCode::UnsupportedMediaType, "Request body must be JSON")
+    } else if rejection.find::<warp::reject::MethodNotAllowed>().is_some() {
+        ApiError::new(ErrorCode::MethodNotAllowed, "Method not allowed for this endpoint")
+    } else {
+        log::error!(target: "remote_text_server::error", "Unhandled rejection: {:?}", rejection);
+        ApiError::new(ErrorCode::InternalError, "Internal server error")
+    };
+    log::info!(target: "remote_text_server::error", "Rejected request: {}", error.message);
+    return Ok(error);
+}
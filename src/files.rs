@@ -1,20 +1,114 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::fs;
 use std::io::Write;
 use std::net::SocketAddr;
-use std::path::Path;
-use std::sync::{Arc, Mutex};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, TryLockError};
 
-use chrono::{DateTime, NaiveDateTime, Utc};
-use git2::{IndexAddOption, Repository, Signature, Sort, Time};
+use chrono::{DateTime, Utc};
+use git2::{IndexAddOption, ObjectType, Oid, Repository, Signature, Sort, Time, Tree, TreeWalkMode, TreeWalkResult};
 use git2::build::CheckoutBuilder;
 use uuid::Uuid;
 
-use crate::api::{CreateFileResult, FileSummary};
-use crate::FILES_DIR;
+use crate::api::{CreateFileResult, FileList, FileSummary, GitCommit, GitHistory, GitRef};
+use crate::config::{Config, StorageKind};
+use crate::error::{ApiError, ErrorCode};
+use crate::index::Index;
+use crate::storage::{check_file_name, TreeExport};
 
-pub(crate) fn repos() -> Arc<Mutex<HashMap<Uuid, Repository>>> {
-    Arc::new(Mutex::new(if let Ok(entries) = fs::read_dir(FILES_DIR()) {
+/// A file's repository, which is only opened when it's used and may be closed again to keep within
+///   `Config::open_repositories`
+pub(crate) struct LazyRepo {
+    path: PathBuf,
+    repo: Option<Repository>,
+}
+
+impl LazyRepo {
+    fn open(&mut self) -> Result<&Repository, git2::Error> {
+        if self.repo.is_none() {
+            self.repo = Some(Repository::open(&self.path)?);
+        }
+        return Ok(self.repo.as_ref().unwrap());
+    }
+}
+
+/// One file's repository, with its own lock so that work on one file doesn't hold up any other
+pub(crate) type RepoHandle = Arc<Mutex<LazyRepo>>;
+
+/// Every file's repository by ID, which of them are open, and the index listFiles is served from
+pub(crate) struct Repositories {
+    repos: HashMap<Uuid, RepoHandle>,
+    /// IDs of the repositories that are open, least recently used first
+    open: VecDeque<Uuid>,
+    capacity: usize,
+    /// Behind its own lock, so summarizing files and saving the index never holds up requests
+    ///   waiting on this map
+    index: Arc<Mutex<Index>>,
+}
+
+/// The lock on this is only held to look up, add or remove a repository
+pub(crate) type Repos = Arc<Mutex<Repositories>>;
+
+impl Repositories {
+    pub(crate) fn len(&self) -> usize {
+        self.repos.len()
+    }
+
+    /// How many repositories are open right now
+    pub(crate) fn open_count(&self) -> usize {
+        self.open.len()
+    }
+
+    /// Adds a repository that was just created, and so is already open
+    pub(crate) fn insert(&mut self, uuid: Uuid, repo: Repository) {
+        let path = repo.workdir().unwrap_or(repo.path()).to_path_buf();
+        self.repos.insert(uuid, Arc::new(Mutex::new(LazyRepo { path, repo: Some(repo) })));
+        self.used(&uuid);
+    }
+
+    pub(crate) fn remove(&mut self, uuid: &Uuid) -> Option<RepoHandle> {
+        self.open.retain(|id| id != uuid);
+        self.repos.remove(uuid)
+    }
+
+    // Marks `uuid` as open and most recently used, then closes the least recently used repositories
+    //   over capacity. Ones in use are skipped, and closed once they're over capacity and free
+    fn used(&mut self, uuid: &Uuid) {
+        self.open.retain(|id| id != uuid);
+        self.open.push_back(*uuid);
+        let mut index = 0;
+        while self.open.len() > self.capacity && index < self.open.len() - 1 {
+            let id = self.open[index];
+            let closed = self.repos.get(&id)
+                .map(|repo| match repo.try_lock() {
+                    Ok(mut repo) => {
+                        repo.repo = None;
+                        true
+                    },
+                    Err(TryLockError::Poisoned(poisoned)) => {
+                        repo.clear_poison();
+                        poisoned.into_inner().repo = None;
+                        true
+                    },
+                    Err(TryLockError::WouldBlock) => false
+                })
+                .unwrap_or(true);
+            if closed {
+                log::trace!(target: "remote_text_server::repositories", "[{}] Closed repository", id);
+                self.open.remove(index);
+            } else {
+                index += 1;
+            }
+        }
+    }
+}
+
+/// Finds every file in the files directory. Repositories are opened when they're first used
+///
+/// The index saved in the files directory is loaded, and any file missing from it is summarized
+///   and added, so a missing index is rebuilt.
+pub(crate) fn repos(config: &Config) -> Repos {
+    let repos: HashMap<Uuid, RepoHandle> = if let Ok(entries) = fs::read_dir(&config.files_dir) {
         entries.into_iter()
             .filter_map(|entry| entry.ok())
             .filter_map(|entry| {
@@ -26,166 +120,235 @@ pub(crate) fn repos() -> Arc<Mutex<HashMap<Uuid, Repository>>> {
                 let uuid = Uuid::parse_str(entry.file_name().to_str()?).ok();
                 Some((entry, uuid?))
             })
-            .filter_map(|(entry, uuid)| Some((uuid, Repository::open(entry.path()).ok()?)))
-            .map(|(uuid, repo)| {
+            .map(|(entry, uuid)| {
                 log::info!(target: "remote_text_server::repositories", "Detected {}", uuid);
-                (uuid, repo)
+                (uuid, Arc::new(Mutex::new(LazyRepo { path: entry.path(), repo: None })))
             })
             .collect()
     } else {
         HashMap::new()
-    }))
+    };
+
+    let loaded = Index::load(&config.files_dir);
+    if loaded.is_none() && !repos.is_empty() {
+        log::warn!(target: "remote_text_server::index", "No index found, rebuilding it from {} repositories", repos.len());
+    }
+    let mut index = loaded.unwrap_or_else(|| Index::new(&config.files_dir));
+    let summarized = index.reconcile(repos.keys(), |uuid| {
+        let path = &lock_repo(uuid, &repos[uuid]).path;
+        return Repository::open(path).map(|repo| tips(&repo)).unwrap_or_default();
+    }, |uuid| {
+        let path = &lock_repo(uuid, &repos[uuid]).path;
+        let Ok(repo) = Repository::open(path) else {
+            log::error!(target: "remote_text_server::index", "[{}] Cannot open repository", uuid);
+            return Err("Cannot open repository");
+        };
+        return summarize_file(uuid, &repo);
+    });
+    log::info!(target: "remote_text_server::index", "Indexed {} file(s)", summarized);
+    Arc::new(Mutex::new(Repositories { repos, open: VecDeque::new(), capacity: config.open_repositories, index: Arc::new(Mutex::new(index)) }))
+}
+
+/// Locks the repository map. Every change to it is a single insert or remove, so a request that
+///   panicked while holding the lock can't have left it unusable; the lock is recovered rather than
+///   failing every later request
+pub(crate) fn lock_repos(repos: &Repos) -> MutexGuard<'_, Repositories> {
+    return repos.lock().unwrap_or_else(|poisoned| {
+        log::warn!(target: "remote_text_server::repositories", "Recovering repository map from a panicked request");
+        repos.clear_poison();
+        poisoned.into_inner()
+    });
+}
+
+/// Locks the index, without holding the lock on `repos` any longer than it takes to find it. Every
+///   change to the index is made in memory before it's saved, so it's recovered like `lock_repos`
+pub(crate) fn lock_index<T>(repos: &Repos, f: impl FnOnce(&mut Index) -> T) -> T {
+    let index = lock_repos(repos).index.clone();
+    let mut index = index.lock().unwrap_or_else(|poisoned| {
+        log::warn!(target: "remote_text_server::index", "Recovering index from a panicked request");
+        index.clear_poison();
+        poisoned.into_inner()
+    });
+    return f(&mut index);
+}
+
+// Locks one repository. A request that panicked while holding it may have left the open handle in
+//   any state, so it's closed and reopened on next use. What's on disk is consistent, since saves
+//   only move a branch once everything else is written
+fn lock_repo<'r>(uuid: &Uuid, repo: &'r RepoHandle) -> MutexGuard<'r, LazyRepo> {
+    return repo.lock().unwrap_or_else(|poisoned| {
+        log::warn!(target: "remote_text_server::repositories", "[{}] Recovering repository from a panicked request", uuid);
+        repo.clear_poison();
+        let mut repo = poisoned.into_inner();
+        repo.repo = None;
+        repo
+    });
+}
+
+/// Looks up a file's repository, holding the lock on `repos` only for the lookup
+pub(crate) fn find_repo(uuid: &Uuid, repos: &Repos) -> Result<RepoHandle, ApiError> {
+    let Some(repo) = lock_repos(repos).repos.get(uuid).cloned() else {
+        log::info!(target: "remote_text_server::repositories", "[{}] Request made for nonexistent file", uuid);
+        return Err(ApiError::file_not_found(uuid));
+    };
+    return Ok(repo);
+}
+
+/// Runs `f` on a file's repository while holding its lock, opening it first if needed
+pub(crate) fn with_repo<T>(uuid: &Uuid, repos: &Repos, f: impl FnOnce(&Repository) -> Result<T, ApiError>) -> Result<T, ApiError> {
+    let repo = find_repo(uuid, repos)?;
+    log::trace!(target: "remote_text_server::repositories", "[{}] Acquiring lock on repository", uuid);
+    let mut repo = lock_repo(uuid, &repo);
+    let repo = open_repo(uuid, &mut repo, repos).map_err(ApiError::storage)?;
+    return f(repo);
 }
 
-pub(crate) fn list_files(repos: Arc<Mutex<HashMap<Uuid, Repository>>>) -> Vec<FileSummary> {
+// Opens a locked repository if it isn't already, and counts it as used
+fn open_repo<'r>(uuid: &Uuid, repo: &'r mut LazyRepo, repos: &Repos) -> Result<&'r Repository, &'static str> {
+    let opened = repo.repo.is_none();
+    let Ok(repo) = repo.open() else {
+        log::error!(target: "remote_text_server::repositories", "[{}] Cannot open repository", uuid);
+        return Err("Cannot open repository");
+    };
+    if opened {
+        log::trace!(target: "remote_text_server::repositories", "[{}] Opened repository", uuid);
+    }
+    lock_repos(repos).used(uuid);
+    return Ok(repo);
+}
+
+/// Summarizes every file from the index, without reading any repository. Files that couldn't be
+///   summarized are reported in `errors` rather than failing the whole list
+pub(crate) fn list_files(repos: &Repos) -> FileList {
     log::trace!(target: "remote_text_server::list_files", "Listing files");
-    let list = repos.lock().unwrap().iter()
-        .map(|(uuid, repo)| {
-            if !Path::new(repo.path()).exists() {
-                log::error!(target: "remote_text_server::list_files", "[{}] Repository does not exist", uuid);
-                panic!()
-            }
-            let Some(path) = repo.path().parent() else {
-                log::error!(target: "remote_text_server::list_files", "[{}] Parent of .git dir does not exist", uuid);
-                panic!()
-            };
-            let Ok(entries) = fs::read_dir(path) else {
-                log::error!(target: "remote_text_server::list_files", "[{}] Cannot read entries in directory", uuid);
-                panic!()
-            };
-
-            log::trace!(target: "remote_text_server::list_files", "[{}] Creating revwalker", uuid);
-            let mut walker = repo.revwalk().ok().unwrap();
-            _ = walker.set_sorting(Sort::TIME);
-            _ = walker.push_head();
-            _ = repo.branches(None).map(|branches| {
-                branches
-                    .filter_map(|branch| branch.ok())
-                    // .for_each(|(branch, _)| {
-                    //     let Ok(Some(name)) = branch.name() else {
-                    //         return
-                    //     };
-                    //     println!("pushing {name}");
-                    //     // let y = x.push_ref(name);
-                    //     let y = x.push_ref(format!("refs/heads/{name}").as_str());
-                    //     println!("{:?}", y);
-                    // })
-                    // .filter_map(|(branch, _)| branch.get().target())
-                    // .for_each(|oid| {
-                    //     log::trace!(target: "remote_text_server::list_files", "[{}] Pushing ")
-                    //     let y = x.push(oid);
-                    //     println!("{:?}", y);
-                    // })
-                    .filter_map(|(branch, _)| {
-                        let target = branch.get().target();
-                        Some((branch, target?))
-                    })
-                    .for_each(|(branch, oid)| {
-                        log::trace!(target: "remote_text_server::list_files", "[{}] Pushing {:?} to refwalker", uuid, branch.name());
-                        let Ok(_) = walker.push(oid) else {
-                            log::warn!(target: "remote_text_server::list_files", "[{}] Failed to push {:?}", uuid, branch.name());
-                            return
-                        };
-                    })
-                    // .filter_map(|(branch, _)| {
-                    //     Some((branch.name().ok().to_owned(), branch.get().target()?))
-                    // })
-                    // .for_each(|(name, oid)| {
-                    //     log::trace!(target: "remote_text_server::list_files", "[{}] Pushing {:?} to refwalker", uuid, name);
-                    //     let Ok(_) = x.push(oid) else {
-                    //         log::warn!(target: "remote_text_server::list_files", "[{}] Failed to push {:?}", uuid, name);
-                    //         return
-                    //     };
-                    // })
-            });
-            let first_oid = walker.next().unwrap().unwrap();
-            log::trace!(target: "remote_text_server::list_files", "[{}] Found most recent commit; setting HEAD ({})", uuid, first_oid.to_string());
-
-            let Ok(_) = repo.set_head_detached(first_oid) else {
-                //Not sure why we'd get this error if we know that the commit exists
-                ////The hash we were given does not exist
-                log::error!(target: "remote_text_server::list_files", "[{}] Unable to set HEAD", &uuid);
-                panic!()
-            };
-            log::trace!(target: "remote_text_server::list_files", "[{}] Set HEAD; checking out", &uuid);
-            let Ok(_) = repo.checkout_head(Some(CheckoutBuilder::new().force())) else {
-                log::error!(target: "remote_text_server::list_files", "[{}] Unable to checkout", &uuid);
-                panic!()
-            };
-
-            let files = entries.into_iter()
-                .filter_map(|entry| entry.ok())
-                .filter_map(|entry| {
-                    let file_type = entry.file_type().ok();
-                    Some((entry, file_type?))
-                })
-                // .filter_map(|entry| Some((entry, entry.file_type().ok()?)))
-                .filter(|(_, file_type)| file_type.is_file())
-                .map(|(entry, _)| {
-                    entry.file_name()
-                })
-                .collect::<Vec<_>>();
-            if files.len() > 1 {
-                log::warn!(target: "remote_text_server::list_files", "[{}] Multiple files found", uuid);
-            }
-            let Some(fname )= files.first() else {
-                log::error!(target: "remote_text_server::list_files", "[{}] No files found", uuid);
-                panic!("no files found!")
-            };
-            let Some(_filename) = fname.to_str() else {
-                log::error!(target: "remote_text_server::list_files", "[{}] Cannot convert filename from OsStr to str", uuid);
-                panic!("Cannot convert filename {:?}", fname)
-            };
-            let filename = _filename.to_string();
-            log::trace!(target: "remote_text_server::list_files", "[{}] Found filename ({filename})", uuid);
-
-            let first_commit = repo.find_commit(first_oid).unwrap();
-            let first_naive_date = NaiveDateTime::from_timestamp_opt(first_commit.time().seconds(), 0).unwrap();
-            let first_date: DateTime<Utc> = DateTime::from_utc(first_naive_date, Utc);
-            log::trace!(target: "remote_text_server::list_files", "[{}] Found most recent timestamp ({})", uuid, first_date.to_string());
-
-            let Some(_last_oid) = walker.last() else {
-                log::trace!(target: "remote_text_server::list_files", "[{}] First commit is last commit", uuid);
-                return FileSummary {
-                    name: filename,
-                    id: *uuid,
-                    edited_time: first_date,
-                    created_time: first_date,
-                }
-            };
-            let Some(last_oid) = _last_oid.ok() else {
-                log::trace!(target: "remote_text_server::list_files", "[{}] Oldest commit is invalid", uuid);
-                return FileSummary {
-                    name: filename,
-                    id: *uuid,
-                    edited_time: first_date,
-                    created_time: first_date,
-                }
-            };
-            log::trace!(target: "remote_text_server::list_files", "[{}] Found oldest commit ({})", uuid, last_oid.to_string());
-            let last_commit = repo.find_commit(last_oid).unwrap();
-            let last_naive_date = NaiveDateTime::from_timestamp_opt(last_commit.time().seconds(), 0).unwrap();
-            let last_date: DateTime<Utc> = DateTime::from_utc(last_naive_date, Utc);
-            log::trace!(target: "remote_text_server::list_files", "[{}] Found oldest timestamp ({})", uuid, last_date.to_string());
-            //git log --all -1 --format=%cd
-            FileSummary {
-                name: filename,
-                id: *uuid,
-                edited_time: first_date,
-                created_time: last_date,
-            }
-        }).collect::<Vec<FileSummary>>();
-    log::info!(target: "remote_text_server::list_files", "Found {} file(s)", list.len());
+    let list = lock_index(repos, |index| index.list());
+    log::info!(target: "remote_text_server::list_files", "Found {} file(s), {} error(s)", list.files.len(), list.errors.len());
     return list;
 }
 
-pub(crate) fn create_file(file_name: String, file_content: Option<String>, addr: Option<SocketAddr>, repos: Arc<Mutex<HashMap<Uuid, Repository>>>) -> Result<CreateFileResult, &'static str> {
+/// Summarizes a file after it's created or changed, and updates the index
+pub(crate) fn reindex(uuid: &Uuid, repo: &Repository, repos: &Repos) {
+    let summary = summarize_file(uuid, repo);
+    let tips = tips(repo);
+    lock_index(repos, |index| index.update(*uuid, summary, tips));
+}
+
+/// Lists a file in the index's `errors` with `reason`, once it's no longer served from `repos`
+pub(crate) fn flag(uuid: &Uuid, reason: &str, repos: &Repos) {
+    lock_index(repos, |index| index.update(*uuid, Err(reason), Vec::new()));
+}
+
+/// Drops a file from the index, once it's no longer in `repos`
+pub(crate) fn unindex(uuid: &Uuid, repos: &Repos) {
+    lock_index(repos, |index| index.remove(uuid));
+}
+
+/// The commits HEAD and each branch point to, which are all a summary depends on
+fn tips(repo: &Repository) -> Vec<String> {
+    let head = repo.head().ok().and_then(|head| head.target());
+    let branches = repo.branches(None).into_iter().flatten()
+        .filter_map(|branch| branch.ok())
+        .filter_map(|(branch, _)| Some(format!("{}:{}", branch.name().ok()??, branch.get().target()?)));
+    return head.map(|oid| format!("HEAD:{}", oid)).into_iter().chain(branches).collect();
+}
+
+fn summarize_file(uuid: &Uuid, repo: &Repository) -> Result<FileSummary, &'static str> {
+    if !Path::new(repo.path()).exists() {
+        log::error!(target: "remote_text_server::list_files", "[{}] Repository does not exist", uuid);
+        return Err("Repository does not exist");
+    }
+
+    log::trace!(target: "remote_text_server::list_files", "[{}] Creating revwalker", uuid);
+    let Ok(mut walker) = repo.revwalk() else {
+        log::error!(target: "remote_text_server::list_files", "[{}] Cannot create revwalker", uuid);
+        return Err("Cannot walk commits");
+    };
+    _ = walker.set_sorting(Sort::TIME);
+    _ = walker.push_head();
+    _ = repo.branches(None).map(|branches| {
+        branches
+            .filter_map(|branch| branch.ok())
+            .filter_map(|(branch, _)| {
+                let target = branch.get().target();
+                Some((branch, target?))
+            })
+            .for_each(|(branch, oid)| {
+                log::trace!(target: "remote_text_server::list_files", "[{}] Pushing {:?} to refwalker", uuid, branch.name());
+                let Ok(_) = walker.push(oid) else {
+                    log::warn!(target: "remote_text_server::list_files", "[{}] Failed to push {:?}", uuid, branch.name());
+                    return
+                };
+            })
+    });
+    let Some(Ok(first_oid)) = walker.next() else {
+        log::error!(target: "remote_text_server::list_files", "[{}] No commits found", uuid);
+        return Err("Repository has no commits");
+    };
+    log::trace!(target: "remote_text_server::list_files", "[{}] Found most recent commit ({})", uuid, first_oid.to_string());
+
+    let Ok(tree) = repo.find_commit(first_oid).and_then(|commit| commit.tree()) else {
+        log::error!(target: "remote_text_server::list_files", "[{}] Cannot read tree of most recent commit", uuid);
+        return Err("Cannot read most recent commit");
+    };
+    let (filename, _) = tracked_file(uuid, &tree).inspect_err(|msg| {
+        log::error!(target: "remote_text_server::list_files", "[{}] {}", uuid, msg);
+    })?;
+    log::trace!(target: "remote_text_server::list_files", "[{}] Found filename ({filename})", uuid);
+
+    let Some(first_date) = commit_date(repo, first_oid) else {
+        log::error!(target: "remote_text_server::list_files", "[{}] Cannot read most recent commit", uuid);
+        return Err("Cannot read most recent commit");
+    };
+    log::trace!(target: "remote_text_server::list_files", "[{}] Found most recent timestamp ({})", uuid, first_date.to_string());
+
+    let Some(Ok(last_oid)) = walker.last() else {
+        log::trace!(target: "remote_text_server::list_files", "[{}] First commit is last commit", uuid);
+        return Ok(FileSummary {
+            name: filename,
+            id: *uuid,
+            edited_time: first_date,
+            created_time: first_date,
+        });
+    };
+    log::trace!(target: "remote_text_server::list_files", "[{}] Found oldest commit ({})", uuid, last_oid.to_string());
+    let Some(last_date) = commit_date(repo, last_oid) else {
+        log::error!(target: "remote_text_server::list_files", "[{}] Cannot read oldest commit", uuid);
+        return Err("Cannot read oldest commit");
+    };
+    log::trace!(target: "remote_text_server::list_files", "[{}] Found oldest timestamp ({})", uuid, last_date.to_string());
+    //git log --all -1 --format=%cd
+    return Ok(FileSummary {
+        name: filename,
+        id: *uuid,
+        edited_time: first_date,
+        created_time: last_date,
+    });
+}
+
+fn commit_date(repo: &Repository, oid: Oid) -> Option<DateTime<Utc>> {
+    let commit = repo.find_commit(oid).ok()?;
+    return DateTime::from_timestamp(commit.time().seconds(), 0);
+}
+
+pub(crate) fn create_file(file_name: String, file_content: Option<String>, addr: Option<SocketAddr>, repos: &Repos, config: &Config) -> Result<CreateFileResult, ApiError> {
     let now = Utc::now();
     let uuid = Uuid::new_v4();
     log::info!(target: "remote_text_server::create_file", "[{}] Creating new file", uuid);
-    let Ok(repo) = Repository::init(FILES_DIR().join(uuid.to_string())) else {
+    if let Err(err) = check_file_name(&file_name) {
+        log::info!(target: "remote_text_server::create_file", "[{}] Invalid file name ({})", uuid, file_name);
+        return Err(err);
+    }
+    let bare = config.storage == StorageKind::Bare;
+    let path = config.files_dir.join(uuid.to_string());
+    let Ok(repo) = (if bare { Repository::init_bare(&path) } else { Repository::init(&path) }) else {
         log::error!(target: "remote_text_server::create_file", "[{}] Cannot create repository", uuid);
-        return Err("Cannot create repository");
+        return Err(ApiError::storage("Cannot create repository"));
+    };
+    // Nothing refers to the repository until it's inserted into `repos`, so a failure removes it
+    let failed = |action: &str, err: String| {
+        log::error!(target: "remote_text_server::create_file", "[{}] Unable to {}: {}", uuid, action, err);
+        let _ = fs::remove_dir_all(&path);
+        ApiError::storage(format!("Unable to {action}"))
     };
     let time = Time::new(now.timestamp(), 0);
     let them = match addr {
@@ -195,22 +358,42 @@ pub(crate) fn create_file(file_name: String, file_content: Option<String>, addr:
             "Non Socket Remote User".to_string()
         }
     };
-    let fp = FILES_DIR().join(uuid.to_string()).join(&file_name);
-    let Ok(mut file) = std::fs::File::create(fp) else {
-        log::error!(target: "remote_text_server::create_file", "[{}] Unable to create file", uuid);
-        return Err("Unable to create file!");
-    };
-    if let Some(content) = file_content {
-        log::trace!(target: "remote_text_server::create_file", "[{}] Writing initial content to file", uuid);
-        file.write_all(content.as_ref()).unwrap();
-    }
-    let their_sig = Signature::new(&them, "blinky@remote-text.com", &time).unwrap();
-    let our_sig = Signature::new("Remote Text", "blinky@remote-text.com", &time).unwrap();
-    let mut index = repo.index().unwrap();
-    index.add_all(&["."], IndexAddOption::DEFAULT, None).unwrap();
-    index.write().unwrap();
-    let tree_id = index.write_tree().unwrap();
-    let co = repo.commit(Some("HEAD"), &their_sig, &our_sig, "", &repo.find_tree(tree_id).unwrap(), &vec![]).unwrap();
+    let tree_id = if bare {
+        // There's no working tree, so the file only goes into the object database
+        let content = file_content.unwrap_or_default();
+        let written = repo.blob(content.as_bytes()).and_then(|blob| {
+            let mut builder = repo.treebuilder(None)?;
+            builder.insert(&file_name, blob, 0o100644)?;
+            builder.write()
+        });
+        let tree_id = match written {
+            Ok(tree_id) => tree_id,
+            Err(err) if err.class() == git2::ErrorClass::Tree => {
+                log::info!(target: "remote_text_server::create_file", "[{}] Invalid file name ({}): {}", uuid, file_name, err.message());
+                let _ = fs::remove_dir_all(&path);
+                return Err(ApiError::invalid_file_name(&file_name));
+            },
+            Err(err) => return Err(failed("create file", err.message().to_string()))
+        };
+        tree_id
+    } else {
+        let fp = path.join(&file_name);
+        let mut file = std::fs::File::create(fp).map_err(|err| failed("create file", err.to_string()))?;
+        if let Some(content) = file_content {
+            log::trace!(target: "remote_text_server::create_file", "[{}] Writing initial content to file", uuid);
+            file.write_all(content.as_ref()).map_err(|err| failed("write file", err.to_string()))?;
+        }
+        let mut index = repo.index().map_err(|err| failed("open index", err.message().to_string()))?;
+        index.add_all(["."], IndexAddOption::DEFAULT, None).map_err(|err| failed("add file", err.message().to_string()))?;
+        index.write().map_err(|err| failed("write index", err.message().to_string()))?;
+        index.write_tree().map_err(|err| failed("write tree", err.message().to_string()))?
+    };
+    let their_sig = Signature::new(&them, "blinky@remote-text.com", &time).map_err(|err| failed("create signature", err.message().to_string()))?;
+    let our_sig = Signature::new("Remote Text", "blinky@remote-text.com", &time).map_err(|err| failed("create signature", err.message().to_string()))?;
+    let co = {
+        let tree = repo.find_tree(tree_id).map_err(|err| failed("read tree", err.message().to_string()))?;
+        repo.commit(Some("HEAD"), &their_sig, &our_sig, "", &tree, &[]).map_err(|err| failed("commit", err.message().to_string()))?
+    };
     log::info!(target: "remote_text_server::create_file", "[{}] Made initial commit ({})", uuid, co.to_string());
     let result = CreateFileResult {
         name: file_name,
@@ -218,8 +401,367 @@ pub(crate) fn create_file(file_name: String, file_content: Option<String>, addr:
         hash: co.to_string(),
         created_time: now,
     };
+    // Indexed first, so a save to the new file can't be overwritten by this older summary
+    reindex(&uuid, &repo, repos);
     log::trace!(target: "remote_text_server::create_file", "[{}] Inserting new repo into hash map", uuid);
-    repos.lock().unwrap().insert(uuid, repo);
+    lock_repos(repos).insert(uuid, repo);
     log::trace!(target: "remote_text_server::create_file", "[{}] Inserted new repo into hash map", uuid);
     return Ok(result);
-}
\ No newline at end of file
+}
+
+/// Commits `content` as the only file, named `name`, on top of `parent`, and points `branch` (and
+///   HEAD) at the new commit
+///
+/// Unless `force` is set, a branch that already exists must point at `parent`. Otherwise it has
+///   moved on since the client read it, and the save is refused with the branch's current head.
+///
+/// The blob, tree and commit are written to the object database before any ref is touched, so a
+///   failure leaves the repository as it was. In a repository with a working tree, it's checked out
+///   once the branch has moved.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn save_file(uuid: &Uuid, name: &str, content: &str, parent: &str, branch: &str, force: bool, addr: Option<SocketAddr>, repo: &Repository) -> Result<GitCommit, ApiError> {
+    //We want to do all our checks before we write anything
+    if let Err(err) = check_file_name(name) {
+        log::info!(target: "remote_text_server::save_file", "[{}] Invalid file name ({})", uuid, name);
+        return Err(err);
+    }
+    let Ok(parent_oid) = Oid::from_str(parent) else {
+        log::info!(target: "remote_text_server::save_file", "[{}] Parent is not a valid git hash ({})", uuid, parent);
+        return Err(ApiError::invalid_hash(parent));
+    };
+    let Ok(par) = repo.find_commit(parent_oid) else {
+        log::info!(target: "remote_text_server::save_file", "[{}] Unable to locate parent commit", uuid);
+        return Err(ApiError::invalid_hash(parent));
+    };
+    log::trace!(target: "remote_text_server::save_file", "[{}] Located parent commit ({})", uuid, par.id().to_string());
+    let branch_ref = format!("refs/heads/{}", branch);
+    if !git2::Reference::is_valid_name(&branch_ref) {
+        log::info!(target: "remote_text_server::save_file", "[{}] Invalid branch name ({})", uuid, branch);
+        return Err(ApiError::invalid_branch_name(branch));
+    }
+
+    let git_error = |action: &str, err: git2::Error| {
+        log::error!(target: "remote_text_server::save_file", "[{}] Unable to {}: {}", uuid, action, err.message());
+        ApiError::storage(format!("Unable to {action}"))
+    };
+    let head = match repo.find_reference(&branch_ref) {
+        Ok(reference) => reference.target(),
+        Err(err) if err.code() == git2::ErrorCode::NotFound => None,
+        Err(err) => return Err(git_error("read branch", err)),
+    };
+    let conflict = |head: Oid| {
+        log::info!(target: "remote_text_server::save_file", "[{}] {} is at {}, not {}", uuid, branch, head.to_string(), parent);
+        ApiError::branch_conflict(branch, &head.to_string())
+    };
+    if let Some(head) = head.filter(|head| *head != parent_oid && !force) {
+        return Err(conflict(head));
+    }
+    let blob = repo.blob(content.as_bytes()).map_err(|err| git_error("write blob", err))?;
+    let mut builder = repo.treebuilder(None).map_err(|err| git_error("create tree builder", err))?;
+    if let Err(err) = builder.insert(name, blob, 0o100644) {
+        log::info!(target: "remote_text_server::save_file", "[{}] Invalid file name ({}): {}", uuid, name, err.message());
+        return Err(ApiError::invalid_file_name(name));
+    }
+    let tree_id = builder.write().map_err(|err| git_error("write tree", err))?;
+    let tree = repo.find_tree(tree_id).map_err(|err| git_error("read tree", err))?;
+    log::trace!(target: "remote_text_server::save_file", "[{}] Wrote tree ({})", uuid, tree_id.to_string());
+
+    let time = Time::new(Utc::now().timestamp(), 0);
+    let them = match addr {
+        Some(addr) => addr.to_string(),
+        None => {
+            log::warn!(target: "remote_text_server::save_file", "[{}] Non-socket connection", uuid);
+            "Non Socket Remote User".to_string()
+        }
+    };
+    let their_sig = Signature::new(&them, "blinky@remote-text.com", &time).map_err(|err| git_error("create signature", err))?;
+    let our_sig = Signature::new("Remote Text", "blinky@remote-text.com", &time).map_err(|err| git_error("create signature", err))?;
+    log::trace!(target: "remote_text_server::save_file", "[{}] Making commit", uuid);
+    let co = repo.commit(None, &their_sig, &our_sig, "", &tree, &[&par]).map_err(|err| git_error("commit", err))?;
+    log::trace!(target: "remote_text_server::save_file", "[{}] Made commit ({})", uuid, co.to_string());
+
+    // Moving the branch is what makes the save visible, and is a single ref write. Without `force`
+    //   it's a compare-and-swap, in case something outside the server moved the branch meanwhile
+    let moved = match (force, head) {
+        (true, _) => repo.reference(&branch_ref, co, true, "saveFile"),
+        (false, Some(head)) => repo.reference_matching(&branch_ref, co, true, head, "saveFile"),
+        (false, None) => repo.reference(&branch_ref, co, false, "saveFile"),
+    };
+    if let Err(err) = moved {
+        if matches!(err.code(), git2::ErrorCode::Modified | git2::ErrorCode::Exists) {
+            let head = repo.refname_to_id(&branch_ref).unwrap_or(parent_oid);
+            return Err(conflict(head));
+        }
+        return Err(git_error("update branch", err));
+    }
+    log::trace!(target: "remote_text_server::save_file", "[{}] Pointed {} at {}", uuid, branch, co.to_string());
+    // The commit is saved either way, so a HEAD that can't be moved shouldn't fail the request
+    if let Err(err) = repo.set_head(&branch_ref) {
+        log::warn!(target: "remote_text_server::save_file", "[{}] Unable to point HEAD at {}: {}", uuid, branch, err.message());
+    } else if !repo.is_bare() {
+        // Bring the index and working tree along with HEAD, so the repository doesn't look modified
+        //   to git. Only the server writes to them, so there's nothing there to lose
+        if let Err(err) = repo.checkout_head(Some(CheckoutBuilder::new().force())) {
+            log::warn!(target: "remote_text_server::save_file", "[{}] Unable to check out {}: {}", uuid, branch, err.message());
+        }
+    }
+
+    return Ok(GitCommit {
+        hash: co.to_string(),
+        parent: Some(par.id().to_string()),
+    });
+}
+
+/// Reads the tracked file at commit `hash` straight from the object database, returning its name
+///   and content. HEAD and the working directory are left untouched
+pub(crate) fn get_file_contents(uuid: &Uuid, hash: &str, repo: &Repository) -> Result<(String, String), ApiError> {
+    let tree = commit_tree(uuid, hash, repo)?;
+    let (filename, blob_id) = tracked_file(uuid, &tree).map_err(|msg| {
+        log::error!(target: "remote_text_server::get_file_contents", "[{}] {} at {}", &uuid, msg, &hash);
+        ApiError::storage(msg)
+    })?;
+    let Ok(blob) = repo.find_blob(blob_id) else {
+        log::error!(target: "remote_text_server::get_file_contents", "[{}] Cannot read blob {}", &uuid, blob_id.to_string());
+        return Err(ApiError::storage("Cannot read file content"));
+    };
+    let Ok(content) = std::str::from_utf8(blob.content()) else {
+        log::error!(target: "remote_text_server::get_file_contents", "[{}] Content of {} is not valid UTF-8", &uuid, filename);
+        return Err(ApiError::storage("File content is not valid UTF-8"));
+    };
+    log::info!(target: "remote_text_server::get_file_contents", "[{}] Found file {}", &uuid, filename);
+    return Ok((filename, content.to_string()));
+}
+
+// Resolves `hash` to the tree of that commit. Unknown or malformed hashes are the client's fault
+fn commit_tree<'r>(uuid: &Uuid, hash: &str, repo: &'r Repository) -> Result<Tree<'r>, ApiError> {
+    let Ok(oid) = Oid::from_str(hash) else {
+        log::info!(target: "remote_text_server::get_file_contents", "[{}] Cannot construct OID from hash {}", &uuid, &hash);
+        return Err(ApiError::invalid_hash(hash));
+    };
+    let Ok(commit) = repo.find_commit(oid) else {
+        log::info!(target: "remote_text_server::get_file_contents", "[{}] No commit with hash {}", &uuid, &hash);
+        return Err(ApiError::invalid_hash(hash));
+    };
+    let Ok(tree) = commit.tree() else {
+        log::error!(target: "remote_text_server::get_file_contents", "[{}] Cannot read tree of commit {}", &uuid, &hash);
+        return Err(ApiError::storage("Cannot read commit"));
+    };
+    return Ok(tree);
+}
+
+/// The file tracked at the root of a commit's tree, as its name and blob ID
+fn tracked_file(uuid: &Uuid, tree: &Tree) -> Result<(String, Oid), &'static str> {
+    let blobs = tree.iter()
+        .filter(|entry| entry.kind() == Some(ObjectType::Blob))
+        .collect::<Vec<_>>();
+    if blobs.len() > 1 {
+        log::warn!(target: "remote_text_server::repositories", "[{}] Multiple files found", uuid);
+    }
+    let Some(entry) = blobs.first() else {
+        return Err("No file found in repository");
+    };
+    let Some(name) = entry.name() else {
+        return Err("File name is not valid UTF-8");
+    };
+    return Ok((name.to_string(), entry.id()));
+}
+
+/// Every commit in a file's repository, along with its branches
+pub(crate) fn get_history(uuid: &Uuid, repo: &Repository) -> Result<GitHistory, ApiError> {
+    let git_error = |action: &str, err: git2::Error| {
+        log::error!(target: "remote_text_server::get_history", "[{}] Unable to {}: {}", uuid, action, err.message());
+        ApiError::storage(format!("Unable to {action}"))
+    };
+    let odb = repo.odb().map_err(|err| git_error("open object database", err))?;
+    log::trace!(target: "remote_text_server::get_history", "[{}] Opened object database", uuid);
+    let mut commits = vec![];
+    odb.foreach(|oid| {
+        log::trace!(target: "remote_text_server::get_history", "[{}] Object {} located in database", uuid, oid.to_string());
+        let Ok(commit) = repo.find_commit(*oid) else {
+            log::trace!(target: "remote_text_server::get_history", "[{}] Object {} is not commit", uuid, oid.to_string());
+            return true;
+        };
+        let parent = commit.parent_ids().next().map(|cm| cm.to_string());
+        log::trace!(target: "remote_text_server::get_history", "[{}] Parent of commit {} is {:?}", uuid, oid.to_string(), parent);
+        // let parent = commit.parent(1).ok().map(|cm| cm.id().to_string());
+        commits.push(GitCommit { hash: commit.id().to_string(), parent });
+        true
+    }).map_err(|err| git_error("read objects", err))?;
+    // repo.references().iter().next().unwrap().
+    // for _ref in repo.references().iter() {
+    //     _ref
+    // }
+    log::trace!(target: "remote_text_server::get_history", "[{}] Iterating through branches", uuid);
+    let mut refs = vec![];
+    for b in repo.branches(None).map_err(|err| git_error("list branches", err))? {
+        log::trace!(target: "remote_text_server::get_history", "[{}] Investigating branch", uuid);
+        let (branch, _) = b.map_err(|err| git_error("read branch", err))?;
+        let Some(name) = branch.name().map_err(|err| git_error("read branch name", err))?.map(|name| name.to_string()) else {
+            log::error!(target: "remote_text_server::get_history", "[{}] Branch name is not UTF-8", uuid);
+            return Err(ApiError::storage("Unable to read branch name"));
+        };
+        log::trace!(target: "remote_text_server::get_history", "[{}] Branch name: {}", uuid, name);
+        let hash = branch.get().peel_to_commit().map_err(|err| git_error("read branch head", err))?.id().to_string();
+        log::trace!(target: "remote_text_server::get_history", "[{}] Branch ref: {}", uuid, hash);
+        refs.push(GitRef {
+            name,
+            hash,
+        });
+    }
+    log::info!(target: "remote_text_server::get_history", "[{}] History loaded", uuid);
+    let history = GitHistory {
+        commits,
+        refs,
+    };
+    return Ok(history);
+}
+
+/// Removes a file's repository from disk and from `repos`
+pub(crate) fn delete_file(uuid: &Uuid, repos: &Repos, config: &Config) -> Result<(), ApiError> {
+    // 1. Delete the repo object from the hash map, if it exists
+    let Some(repo) = lock_repos(repos).remove(uuid) else {
+        log::info!(target: "remote_text_server::delete_file", "[{}] Request made to delete nonexistent file", uuid);
+        return Err(ApiError::file_not_found(uuid));
+    };
+    log::info!(target: "remote_text_server::delete_file", "[{}] Target repo deleted", uuid);
+
+    // 2. Wait for requests still using the repository (e.g. a save) to finish, then close it
+    let mut repo = lock_repo(uuid, &repo);
+    repo.repo = None;
+    // Only now, so a save that was still running can't index the file again
+    unindex(uuid, repos);
+
+    // 3. Delete file on disk
+    let uuid_string = &uuid.to_string();
+    match fs::remove_dir_all(config.files_dir.join(uuid_string)) {
+        Ok(_) => {
+            log::info!(target: "remote_text_server::delete_file", "[{}] Target directory successfully removed", uuid);
+            return Ok(())
+        },
+        Err(_) => {
+            log::error!(target: "remote_text_server::delete_file", "[{}] Target directory was unable to be removed", uuid);
+            return Err(ApiError::storage("Unable to remove the file's repository"))
+        }
+    }
+}
+
+/// Copies a file's repository, with every branch and commit, into a new repository at `dest`
+///
+/// `dest` must not exist or be an empty directory. The copy has the same HEAD as the original,
+///   and its working tree is checked out.
+pub(crate) fn export_file(uuid: &Uuid, repo: &Repository, dest: &Path) -> Result<(), &'static str> {
+    if fs::read_dir(dest).map(|mut entries| entries.next().is_some()).unwrap_or(dest.exists()) {
+        log::info!(target: "remote_text_server::export_file", "[{}] Destination {:?} is not empty", uuid, dest);
+        return Err("Destination already exists and is not empty");
+    }
+    let Some(source) = repo.path().to_str() else {
+        log::error!(target: "remote_text_server::export_file", "[{}] Repository path is not valid UTF-8", uuid);
+        return Err("Repository path is not valid UTF-8");
+    };
+    let Ok(export) = Repository::init(dest) else {
+        log::error!(target: "remote_text_server::export_file", "[{}] Cannot create repository at {:?}", uuid, dest);
+        return Err("Cannot create repository");
+    };
+    log::trace!(target: "remote_text_server::export_file", "[{}] Fetching branches into {:?}", uuid, dest);
+    let fetched = export.remote_anonymous(source)
+        .and_then(|mut remote| remote.fetch(&["+refs/heads/*:refs/heads/*"], None, None));
+    if fetched.is_err() {
+        log::error!(target: "remote_text_server::export_file", "[{}] Unable to fetch branches", uuid);
+        return Err("Unable to copy branches");
+    }
+
+    let Ok(head) = repo.head() else {
+        log::error!(target: "remote_text_server::export_file", "[{}] Unable to read HEAD", uuid);
+        return Err("Unable to read HEAD");
+    };
+    let head_set = match (head.is_branch(), head.name(), head.target()) {
+        (true, Some(name), _) => export.set_head(name),
+        (_, _, Some(oid)) => export.set_head_detached(oid),
+        _ => {
+            log::error!(target: "remote_text_server::export_file", "[{}] HEAD does not point to a commit", uuid);
+            return Err("HEAD does not point to a commit");
+        }
+    };
+    if head_set.is_err() || export.checkout_head(Some(CheckoutBuilder::new().force())).is_err() {
+        log::error!(target: "remote_text_server::export_file", "[{}] Unable to checkout HEAD in {:?}", uuid, dest);
+        return Err("Unable to checkout the exported repository");
+    }
+    log::info!(target: "remote_text_server::export_file", "[{}] Exported to {:?}", uuid, dest);
+    return Ok(());
+}
+
+/// Writes every file in the tree of commit `hash` into a new temporary directory. The repository
+///   is never checked out
+pub(crate) fn export_tree(uuid: &Uuid, hash: &str, repo: &Repository) -> Result<TreeExport, ApiError> {
+    let tree = commit_tree(uuid, hash, repo)?;
+    let Ok(export) = TreeExport::new(uuid) else {
+        log::error!(target: "remote_text_server::export_tree", "[{}] Cannot create temporary directory", uuid);
+        return Err(ApiError::new(ErrorCode::PreviewStorageError, "Cannot create temporary directory"));
+    };
+    let mut failed = None;
+    let _ = tree.walk(TreeWalkMode::PreOrder, |root, entry| {
+        let Some(name) = entry.name().filter(|name| *name != "." && *name != "..") else {
+            return TreeWalkResult::Skip;
+        };
+        let path = export.dir().join(root).join(name);
+        let written = match entry.kind() {
+            Some(ObjectType::Tree) => fs::create_dir_all(&path),
+            // Symlinks could point outside of the export
+            Some(ObjectType::Blob) if entry.filemode() == 0o120000 => return TreeWalkResult::Ok,
+            Some(ObjectType::Blob) => repo.find_blob(entry.id())
+                .map_err(std::io::Error::other)
+                .and_then(|blob| fs::write(&path, blob.content())),
+            _ => return TreeWalkResult::Ok,
+        };
+        if let Err(err) = written {
+            failed = Some((path, err));
+            return TreeWalkResult::Abort;
+        }
+        TreeWalkResult::Ok
+    });
+    if let Some((path, err)) = failed {
+        log::error!(target: "remote_text_server::export_tree", "[{}] Cannot write {:?}: {}", uuid, path, err);
+        return Err(ApiError::new(ErrorCode::PreviewStorageError, "Cannot export the file for compiling"));
+    }
+    log::trace!(target: "remote_text_server::export_tree", "[{}] Exported {} to {:?}", uuid, hash, export.dir());
+    return Ok(export);
+}
+
+/// Removes preview directories that have no `.status` file
+///
+/// The status file is the last thing written when previewing, so a directory without one belongs
+///   to a compile that was interrupted (e.g. by a shutdown) and only takes up space until that commit
+///   is previewed again.
+///
+/// Returns the number of directories removed
+pub(crate) fn remove_partial_previews(config: &Config) -> usize {
+    let Ok(file_dirs) = fs::read_dir(&config.previews_dir) else {
+        return 0;
+    };
+    let mut removed = 0;
+    for commit_dir in file_dirs
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().map(|file_type| file_type.is_dir()).unwrap_or(false))
+        .filter_map(|entry| fs::read_dir(entry.path()).ok())
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().map(|file_type| file_type.is_dir()).unwrap_or(false))
+        .map(|entry| entry.path()) {
+        let has_status = fs::read_dir(&commit_dir)
+            .map(|entries| entries
+                .filter_map(|entry| entry.ok())
+                .any(|entry| entry.path().extension().map(|ext| ext == "status").unwrap_or(false)))
+            .unwrap_or(true);
+        if has_status {
+            continue;
+        }
+        match fs::remove_dir_all(&commit_dir) {
+            Ok(_) => {
+                log::warn!(target: "remote_text_server::previews", "Removed partial preview {:?}", commit_dir);
+                removed += 1;
+            },
+            Err(_) => log::error!(target: "remote_text_server::previews", "Unable to remove partial preview {:?}", commit_dir)
+        }
+    }
+    return removed;
+}
@@ -0,0 +1,302 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::Utc;
+use git2::{BranchType, ErrorCode, ObjectType, Oid, Repository};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::index::{INDEX_FILE, INDEX_TEMP_FILE};
+
+/// Something wrong with an entry in the files or previews directory
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub(crate) enum Problem {
+    /// A UUID-named directory that git can't open
+    NotARepository,
+    /// The repository's working directory can't be listed
+    UnreadableDirectory,
+    /// The repository has no commits at all
+    NoCommits,
+    /// HEAD points at a branch or commit that doesn't exist, while other commits do
+    DanglingHead,
+    /// HEAD points directly at a commit rather than at a branch
+    DetachedHead,
+    /// A branch or HEAD points at a commit that doesn't track any file
+    NoTrackedFile { commit: String },
+    /// A branch or HEAD points at a commit tracking more than one file
+    MultipleTrackedFiles { commit: String, count: usize },
+    /// A tracked file whose name isn't valid UTF-8
+    NonUtf8FileName { commit: String },
+    /// An entry in the files directory that isn't named after a UUID, so it is never served
+    UnknownEntry,
+    /// A preview directory without a matching file
+    OrphanedPreviews,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Severity {
+    /// Expected in normal use, such as the detached HEAD earlier versions left behind when reading an
+    ///   older commit
+    Info,
+    /// The entry can be served, but isn't in the state the server expects
+    Warning,
+    /// The entry can't be served; `--repair` quarantines it
+    Error,
+}
+
+impl Problem {
+    pub(crate) fn severity(&self) -> Severity {
+        match self {
+            Problem::DetachedHead => Severity::Info,
+            Problem::MultipleTrackedFiles { .. } | Problem::UnknownEntry | Problem::OrphanedPreviews => Severity::Warning,
+            _ => Severity::Error,
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::NotARepository => write!(f, "not a git repository"),
+            Problem::UnreadableDirectory => write!(f, "working directory cannot be read"),
+            Problem::NoCommits => write!(f, "repository has no commits"),
+            Problem::DanglingHead => write!(f, "HEAD does not point to a commit"),
+            Problem::DetachedHead => write!(f, "HEAD is detached"),
+            Problem::NoTrackedFile { commit } => write!(f, "commit {commit} tracks no file"),
+            Problem::MultipleTrackedFiles { commit, count } => write!(f, "commit {commit} tracks {count} files"),
+            Problem::NonUtf8FileName { commit } => write!(f, "commit {commit} tracks a file whose name is not valid UTF-8"),
+            Problem::UnknownEntry => write!(f, "not named after a file ID; ignored"),
+            Problem::OrphanedPreviews => write!(f, "previews for a file that does not exist"),
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub(crate) struct Issue {
+    pub(crate) path: PathBuf,
+    pub(crate) id: Option<Uuid>,
+    pub(crate) severity: Severity,
+    pub(crate) problem: Problem,
+}
+
+#[derive(Serialize, Clone, Debug, Default)]
+pub(crate) struct Report {
+    /// How many repositories were checked
+    pub(crate) repositories: usize,
+    pub(crate) issues: Vec<Issue>,
+}
+
+impl Report {
+    fn push(&mut self, path: &Path, id: Option<Uuid>, problem: Problem) {
+        self.issues.push(Issue { path: path.to_path_buf(), id, severity: problem.severity(), problem });
+    }
+
+    pub(crate) fn errors(&self) -> impl Iterator<Item = &Issue> {
+        self.issues.iter().filter(|issue| issue.severity == Severity::Error)
+    }
+
+    /// The files that can't be served, each with the first error found in it
+    pub(crate) fn broken(&self) -> HashMap<Uuid, String> {
+        let mut broken = HashMap::new();
+        for issue in self.errors() {
+            if let Some(id) = issue.id {
+                broken.entry(id).or_insert_with(|| issue.problem.to_string());
+            }
+        }
+        return broken;
+    }
+
+    fn count(&self, severity: Severity) -> usize {
+        self.issues.iter().filter(|issue| issue.severity == severity).count()
+    }
+
+    /// e.g. `Checked 12 repositories: 1 error(s), 0 warning(s)`
+    pub(crate) fn summary(&self) -> String {
+        format!("Checked {} repositories: {} error(s), {} warning(s)", self.repositories, self.count(Severity::Error), self.count(Severity::Warning))
+    }
+
+    /// Logs every issue, and a summary line
+    pub(crate) fn log(&self) {
+        for issue in &self.issues {
+            match issue.severity {
+                Severity::Info => log::info!(target: "remote_text_server::fsck", "{:?}: {}", issue.path, issue.problem),
+                Severity::Warning => log::warn!(target: "remote_text_server::fsck", "{:?}: {}", issue.path, issue.problem),
+                Severity::Error => log::error!(target: "remote_text_server::fsck", "{:?}: {}", issue.path, issue.problem),
+            }
+        }
+        log::info!(target: "remote_text_server::fsck", "{}", self.summary());
+    }
+}
+
+/// What [`repair`] did
+#[derive(Serialize, Clone, Debug, Default)]
+pub(crate) struct Repair {
+    /// Repositories moved into the quarantine directory, as (from, to)
+    pub(crate) quarantined: Vec<(PathBuf, PathBuf)>,
+    /// Preview directories removed
+    pub(crate) removed: Vec<PathBuf>,
+    /// Entries that could not be repaired, with the reason
+    pub(crate) failed: Vec<(PathBuf, String)>,
+}
+
+/// Checks every entry of the files and previews directories, without changing anything
+pub(crate) fn check(config: &Config) -> Report {
+    let mut report = Report::default();
+    let mut ids = HashSet::new();
+
+    if let Ok(entries) = fs::read_dir(&config.files_dir) {
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            let path = entry.path();
+            if entry.file_name() == INDEX_FILE || entry.file_name() == INDEX_TEMP_FILE {
+                continue;
+            }
+            let is_dir = entry.file_type().map(|file_type| file_type.is_dir()).unwrap_or(false);
+            let Some(id) = entry.file_name().to_str().and_then(|name| Uuid::parse_str(name).ok()) else {
+                report.push(&path, None, Problem::UnknownEntry);
+                continue;
+            };
+            if !is_dir {
+                report.push(&path, Some(id), Problem::UnknownEntry);
+                continue;
+            }
+            ids.insert(id);
+            report.repositories += 1;
+            for problem in check_repository(&path) {
+                report.push(&path, Some(id), problem);
+            }
+        }
+    }
+
+    if let Ok(entries) = fs::read_dir(&config.previews_dir) {
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            let id = entry.file_name().to_str().and_then(|name| Uuid::parse_str(name).ok());
+            if !id.map(|id| ids.contains(&id)).unwrap_or(false) {
+                report.push(&entry.path(), id, Problem::OrphanedPreviews);
+            }
+        }
+    }
+
+    return report;
+}
+
+fn check_repository(path: &Path) -> Vec<Problem> {
+    let Ok(repo) = Repository::open(path) else {
+        return vec![Problem::NotARepository];
+    };
+    let mut problems = vec![];
+    if let Some(workdir) = repo.workdir() {
+        if fs::read_dir(workdir).is_err() {
+            problems.push(Problem::UnreadableDirectory);
+        }
+    }
+
+    let branch_tips = repo.branches(Some(BranchType::Local))
+        .map(|branches| branches
+            .filter_map(|branch| branch.ok())
+            .filter_map(|(branch, _)| branch.get().peel_to_commit().ok())
+            .map(|commit| commit.id())
+            .collect::<Vec<_>>())
+        .unwrap_or_default();
+    let head = match repo.head() {
+        Ok(head) => {
+            if !head.is_branch() {
+                problems.push(Problem::DetachedHead);
+            }
+            match head.peel_to_commit() {
+                Ok(commit) => Some(commit.id()),
+                Err(_) => {
+                    problems.push(Problem::DanglingHead);
+                    None
+                }
+            }
+        },
+        Err(err) if err.code() == ErrorCode::UnbornBranch && branch_tips.is_empty() => {
+            problems.push(Problem::NoCommits);
+            None
+        },
+        Err(_) => {
+            problems.push(Problem::DanglingHead);
+            None
+        }
+    };
+
+    let mut checked = HashSet::new();
+    for oid in head.into_iter().chain(branch_tips) {
+        if checked.insert(oid) {
+            problems.extend(check_commit(&repo, oid));
+        }
+    }
+    return problems;
+}
+
+// The server stores exactly one file, with a UTF-8 name, at the root of every commit
+fn check_commit(repo: &Repository, oid: Oid) -> Option<Problem> {
+    let commit = oid.to_string();
+    let tree = repo.find_commit(oid).and_then(|commit| commit.tree()).ok()?;
+    let blobs = tree.iter()
+        .filter(|entry| entry.kind() == Some(ObjectType::Blob))
+        .collect::<Vec<_>>();
+    if blobs.iter().any(|entry| entry.name().is_none()) {
+        return Some(Problem::NonUtf8FileName { commit });
+    }
+    match blobs.len() {
+        0 => Some(Problem::NoTrackedFile { commit }),
+        1 => None,
+        count => Some(Problem::MultipleTrackedFiles { commit, count }),
+    }
+}
+
+/// Moves repositories with errors into `config.quarantine_dir`, and removes their previews and
+///   orphaned previews. Nothing is deleted from the files directory
+pub(crate) fn repair(report: &Report, config: &Config) -> Repair {
+    let mut repair = Repair::default();
+    let mut quarantined = HashSet::new();
+    for issue in report.errors() {
+        let Some(id) = issue.id else { continue };
+        if !quarantined.insert(id) {
+            continue;
+        }
+        match quarantine(&issue.path, id, config) {
+            Ok(dest) => {
+                log::warn!(target: "remote_text_server::fsck", "[{}] Quarantined {:?} to {:?}", id, issue.path, dest);
+                repair.quarantined.push((issue.path.clone(), dest));
+                let previews = config.previews_dir.join(id.to_string());
+                if previews.exists() && fs::remove_dir_all(&previews).is_ok() {
+                    repair.removed.push(previews);
+                }
+            },
+            Err(msg) => {
+                log::error!(target: "remote_text_server::fsck", "[{}] Unable to quarantine {:?}: {msg}", id, issue.path);
+                repair.failed.push((issue.path.clone(), msg));
+            }
+        }
+    }
+    for issue in report.issues.iter().filter(|issue| issue.problem == Problem::OrphanedPreviews) {
+        let removed = if issue.path.is_dir() { fs::remove_dir_all(&issue.path) } else { fs::remove_file(&issue.path) };
+        match removed {
+            Ok(_) => {
+                log::info!(target: "remote_text_server::fsck", "Removed orphaned previews {:?}", issue.path);
+                repair.removed.push(issue.path.clone());
+            },
+            Err(err) => repair.failed.push((issue.path.clone(), err.to_string()))
+        }
+    }
+    return repair;
+}
+
+fn quarantine(path: &Path, id: Uuid, config: &Config) -> Result<PathBuf, String> {
+    fs::create_dir_all(&config.quarantine_dir)
+        .map_err(|err| format!("Unable to create {:?}: {err}", config.quarantine_dir))?;
+    let mut dest = config.quarantine_dir.join(id.to_string());
+    if dest.exists() {
+        // Quarantined before, and restored or recreated since; keep both copies
+        dest = config.quarantine_dir.join(format!("{id}-{}", Utc::now().format("%Y%m%dT%H%M%SZ")));
+    }
+    fs::rename(path, &dest).map_err(|err| err.to_string())?;
+    return Ok(dest);
+}
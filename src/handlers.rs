@@ -1,28 +1,56 @@
 use std::collections::HashMap;
 use std::convert::Infallible;
+use std::ffi::OsString;
 use std::fs;
 use std::net::SocketAddr;
-use std::path::PathBuf;
-use std::process::{Command, Stdio};
-use std::sync::{Arc, Mutex, MutexGuard};
+use std::path::Path;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex, OnceLock, Weak};
+use std::time::Instant;
 
-use chrono::Utc;
-use git2::{IndexAddOption, Oid, Repository, Signature, Time};
-use git2::build::CheckoutBuilder;
+use git2::Oid;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::process::Command;
+use utoipa::ToSchema;
 use uuid::Uuid;
 use warp::http::HeaderValue;
 use warp::hyper::{Body, StatusCode};
 use warp::reply::Response;
 
-use crate::{files, FILES_DIR, PREVIEWS_DIR};
-use crate::api::{CompilationOutput, CompilationState, File, GitCommit, GitHistory, GitRef};
+use crate::config::Config;
+use crate::error::{ApiError, ErrorCode};
+use crate::{health, logging, openapi};
+use crate::listing::{self, ListQuery};
+use crate::storage::Store;
+use crate::metrics::{metrics, CompileOutcome};
+use crate::api::{CompilationOutput, CompilationState, File};
+
+pub(crate) async fn list_files(query: ListQuery, store: Store) -> Result<Box<dyn warp::Reply>, Infallible> {
+    let list = logging::spawn_blocking(move || store.list_files()).await;
+    return Ok(match listing::page(list, &query) {
+        Ok(page) => Box::new(warp::reply::json(&page)),
+        Err(code) => Box::new(code)
+    });
+}
 
-pub(crate) async fn list_files(repos: Arc<Mutex<HashMap<Uuid, Repository>>>) -> Result<impl warp::Reply, Infallible> {
-    return Ok(warp::reply::json(&files::list_files(repos)));
+// The original clients read a bare array of files, so that's what they still get. The files that
+//   can't be read are only in v2's `errors`
+pub(crate) async fn list_files_array(query: ListQuery, store: Store) -> Result<Box<dyn warp::Reply>, Infallible> {
+    let list = logging::spawn_blocking(move || store.list_files()).await;
+    let page = match listing::page(list, &query) {
+        Ok(page) => page,
+        Err(code) => return Ok(Box::new(code))
+    };
+    let mut response = warp::Reply::into_response(warp::reply::json(&page.files));
+    response.headers_mut().insert(listing::TOTAL_HEADER, HeaderValue::from(page.total));
+    if let Some(next) = page.next.and_then(|next| HeaderValue::from_str(&next).ok()) {
+        response.headers_mut().insert(listing::NEXT_HEADER, next);
+    }
+    return Ok(Box::new(response));
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
 pub(crate) struct NameAndOptionalContent {
     pub(crate) name: String,
     pub(crate) content: Option<String>
@@ -37,19 +65,19 @@ repository (?) to create a new file instance, as well as start its git history.
 // TODO: Make files save to a designated directory
 
 */
-pub(crate) async fn create_file(name: NameAndOptionalContent, addr: Option<SocketAddr>, repos: Arc<Mutex<HashMap<Uuid, Repository>>>) -> Result<Box<dyn warp::Reply>, Infallible> {
-    return match files::create_file(name.name, name.content, addr, repos) {
+pub(crate) async fn create_file(name: NameAndOptionalContent, addr: Option<SocketAddr>, store: Store) -> Result<Box<dyn warp::Reply>, Infallible> {
+    let result = logging::spawn_blocking(move || store.create_file(name.name, name.content, addr)).await;
+    return match result {
         Ok(result) => {
             Ok(Box::new(warp::reply::json(&result)))
         },
-        Err(msg) => {
-            Ok(Box::new(warp::reply::with_status(msg, StatusCode::INTERNAL_SERVER_ERROR)))
-            // Ok(Box::new(StatusCode::INTERNAL_SERVER_ERROR))
+        Err(err) => {
+            Ok(Box::new(err))
         }
     }
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
 pub(crate) struct FileIDAndGitHash {
     pub(crate) id: Uuid,
     pub(crate) hash: String
@@ -61,11 +89,11 @@ pub(crate) struct FileIDAndGitHash {
 TODO: Comment get_file() functionality & general description
 
 */
-pub(crate) async fn get_file(obj: FileIDAndGitHash, repos: Arc<Mutex<HashMap<Uuid, Repository>>>) -> Result<Box<dyn warp::Reply>, Infallible> {
-    log::trace!(target: "remote_text_server::get_file", "[{}] Acquiring lock on hash map", &obj.id);
-    let repos = repos.lock().unwrap();
-    log::trace!(target: "remote_text_server::get_file", "[{}] Calling get_file_contents", &obj.id);
-    return Ok(match get_file_contents(&obj.id, &obj.hash, &repos) {
+pub(crate) async fn get_file(obj: FileIDAndGitHash, store: Store) -> Result<Box<dyn warp::Reply>, Infallible> {
+    log::trace!(target: "remote_text_server::get_file", "[{}] Reading file", &obj.id);
+    let (id, hash) = (obj.id, obj.hash.clone());
+    let contents = logging::spawn_blocking(move || store.read_file(&id, &hash)).await;
+    return Ok(match contents {
         Ok((filename, content)) => {
             log::trace!(target: "remote_text_server::get_file", "[{}] Located filename and content", &obj.id);
             Box::new(warp::reply::json(&File {
@@ -81,77 +109,27 @@ pub(crate) async fn get_file(obj: FileIDAndGitHash, repos: Arc<Mutex<HashMap<Uui
     })
 }
 
-fn get_file_contents(uuid: &Uuid, hash: &String, repos: &MutexGuard<HashMap<Uuid, Repository>>) -> Result<(String, String), StatusCode> {
-    let Some(repo) = repos.get(&uuid) else {
-        log::info!(target: "remote_text_server::get_file_contents", "[{}] Request made to get nonexistent file", &uuid);
-        return Err(StatusCode::NOT_FOUND);
-    };
-    /*
-    repo.set_head(obj.hash.as_str()).unwrap();
-     */
-    let Ok(oid) = Oid::from_str(hash.as_str()) else {
-        log::info!(target: "remote_text_server::get_file_contents", "[{}] Cannot construct OID from hash {}", &uuid, &hash);
-        return Err(StatusCode::BAD_REQUEST);
-    };
-    log::trace!(target: "remote_text_server::get_file_contents", "[{}] Setting HEAD to {}", &uuid, oid.to_string());
-    let Ok(_) = repo.set_head_detached(oid) else {
-        //The hash we were given does not exist
-        log::info!(target: "remote_text_server::get_file_contents", "[{}] Unable to set HEAD (invalid hash)", &uuid);
-        return Err(StatusCode::BAD_REQUEST);
-    };
-    log::trace!(target: "remote_text_server::get_file_contents", "[{}] Set HEAD", &uuid);
-    log::trace!(target: "remote_text_server::get_file_contents", "[{}] Checking out HEAD", &uuid);
-    let Ok(_) = repo.checkout_head(Some(CheckoutBuilder::new().force())) else {
-        log::error!(target: "remote_text_server::get_file_contents", "[{}] Unable to checkout", &uuid);
-        return Err(StatusCode::INTERNAL_SERVER_ERROR);
-    };
-    if std::path::Path::new(repo.path()).exists() {
-        if let Some(path) = repo.path().parent() {
-            if let Ok(entries) = std::fs::read_dir(path) {
-                if let Some((fname, content)) = entries.into_iter()
-                    .filter_map(|entry| entry.ok())
-                    .filter_map(|entry| {
-                        let file_type = entry.file_type().ok();
-                        Some((entry, file_type?))
-                    })
-                    // .filter_map(|entry| Some((entry, entry.file_type().ok()?)))
-                    .filter(|(_, file_type)| file_type.is_file())
-                    .filter_map(|(entry, _)| {
-                        Some((entry.file_name(), std::fs::read_to_string(entry.path()).ok()?))
-                    })
-                    .collect::<Vec<_>>()
-                    .first() {
-                    let Ok(filename) = fname.clone().into_string() else {
-                        log::error!(target: "remote_text_server::get_file_contents", "[{}] Cannot convert filename '{:?}' to string", &uuid, fname.clone());
-                        return Err(StatusCode::INTERNAL_SERVER_ERROR);
-                    };
-                    log::info!(target: "remote_text_server::get_file_contents", "[{}] Found file {}", &uuid, filename);
-                    return Ok((filename, content.to_string()));
-                } else {
-                    log::error!(target: "remote_text_server::get_file_contents", "[{}] No file found in repo", &uuid);
-                    return Err(StatusCode::INTERNAL_SERVER_ERROR);
-                }
-            } else {
-                log::error!(target: "remote_text_server::get_file_contents", "[{}] Cannot read repo dir", &uuid);
-                return Err(StatusCode::INTERNAL_SERVER_ERROR);
-            }
-        } else {
-            log::error!(target: "remote_text_server::get_file_contents", "[{}] Parent to git dir does not exist", &uuid);
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
-        }
-    } else {
-        log::error!(target: "remote_text_server::get_file_contents", "[{}] No repo exists", &uuid);
-        return Err(StatusCode::INTERNAL_SERVER_ERROR);
-    }
-}
-
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
 pub(crate) struct FileAndHashAndBranchName {
     pub(crate) name: String,
     pub(crate) id: Uuid,
     pub(crate) content: String,
     pub(crate) parent: String,
-    pub(crate) branch: String
+    pub(crate) branch: String,
+    /// Move the branch even if it doesn't point at `parent`, discarding what it pointed to
+    #[serde(default)]
+    pub(crate) force: bool
+}
+
+/// The body of a v2 save, which takes the file ID and branch from the path
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
+pub(crate) struct NameAndContentAndParent {
+    pub(crate) name: String,
+    pub(crate) content: String,
+    pub(crate) parent: String,
+    /// Move the branch even if it doesn't point at `parent`, discarding what it pointed to
+    #[serde(default)]
+    pub(crate) force: bool
 }
 
 /*
@@ -162,136 +140,36 @@ TODO: Comment save_file() functionality & general description
 //TODO: update branch to point to new commit
 
 */
-pub(crate) async fn save_file(obj: FileAndHashAndBranchName, addr: Option<SocketAddr>, repos: Arc<Mutex<HashMap<Uuid, Repository>>>) -> Result<Box<dyn warp::Reply>, Infallible> {
-    if obj.branch == "" {
+pub(crate) async fn save_file(obj: FileAndHashAndBranchName, addr: Option<SocketAddr>, store: Store) -> Result<Box<dyn warp::Reply>, Infallible> {
+    if obj.branch.is_empty() {
         log::info!(target: "remote_text_server::save_file", "[{}] Tried to save to empty branch", obj.id);
-        return Ok(Box::new(StatusCode::BAD_REQUEST));
+        return Ok(Box::new(ApiError::invalid_branch_name(&obj.branch)));
     }
-    if obj.name == "" {
+    if obj.name.is_empty() {
         log::info!(target: "remote_text_server::save_file", "[{}] Tried to save to empty file name", obj.id);
-        return Ok(Box::new(StatusCode::BAD_REQUEST));
+        return Ok(Box::new(ApiError::invalid_file_name(&obj.name)));
     }
-    log::trace!(target: "remote_text_server::save_file", "[{}] Acquiring lock on hash map", &obj.id);
-    let repos = repos.lock().unwrap();
-    let Some(repo) = repos.get(&obj.id) else {
-        log::info!(target: "remote_text_server::save_file", "[{}] Request made to save nonexistent file", &obj.id);
-        return Ok(Box::new(StatusCode::NOT_FOUND));
-    };
-    let Some(path) = repo.path().parent() else {
-        log::error!(target: "remote_text_server::save_file", "[{}] Parent to git dir cannot be found", &obj.id);
-        return Ok(Box::new(StatusCode::INTERNAL_SERVER_ERROR));
-    };
-    if !path.exists() {
-        log::trace!(target: "remote_text_server::save_file", "[{}] Parent to git dir does not exist", &obj.id);
-        return Ok(Box::new(StatusCode::INTERNAL_SERVER_ERROR));
-    }
-
-    //We want to do all our checks before we make any changes on-disk
-    let Ok(parent_oid) = Oid::from_str(obj.parent.as_str()) else {
-        log::info!(target: "remote_text_server::save_file", "[{}] Parent is not a valid git hash ({})", &obj.id, obj.parent);
-        return Ok(Box::new(StatusCode::BAD_REQUEST));
-    };
-    let Ok(par) = repo.find_commit(parent_oid) else {
-        log::info!(target: "remote_text_server::save_file", "[{}] Unable to locate parent commit", &obj.id);
-        return Ok(Box::new(StatusCode::BAD_REQUEST));
-    };
-    log::trace!(target: "remote_text_server::save_file", "[{}] Located parent commit ({})", &obj.id, par.id().to_string());
-
-    if let Ok(entries) = std::fs::read_dir(path) {
-        for path in entries.into_iter()
-            .filter_map(|entry| entry.ok())
-            .filter_map(|entry| {
-                let file_type = entry.file_type().ok();
-                Some((entry, file_type?))
-            })
-            // .filter_map(|entry| Some((entry, entry.file_type().ok()?)))
-            .filter(|(_, file_type)| file_type.is_file())
-            .map(|(entry, _)| entry.path())
-            .into_iter() {
-            if std::fs::remove_file(path.clone()).is_ok() {
-                log::trace!(target: "remote_text_server::save_file", "[{}] Removed {:?}", &obj.id, path);
-                // index.add_path("../")
-                // index.add_path(path.strip_prefix(cd.as_path()).unwrap());
-            } else {
-                log::error!(target: "remote_text_server::save_file", "[{}] Unable to remove {:?}", &obj.id, path);
-            }
-        }
-    } else {
-        log::error!(target: "remote_text_server::save_file", "[{}] No files found in repo", &obj.id);
-    }
-    let file_path = path.join(&obj.name);
-    if std::fs::write(file_path, obj.content).is_err() {
-        log::error!(target: "remote_text_server::save_file", "[{}] Unable to write to file", &obj.id);
-        return Ok(Box::new(StatusCode::INTERNAL_SERVER_ERROR));
-    }
-    log::trace!(target: "remote_text_server::save_file", "[{}] Wrote content to {}", &obj.id, &obj.name);
-
-    //Perform commit
-    log::trace!(target: "remote_text_server::save_file", "[{}] Detaching head", &obj.id);
-    repo.set_head_detached(parent_oid).unwrap();
-    log::trace!(target: "remote_text_server::save_file", "[{}] Detached head", &obj.id);
-    log::trace!(target: "remote_text_server::save_file", "[{}] Creating branch pointing to parent commit ({})", &obj.id, obj.branch);
-    repo.branch(obj.branch.as_str(), &par, true).unwrap();
-    log::trace!(target: "remote_text_server::save_file", "[{}] Created branch", &obj.id);
-    let now = Utc::now();
-    let time = Time::new(now.timestamp(), 0);
-    let them = if addr.is_some() {
-        addr.unwrap().to_string()
-    } else {
-        "".to_string()
-    };
-    let their_sig = Signature::new(&them, "blinky@remote-text.com", &time).unwrap();
-    let our_sig = Signature::new("Remote Text", "blinky@remote-text.com", &time).unwrap();
-    let mut index = repo.index().unwrap();
-    index.add_all(&["."], IndexAddOption::DEFAULT, None).unwrap();
-    index.write().unwrap();
-    let tree_id = index.write_tree().unwrap();
-    log::trace!(target: "remote_text_server::save_file", "[{}] Making commit", &obj.id);
-    let co = repo.commit(Some(format!("refs/heads/{}", obj.branch).as_str()), &their_sig, &our_sig, "", &repo.find_tree(tree_id).unwrap(), &[&par]).unwrap();
-    log::trace!(target: "remote_text_server::save_file", "[{}] Made commit ({})", &obj.id, co.to_string());
-    log::trace!(target: "remote_text_server::save_file", "[{}] Checking out new commit", &obj.id);
-    repo.set_head(format!("refs/heads/{}", obj.branch).as_str()).unwrap();
-    log::trace!(target: "remote_text_server::save_file", "[{}] Checked out new commit", &obj.id);
-
-    let gc = GitCommit {
-        hash: co.to_string(),
-        parent: Some(par.id().to_string()),
-    };
-    return Ok(Box::new(warp::reply::json(&gc)));
+    let saved = logging::spawn_blocking(move || store.save_file(&obj.id, &obj.name, &obj.content, &obj.parent, &obj.branch, obj.force, addr)).await;
+    return Ok(match saved {
+        Ok(commit) => Box::new(warp::reply::json(&commit)),
+        Err(code) => Box::new(code)
+    });
 }
 
 /*
 // DELETE FILE //
 
 */
-pub(crate) async fn delete_file(obj: IdOnly, repos: Arc<Mutex<HashMap<Uuid, Repository>>>) -> Result<Box<dyn warp::Reply>, Infallible> {
-    // Before running the function, attempt to acquire a lock on the hash map
-    log::trace!(target: "remote_text_server::delete_file", "[{}] Acquiring lock on hash map", &obj.id);
-    let mut repos = repos.lock().unwrap();
-
-    // 1. See if repo exists
-    let Some(_) = repos.get(&obj.id) else {
-        log::info!(target: "remote_text_server::delete_file", "[{}] Request made to delete nonexistent file", &obj.id);
-        return Ok(Box::new(StatusCode::NOT_FOUND));
-    };
-
-    // 2. Delete the repo object from the hash map
-    repos.remove(&obj.id);
-    log::info!(target: "remote_text_server::delete_file", "[{}] Target repo deleted", &obj.id);
-
-    // 3. Delete file on disk
-    let uuid_string = &obj.id.to_string();
-    match fs::remove_dir_all(FILES_DIR().join(uuid_string)) {
+pub(crate) async fn delete_file(obj: IdOnly, store: Store, config: Arc<Config>) -> Result<Box<dyn warp::Reply>, Infallible> {
+    let id = obj.id;
+    let deleted = logging::spawn_blocking(move || store.delete_file(&id)).await;
+    return Ok(match deleted {
         Ok(_) => {
-            log::info!(target: "remote_text_server::delete_file", "[{}] Target directory successfully removed", &obj.id);
-            let _ = fs::remove_dir_all(PREVIEWS_DIR().join(uuid_string));
-            return Ok(Box::new(StatusCode::OK))
+            let _ = fs::remove_dir_all(config.previews_dir.join(obj.id.to_string()));
+            Box::new(StatusCode::OK)
         },
-        Err(_) => {
-            log::error!(target: "remote_text_server::delete_file", "[{}] Target directory was unable to be removed", &obj.id);
-            return Ok(Box::new(StatusCode::INTERNAL_SERVER_ERROR))
-        }
-    }
+        Err(code) => Box::new(code)
+    });
 }
 
 /*
@@ -303,11 +181,39 @@ TODO: Comment preview_file() functionality & general description
 TODO: do
 
 */
-pub(crate) async fn preview_file(obj: FileIDAndGitHash, repos: Arc<Mutex<HashMap<Uuid, Repository>>>) -> Result<Box<dyn warp::Reply>, Infallible> {
-    log::trace!(target: "remote_text_server::preview_file", "[{}] Acquiring lock on hash map", &obj.id);
-    let repos = repos.lock().unwrap();
-    log::trace!(target: "remote_text_server::preview_file", "[{}] Calling get_file_contents", &obj.id);
-    let (filename, _content) = match get_file_contents(&obj.id, &obj.hash, &repos) {
+type PreviewKey = (Uuid, String);
+
+// The previews being made or waited on, by file and commit
+fn previews_in_progress() -> &'static Mutex<HashMap<PreviewKey, Weak<tokio::sync::Mutex<()>>>> {
+    static IN_PROGRESS: OnceLock<Mutex<HashMap<PreviewKey, Weak<tokio::sync::Mutex<()>>>>> = OnceLock::new();
+    IN_PROGRESS.get_or_init(Default::default)
+}
+
+/// Waits until no other request is previewing this commit, then keeps the others waiting until the
+///   guard is dropped. A second request for the same preview reads what the first one made,
+///   rather than compiling into the same directory
+pub(crate) async fn lock_preview(id: Uuid, hash: &str) -> tokio::sync::OwnedMutexGuard<()> {
+    let lock = {
+        let mut in_progress = previews_in_progress().lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        in_progress.retain(|_, lock| lock.strong_count() > 0);
+        let key = (id, hash.to_string());
+        match in_progress.get(&key).and_then(Weak::upgrade) {
+            Some(lock) => lock,
+            None => {
+                let lock = Arc::new(tokio::sync::Mutex::new(()));
+                in_progress.insert(key, Arc::downgrade(&lock));
+                lock
+            }
+        }
+    };
+    return lock.lock_owned().await;
+}
+
+pub(crate) async fn preview_file(obj: FileIDAndGitHash, store: Store, config: Arc<Config>) -> Result<Box<dyn warp::Reply>, Infallible> {
+    log::trace!(target: "remote_text_server::preview_file", "[{}] Reading file", &obj.id);
+    let (id, hash, file_store) = (obj.id, obj.hash.clone(), store.clone());
+    let contents = logging::spawn_blocking(move || file_store.read_file(&id, &hash)).await;
+    let (filename, _content) = match contents {
         Ok((filename, content)) => (filename, content),
         Err(code) => {
             log::trace!(target: "remote_text_server::preview_file", "[{}] Unable to locate file", &obj.id);
@@ -323,9 +229,9 @@ pub(crate) async fn preview_file(obj: FileIDAndGitHash, repos: Arc<Mutex<HashMap
     let mut parts = filename.rsplit(".");
     let ext = parts.next().unwrap();
     let mut rest = parts.collect::<Vec<_>>();
-    if rest.len() == 0 {
+    if rest.is_empty() {
         log::warn!(target: "remote_text_server::preview_file", "[{}] No file extension (filename: {})", &obj.id, filename);
-        return Ok(Box::new(StatusCode::IM_A_TEAPOT))
+        return Ok(Box::new(ApiError::new(ErrorCode::MissingFileExtension, "The file name has no extension, so it can't be previewed").with_details(json!({ "name": filename }))))
     };
     rest.reverse();
     let name_root = rest.join(".");
@@ -334,27 +240,29 @@ pub(crate) async fn preview_file(obj: FileIDAndGitHash, repos: Arc<Mutex<HashMap
     let status_name = format!("{name_root}.status");
     log::trace!(target: "remote_text_server::preview_file", "[{}] Status name: {status_name}", &obj.id);
 
-    let previews_path = PREVIEWS_DIR().join(&obj.id.to_string());
+    let previews_path = config.previews_dir.join(obj.id.to_string());
     log::trace!(target: "remote_text_server::preview_file", "[{}] Creating preview path for file (if it doesn't exist)", &obj.id);
     let Ok(_) = fs::create_dir_all(&previews_path) else {
         log::error!(target: "remote_text_server::preview_file", "[{}] Cannot create preview path ({:?})", &obj.id, previews_path);
-        return Ok(Box::new(StatusCode::INTERNAL_SERVER_ERROR))
+        return Ok(Box::new(previews_error("Cannot create the preview directory")))
     };
 
     let this_commit_path = previews_path.join(&obj.hash);
     let log_path = this_commit_path.join(&log_name);
     let status_path = this_commit_path.join(&status_name);
-    if this_commit_path.exists() {
+    log::trace!(target: "remote_text_server::preview_file", "[{}] Waiting for other previews of commit {}", &obj.id, obj.hash);
+    let _preview = lock_preview(obj.id, &obj.hash).await;
+    if status_path.exists() {
         log::trace!(target: "remote_text_server::preview_file", "[{}] Preview path already exists for commit {}", &obj.id, obj.hash);
 
         let Ok(log_contents) = fs::read_to_string(log_path) else {
             log::error!(target: "remote_text_server::preview_file", "[{}] Although preview path exists, log file does not", &obj.id);
-            return Ok(Box::new(StatusCode::INTERNAL_SERVER_ERROR));
+            return Ok(Box::new(previews_error("Cannot read the preview log")));
         };
         log::trace!(target: "remote_text_server::preview_file", "[{}] Loaded preview log", &obj.id);
         let Ok(status_contents) = fs::read_to_string(status_path) else {
             log::error!(target: "remote_text_server::preview_file", "[{}] Although preview path exists, status code file does not", &obj.id);
-            return Ok(Box::new(StatusCode::INTERNAL_SERVER_ERROR));
+            return Ok(Box::new(previews_error("Cannot read the preview status")));
         };
         log::trace!(target: "remote_text_server::preview_file", "[{}] Loaded preview status", &obj.id);
         let status = match status_contents.as_str() {
@@ -362,24 +270,42 @@ pub(crate) async fn preview_file(obj: FileIDAndGitHash, repos: Arc<Mutex<HashMap
             "FAILURE" => CompilationState::FAILURE,
             _ => {
                 log::error!(target: "remote_text_server::preview_file", "[{}] Although status code file exists, it has invalid contents", &obj.id);
-                return Ok(Box::new(StatusCode::INTERNAL_SERVER_ERROR));
+                return Ok(Box::new(previews_error("Preview status is corrupt")));
             }
         };
         log::trace!(target: "remote_text_server::preview_file", "[{}] Converted preview status to enum", &obj.id);
+        metrics().record_preview_cache(true);
         log::info!(target: "remote_text_server::preview_file", "[{}] Loaded and returned cached preview", &obj.id);
         return Ok(Box::new(warp::reply::json(&CompilationOutput {
             state: status,
             log: log_contents,
         })));
     }
-    log::trace!(target: "remote_text_server::preview_file", "[{}] Preview path does not yet exist for commit {}", &obj.id, obj.hash);
+    log::trace!(target: "remote_text_server::preview_file", "[{}] No preview yet for commit {}", &obj.id, obj.hash);
+    metrics().record_preview_cache(false);
 
-    let Ok(_) = fs::create_dir(&this_commit_path) else {
+    if this_commit_path.exists() {
+        // The status file is written last, and nobody else is previewing this commit, so this is
+        //   what's left of a request that was cancelled mid-compile
+        log::warn!(target: "remote_text_server::preview_file", "[{}] Preview path for commit {} has no status file; recompiling", &obj.id, obj.hash);
+    }
+    let Ok(_) = fs::create_dir_all(&this_commit_path) else {
         log::error!(target: "remote_text_server::preview_file", "[{}] Unable to create preview path for commit {}", &obj.id, obj.hash);
-        return Ok(Box::new(StatusCode::INTERNAL_SERVER_ERROR))
+        return Ok(Box::new(previews_error("Cannot create the preview directory")))
     };
     log::trace!(target: "remote_text_server::preview_file", "[{}] Created preview path", &obj.id);
 
+    // The compilers read an export of the commit's tree, so the file isn't locked while they run
+    let (id, hash) = (obj.id, obj.hash.clone());
+    let export = match logging::spawn_blocking(move || store.export(&id, &hash)).await {
+        Ok(export) => export,
+        Err(code) => {
+            log::error!(target: "remote_text_server::preview_file", "[{}] Unable to export commit {}", &obj.id, obj.hash);
+            return Ok(Box::new(code));
+        }
+    };
+    let source = export.dir().join(&filename);
+
     log::trace!(target: "remote_text_server::preview_file", "[{}] Compiling based on file extension ({})", &obj.id, ext);
     match ext {
         "tex" => {
@@ -388,32 +314,45 @@ pub(crate) async fn preview_file(obj: FileIDAndGitHash, repos: Arc<Mutex<HashMap
             let output_name = format!("{name_root}.pdf");
             log::trace!(target: "remote_text_server::preview_file", "[{}] Output name: {}", &obj.id, output_name);
 
+            let Ok(output_path) = this_commit_path.canonicalize() else {
+                log::error!(target: "remote_text_server::preview_file", "[{}] Preview path for commit {} has disappeared", &obj.id, obj.hash);
+                return Ok(Box::new(previews_error("Cannot find the preview directory")));
+            };
+            // Passed as an OsString, so paths that aren't UTF-8 work too
+            let mut output_directory = OsString::from("-output-directory=");
+            output_directory.push(&output_path);
+
             // latexmk -pdf -output-directory=dest/ -interaction=nonstopmode -halt-on-error source/file.tex
+            let started = Instant::now();
             let res = Command::new("latexmk")
+                .kill_on_drop(true)
                 .arg("-pdf")
-                .arg(format!("-output-directory={}", &this_commit_path.canonicalize().unwrap().to_str().unwrap()))
+                .arg(output_directory)
                 .arg("-interaction=nonstopmode")
                 .arg("-halt-on-error")
-                .arg(format!("./files/{}/{filename}", &obj.id))
+                .arg(&source)
                 .stdout(Stdio::null())
                 .stderr(Stdio::null())
-                .status();
+                .status()
+                .await;
             // .spawn();
             let Ok(res) = res else {
                 log::error!(target: "remote_text_server::preview_file", "[{}] Unable to launch pdflatex", &obj.id);
-                return Ok(Box::new(StatusCode::INTERNAL_SERVER_ERROR));
+                metrics().record_compile("latexmk", CompileOutcome::Error, started.elapsed());
+                return Ok(Box::new(compiler_unavailable("latexmk")));
             };
+            metrics().record_compile("latexmk", if res.success() { CompileOutcome::Success } else { CompileOutcome::Failure }, started.elapsed());
             log::trace!(target: "remote_text_server::preview_file", "[{}] Launched pdflatex", &obj.id);
 
             let Ok(log_content) = fs::read_to_string(log_path) else {
                 log::error!(target: "remote_text_server::preview_file", "[{}] Unable to read pdflatex log", &obj.id);
-                return Ok(Box::new(StatusCode::INTERNAL_SERVER_ERROR));
+                return Ok(Box::new(previews_error("Cannot read the compiler log")));
             };
             log::trace!(target: "remote_text_server::preview_file", "[{}] Read pdflatex log", &obj.id);
 
             let Ok(_) = fs::write(status_path, if res.success() { "SUCCESS" } else { "FAILURE" }) else {
                 log::error!(target: "remote_text_server::preview_file", "[{}] Unable to write pdflatex status", &obj.id);
-                return Ok(Box::new(StatusCode::INTERNAL_SERVER_ERROR));
+                return Ok(Box::new(previews_error("Cannot write the preview status")));
             };
             log::trace!(target: "remote_text_server::preview_file", "[{}] Wrote pdflatex status", &obj.id);
 
@@ -427,7 +366,7 @@ pub(crate) async fn preview_file(obj: FileIDAndGitHash, repos: Arc<Mutex<HashMap
             log::trace!(target: "remote_text_server::preview_file", "[{}] Detected Markdown file", &obj.id);
 
             log::trace!(target: "remote_text_server::preview_file", "[{}] Converting with Pandoc", &obj.id);
-            match convert_with_pandoc(&obj.id, &name_root, &filename, &this_commit_path, &log_path, &status_path) {
+            match convert_with_pandoc(&obj.id, &name_root, &source, &this_commit_path, &log_path, &status_path).await {
                 Ok(output) => {
                     log::trace!(target: "remote_text_server::preview_file", "[{}] Got CompilationOutput", &obj.id);
                     return Ok(Box::new(warp::reply::json(&output)));
@@ -442,7 +381,7 @@ pub(crate) async fn preview_file(obj: FileIDAndGitHash, repos: Arc<Mutex<HashMap
             log::info!(target: "remote_text_server::preview_file", "[{}] Unknown file type ({})", &obj.id, ext);
 
             log::trace!(target: "remote_text_server::preview_file", "[{}] Attempting to convert with Pandoc", &obj.id);
-            match convert_with_pandoc(&obj.id, &name_root, &filename, &this_commit_path, &log_path, &status_path) {
+            match convert_with_pandoc(&obj.id, &name_root, &source, &this_commit_path, &log_path, &status_path).await {
                 Ok(output) => {
                     log::trace!(target: "remote_text_server::preview_file", "[{}] Got CompilationOutput", &obj.id);
                     return Ok(Box::new(warp::reply::json(&output)));
@@ -456,38 +395,57 @@ pub(crate) async fn preview_file(obj: FileIDAndGitHash, repos: Arc<Mutex<HashMap
     }
 }
 
-fn convert_with_pandoc(uuid: &Uuid, name_root: &String, filename: &String, this_commit_path: &PathBuf, log_path: &PathBuf, status_path: &PathBuf) -> Result<CompilationOutput, StatusCode> {
+fn previews_error(message: &str) -> ApiError {
+    return ApiError::new(ErrorCode::PreviewStorageError, message);
+}
+
+fn compiler_unavailable(compiler: &str) -> ApiError {
+    return ApiError::new(ErrorCode::CompilerUnavailable, format!("Unable to run {compiler}")).with_details(json!({ "compiler": compiler }));
+}
+
+async fn convert_with_pandoc(uuid: &Uuid, name_root: &String, source: &Path, this_commit_path: &Path, log_path: &Path, status_path: &Path) -> Result<CompilationOutput, ApiError> {
     let output_name = format!("{name_root}.html");
     log::trace!(target: "remote_text_server::preview_file_pandoc", "[{}] Output name: {}", &uuid, output_name);
 
+    let Ok(output_path) = this_commit_path.canonicalize() else {
+        log::error!(target: "remote_text_server::preview_file_pandoc", "[{}] Preview path has disappeared", &uuid);
+        return Err(previews_error("Cannot find the preview directory"));
+    };
+
+    let started = Instant::now();
     let res = Command::new("pandoc")
+        .kill_on_drop(true)
         .arg("--verbose")
         .arg("-s")
-        .args(["-o", this_commit_path.canonicalize().unwrap().join(output_name).to_str().unwrap()])
-        .arg(FILES_DIR().join(&uuid.to_string()).join(filename))
-        .output();
+        .arg("-o")
+        .arg(output_path.join(output_name))
+        .arg(source)
+        .output()
+        .await;
 
     let Ok(res) = res else {
         log::error!(target: "remote_text_server::preview_file_pandoc", "[{}] Unable to launch pandoc", &uuid);
-        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        metrics().record_compile("pandoc", CompileOutcome::Error, started.elapsed());
+        return Err(compiler_unavailable("pandoc"));
     };
+    metrics().record_compile("pandoc", if res.status.success() { CompileOutcome::Success } else { CompileOutcome::Failure }, started.elapsed());
     log::trace!(target: "remote_text_server::preview_file_pandoc", "[{}] Ran pandoc", &uuid);
 
     let Ok(_) = fs::write(log_path, &res.stderr) else {
         log::error!(target: "remote_text_server::preview_file_pandoc", "[{}] Unable to write pandoc log", &uuid);
-        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        return Err(previews_error("Cannot write the compiler log"));
     };
     log::trace!(target: "remote_text_server::preview_file_pandoc", "[{}] Wrote pandoc log to file", &uuid);
 
     let Ok(log_content) = std::str::from_utf8(&res.stderr) else {
         log::error!(target: "remote_text_server::preview_file_pandoc", "[{}] Pandoc log is not UTF-8", &uuid);
-        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        return Err(previews_error("Compiler log is not UTF-8"));
     };
     log::trace!(target: "remote_text_server::preview_file_pandoc", "[{}] Validated pandoc log as UTF-8", &uuid);
 
     let Ok(_) = fs::write(status_path, if res.status.success() { "SUCCESS" } else { "FAILURE" }) else {
         log::error!(target: "remote_text_server::preview_file_pandoc", "[{}] Unable to write pandoc status", &uuid);
-        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        return Err(previews_error("Cannot write the preview status"));
     };
     log::trace!(target: "remote_text_server::preview_file_pandoc", "[{}] Wrote pandoc status", &uuid);
 
@@ -504,28 +462,38 @@ fn convert_with_pandoc(uuid: &Uuid, name_root: &String, filename: &String, this_
 /// ## Arguments
 ///
 /// * `obj`: the file ID and the commit hash, from the JSON data in the HTTP request
-/// * `repos`: The lock on the list of files. Currently unused
+/// * `config`: The server configuration, used to locate the previews directory
 ///
 /// ## Returns
 ///
 /// * if the preview exists and can be successfully read, the contents of the previewed file
 /// * if the file was never previewed or the preview failed, HTTP 404
 /// * HTTP 500 otherwise (primarily when files cannot be read)
-pub(crate) async fn get_preview(obj: FileIDAndGitHash, _repos: Arc<Mutex<HashMap<Uuid, Repository>>>) -> Result<Box<dyn warp::Reply>, Infallible> {
-    // `preview_path` looks like `PREVIEWS_DIR/f204bae2-4c98-4952-86e6-cb02bc72049b/a0a81fdd89425113d9c1703401039c68ee3d855e`
-    let preview_path = PREVIEWS_DIR().join(obj.id.to_string()).join(obj.hash);
+pub(crate) async fn get_preview(obj: FileIDAndGitHash, store: Store, config: Arc<Config>) -> Result<Box<dyn warp::Reply>, Infallible> {
+    // The hash becomes part of the path, so it's checked before anything is read
+    if Oid::from_str(&obj.hash).is_err() {
+        log::info!(target: "remote_text_server::get_preview", "[{}] Not a valid git hash ({})", obj.id, obj.hash);
+        return Ok(Box::new(ApiError::invalid_hash(&obj.hash)));
+    }
+    let (id, hash) = (obj.id, obj.hash.clone());
+    if let Err(code) = logging::spawn_blocking(move || store.read_file(&id, &hash)).await {
+        log::info!(target: "remote_text_server::get_preview", "[{}] Unable to locate commit {}", obj.id, obj.hash);
+        return Ok(Box::new(code));
+    }
+    // `preview_path` looks like `previews_dir/f204bae2-4c98-4952-86e6-cb02bc72049b/a0a81fdd89425113d9c1703401039c68ee3d855e`
+    let preview_path = config.previews_dir.join(obj.id.to_string()).join(&obj.hash);
     log::trace!(target: "remote_text_server::get_preview", "[{}] Looking for preview path '{:?}'", obj.id, preview_path);
     if !preview_path.exists() {
         // If the path does not exist, then the file was never previewed
         log::info!(target: "remote_text_server::get_preview", "[{}] Preview path does not exist", obj.id);
-        return Ok(Box::new(StatusCode::NOT_FOUND))
+        return Ok(Box::new(ApiError::new(ErrorCode::PreviewNotFound, "This commit was never previewed").with_details(json!({ "id": obj.id, "hash": obj.hash }))))
     }
 
     let Ok(entries) = fs::read_dir(preview_path) else {
         // I think this error would only happen if the permissions on the folder don't allow us
         //   to read it, since we already know that it exists
         log::error!(target: "remote_text_server::get_preview", "[{}] Cannot read preview path, though it exists", obj.id);
-        return Ok(Box::new(StatusCode::INTERNAL_SERVER_ERROR))
+        return Ok(Box::new(previews_error("Cannot read the preview directory")))
     };
 
     let items = entries.into_iter() // Iterate over all entries in dir
@@ -554,17 +522,17 @@ pub(crate) async fn get_preview(obj: FileIDAndGitHash, _repos: Arc<Mutex<HashMap
         .collect::<Vec<_>>(); // Collect into Vec (i.e. list/array)
 
     let Some((status_file_name, status_path, _)) = items.iter().find(|(_, _, extension)| {
-        extension == &"status" // First file with a ".status" extension
+        extension == "status" // First file with a ".status" extension
     }) else {
         // If we can't find it, either the file was never previewed or the status file has disappeared
         // Since we know the file has been previewed, it's unclear what's happened, so bail
         log::error!(target: "remote_text_server::get_preview", "[{}] Cannot locate preview status file", obj.id);
-        return Ok(Box::new(StatusCode::INTERNAL_SERVER_ERROR));
+        return Ok(Box::new(previews_error("Preview status is missing")));
     };
     let Ok(status_contents) = fs::read_to_string(status_path) else {
         // Probably a permissions error again
         log::error!(target: "remote_text_server::get_preview", "[{}] Cannot read preview status file {status_file_name}", &obj.id);
-        return Ok(Box::new(StatusCode::INTERNAL_SERVER_ERROR));
+        return Ok(Box::new(previews_error("Cannot read the preview status")));
     };
     log::trace!(target: "remote_text_server::get_preview", "[{}] Loaded preview status", &obj.id);
     match status_contents.as_str() {
@@ -575,23 +543,23 @@ pub(crate) async fn get_preview(obj: FileIDAndGitHash, _repos: Arc<Mutex<HashMap
         "FAILURE" => {
             // Previewing the file was unsuccessful; why are they trying?
             log::info!(target: "remote_text_server::get_preview", "[{}] Status of previewing was failure", obj.id);
-            return Ok(Box::new(StatusCode::NOT_FOUND));
+            return Ok(Box::new(ApiError::new(ErrorCode::PreviewFailed, "Compiling this commit failed, so there is no preview").with_details(json!({ "id": obj.id, "hash": obj.hash }))));
         },
         _ => {
             // Status file is corrupt
             log::error!(target: "remote_text_server::get_preview", "[{}] Although status code file exists, it has invalid contents", &obj.id);
-            return Ok(Box::new(StatusCode::INTERNAL_SERVER_ERROR));
+            return Ok(Box::new(previews_error("Preview status is corrupt")));
         }
     };
 
     if let Some((file_name, path, _)) = items.iter().find(|(_, _, extension)| {
-        extension == &"pdf" // First file with a ".pdf" extension
+        extension == "pdf" // First file with a ".pdf" extension
     }) {
         log::trace!(target: "remote_text_server::get_preview", "[{}] Found PDF file {file_name}", obj.id);
         let Ok(data) = fs::read(path) else {
             // Probably a permissions error
             log::error!(target: "remote_text_server::get_preview", "[{}] Cannot read previewed file", obj.id);
-            return Ok(Box::new(StatusCode::INTERNAL_SERVER_ERROR));
+            return Ok(Box::new(previews_error("Cannot read the preview")));
         };
         log::trace!(target: "remote_text_server::get_preview", "[{}] Read {} bytes", obj.id, data.len());
         let mut resp = Response::new(Body::from(data));
@@ -603,13 +571,13 @@ pub(crate) async fn get_preview(obj: FileIDAndGitHash, _repos: Arc<Mutex<HashMap
         return Ok(Box::new(resp));
     }
     if let Some((file_name, path, _)) = items.iter().find(|(_, _, extension)| {
-        extension == &"html" // First file with a ".html" extension
+        extension == "html" // First file with a ".html" extension
     }) {
         log::trace!(target: "remote_text_server::get_preview", "[{}] Found HTML file {file_name}", obj.id);
         let Ok(data) = fs::read(path) else {
             // Probably a permissions error
             log::error!(target: "remote_text_server::get_preview", "[{}] Cannot read previewed file", obj.id);
-            return Ok(Box::new(StatusCode::INTERNAL_SERVER_ERROR));
+            return Ok(Box::new(previews_error("Cannot read the preview")));
         };
         log::trace!(target: "remote_text_server::get_preview", "[{}] Read {} bytes", obj.id, data.len());
         // Since HTML is how webpages are expressed, there's no "application/html" MIME type
@@ -620,10 +588,10 @@ pub(crate) async fn get_preview(obj: FileIDAndGitHash, _repos: Arc<Mutex<HashMap
     // The only output formats we currently use are PDF or HTML, so if neither are found, we're
     //   missing our output file.
     log::error!(target: "remote_text_server::get_preview", "[{}] Neither PDF nor HTML files found", obj.id);
-    Ok(Box::new(StatusCode::INTERNAL_SERVER_ERROR))
+    Ok(Box::new(previews_error("Preview output is missing")))
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
 pub(crate) struct IdOnly {
     pub(crate) id: Uuid
 }
@@ -634,49 +602,35 @@ pub(crate) struct IdOnly {
 TODO: Comment get_history() functionality & general description
 
 */
-pub(crate) async fn get_history(file_id: IdOnly, repos: Arc<Mutex<HashMap<Uuid, Repository>>>) -> Result<Box<dyn warp::Reply>, Infallible> {
-    log::trace!(target: "remote_text_server::get_history", "[{}] Acquiring lock on hash map", &file_id.id);
-    let repos = repos.lock().unwrap();
-    let Some(repo) = repos.get(&file_id.id) else {
-        log::info!(target: "remote_text_server::get_history", "[{}] Request made to get history of nonexistent file", &file_id.id);
-        return Ok(Box::new(StatusCode::NOT_FOUND));
-    };
-    let odb = repo.odb().unwrap();
-    log::trace!(target: "remote_text_server::get_history", "[{}] Opened object database", &file_id.id);
-    let mut commits = vec![];
-    odb.foreach(|oid| {
-        log::trace!(target: "remote_text_server::get_history", "[{}] Object {} located in database", &file_id.id, oid.to_string());
-        let Ok(commit) = repo.find_commit(*oid) else {
-            log::trace!(target: "remote_text_server::get_history", "[{}] Object {} is not commit", &file_id.id, oid.to_string());
-            return true;
-        };
-        let parent = commit.parent_ids().next().map(|cm| cm.to_string());
-        log::trace!(target: "remote_text_server::get_history", "[{}] Parent of commit {} is {:?}", &file_id.id, oid.to_string(), parent);
-        // let parent = commit.parent(1).ok().map(|cm| cm.id().to_string());
-        commits.push(GitCommit { hash: commit.id().to_string(), parent });
-        true
-    }).unwrap();
-    // repo.references().iter().next().unwrap().
-    // for _ref in repo.references().iter() {
-    //     _ref
-    // }
-    log::trace!(target: "remote_text_server::get_history", "[{}] Iterating through branches", &file_id.id);
-    let refs = repo.branches(None).unwrap().map(|b| {
-        log::trace!(target: "remote_text_server::get_history", "[{}] Investigating branch", &file_id.id);
-        let (branch, _) = b.unwrap();
-        let name = branch.name().unwrap().unwrap().to_string();
-        log::trace!(target: "remote_text_server::get_history", "[{}] Branch name: {}", &file_id.id, name);
-        let hash = branch.get().peel_to_commit().unwrap().id().to_string();
-        log::trace!(target: "remote_text_server::get_history", "[{}] Branch ref: {}", &file_id.id, hash);
-        return GitRef {
-            name,
-            hash,
-        }
-    }).collect::<Vec<GitRef>>();
-    log::info!(target: "remote_text_server::get_history", "[{}] History loaded", &file_id.id);
-    let history = GitHistory {
-        commits,
-        refs,
-    };
-    return Ok(Box::new(warp::reply::json(&history)))
+pub(crate) async fn get_history(file_id: IdOnly, store: Store) -> Result<Box<dyn warp::Reply>, Infallible> {
+    let id = file_id.id;
+    let history = logging::spawn_blocking(move || store.get_history(&id)).await;
+    return Ok(match history {
+        Ok(history) => Box::new(warp::reply::json(&history)),
+        Err(code) => Box::new(code)
+    });
+}
+
+/// Reports the server's metrics in the Prometheus text format
+pub(crate) async fn metrics_report(store: Store, config: Arc<Config>) -> Result<impl warp::Reply, Infallible> {
+    // Measuring disk usage walks the files and previews directories
+    let body = logging::spawn_blocking(move || metrics().render(store.file_count(), store.open_count(), &config)).await;
+    return Ok(warp::reply::with_header(body, "content-type", "text/plain; version=0.0.4"));
+}
+
+pub(crate) async fn openapi() -> Result<impl warp::Reply, Infallible> {
+    return Ok(warp::reply::json(&openapi::spec()));
+}
+
+pub(crate) async fn health() -> Result<impl warp::Reply, Infallible> {
+    return Ok(warp::reply::json(&health::health()));
+}
+
+pub(crate) async fn ready(store: Store, config: Arc<Config>) -> Result<impl warp::Reply, Infallible> {
+    let readiness = health::readiness(store, &config).await;
+    if !readiness.ready {
+        log::warn!(target: "remote_text_server::ready", "Not ready: {}", serde_json::to_string(&readiness).unwrap_or_default());
+    }
+    let status = if readiness.ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    return Ok(warp::reply::with_status(warp::reply::json(&readiness), status));
 }
@@ -0,0 +1,170 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use tokio::sync::Mutex;
+use utoipa::ToSchema;
+
+use crate::config::Config;
+use crate::logging;
+use crate::storage::Store;
+
+/// The external programs previews are compiled with
+pub(crate) const TOOLS: &[&str] = &["latexmk", "pdflatex", "pandoc"];
+
+/// How long the readiness check waits for the storage's lock
+const LOCK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long each tool gets to print its version
+const VERSION_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long the tool checks are reused for before the tools are looked up and run again
+const TOOLS_TTL: Duration = Duration::from_secs(60);
+
+/// The last tool checks and when they were made. Held across a refresh, so readiness calls that
+///   arrive during one wait for it rather than starting their own
+static TOOLS_CACHE: Mutex<Option<(Instant, BTreeMap<&'static str, ToolCheck>)>> = Mutex::const_new(None);
+
+#[derive(Serialize, Clone, Debug, ToSchema)]
+pub(crate) struct Health {
+    pub(crate) status: &'static str,
+    pub(crate) version: &'static str,
+}
+
+/// The result of the readiness check. The server is ready when every check is `ok`
+#[derive(Serialize, Clone, Debug, ToSchema)]
+pub(crate) struct Readiness {
+    pub(crate) ready: bool,
+    pub(crate) files_dir: DirCheck,
+    pub(crate) previews_dir: DirCheck,
+    pub(crate) repositories: LockCheck,
+    pub(crate) tools: BTreeMap<&'static str, ToolCheck>,
+}
+
+#[derive(Serialize, Clone, Debug, ToSchema)]
+pub(crate) struct DirCheck {
+    pub(crate) ok: bool,
+    #[schema(value_type = String)]
+    pub(crate) path: PathBuf,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) error: Option<String>,
+}
+
+#[derive(Serialize, Clone, Debug, ToSchema)]
+pub(crate) struct LockCheck {
+    pub(crate) ok: bool,
+    /// The number of files, if the lock was taken
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) count: Option<usize>,
+    /// How long it took to take the lock
+    pub(crate) wait_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) error: Option<String>,
+}
+
+#[derive(Serialize, Clone, Debug, ToSchema)]
+pub(crate) struct ToolCheck {
+    pub(crate) ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>)]
+    pub(crate) path: Option<PathBuf>,
+    /// The first line the tool prints for `--version`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) error: Option<String>,
+}
+
+pub(crate) fn health() -> Health {
+    Health { status: "ok", version: env!("CARGO_PKG_VERSION") }
+}
+
+pub(crate) async fn readiness(store: Store, config: &Config) -> Readiness {
+    let dirs = (config.files_dir.clone(), config.previews_dir.clone());
+    let (files_dir, previews_dir) = logging::spawn_blocking(move || (check_dir(&dirs.0), check_dir(&dirs.1))).await;
+    let repositories = check_lock(&store).await;
+    let tools = check_tools().await;
+
+    let ready = files_dir.ok && previews_dir.ok && repositories.ok && tools.values().all(|tool| tool.ok);
+    Readiness { ready, files_dir, previews_dir, repositories, tools }
+}
+
+// Creates the directory if needed, then writes and removes a scratch file in it
+fn check_dir(path: &Path) -> DirCheck {
+    let scratch = path.join(format!(".ready-{:016x}", rand::random::<u64>()));
+    let result = fs::create_dir_all(path)
+        .and_then(|_| fs::write(&scratch, b"ready"))
+        .and_then(|_| fs::remove_file(&scratch));
+    DirCheck { ok: result.is_ok(), path: path.to_path_buf(), error: result.err().map(|err| err.to_string()) }
+}
+
+// Polls rather than blocking, so a request holding the lock for a long compile can't hang the check
+async fn check_lock(store: &Store) -> LockCheck {
+    let started = Instant::now();
+    loop {
+        let wait_ms = started.elapsed().as_millis() as u64;
+        match store.try_file_count() {
+            Ok(Some(count)) => return LockCheck { ok: true, count: Some(count), wait_ms, error: None },
+            Err(err) => return LockCheck { ok: false, count: None, wait_ms, error: Some(err) },
+            Ok(None) if started.elapsed() >= LOCK_TIMEOUT => {
+                return LockCheck { ok: false, count: None, wait_ms, error: Some(format!("The lock was not released within {}s", LOCK_TIMEOUT.as_secs())) };
+            },
+            Ok(None) => tokio::time::sleep(Duration::from_millis(10)).await
+        }
+    }
+}
+
+/// Runs the tool checks at most once per `TOOLS_TTL`, since each one starts a process. Called at
+///   startup too, so the first readiness check doesn't wait on them
+pub(crate) async fn check_tools() -> BTreeMap<&'static str, ToolCheck> {
+    let mut cache = TOOLS_CACHE.lock().await;
+    if let Some((checked, tools)) = cache.as_ref() {
+        if checked.elapsed() < TOOLS_TTL {
+            return tools.clone();
+        }
+    }
+    let tool_checks = futures::future::join_all(TOOLS.iter().map(|tool| check_tool(tool))).await;
+    let tools = TOOLS.iter().copied().zip(tool_checks).collect::<BTreeMap<_, _>>();
+    *cache = Some((Instant::now(), tools.clone()));
+    tools
+}
+
+async fn check_tool(tool: &str) -> ToolCheck {
+    let Some(path) = find_in_path(tool) else {
+        return ToolCheck { ok: false, path: None, version: None, error: Some(format!("{tool} was not found in PATH")) };
+    };
+    let output = tokio::time::timeout(VERSION_TIMEOUT, tokio::process::Command::new(&path).arg("--version").kill_on_drop(true).output()).await;
+    match output {
+        Ok(Ok(output)) if output.status.success() => {
+            let version = String::from_utf8_lossy(&output.stdout).lines()
+                .map(str::trim)
+                .find(|line| !line.is_empty())
+                .map(str::to_string);
+            ToolCheck { ok: true, path: Some(path), version, error: None }
+        },
+        Ok(Ok(output)) => ToolCheck { ok: false, path: Some(path), version: None, error: Some(format!("`{tool} --version` exited with {}", output.status)) },
+        Ok(Err(err)) => ToolCheck { ok: false, path: Some(path), version: None, error: Some(format!("Unable to run {tool}: {err}")) },
+        Err(_) => ToolCheck { ok: false, path: Some(path), version: None, error: Some(format!("`{tool} --version` did not finish within {}s", VERSION_TIMEOUT.as_secs())) },
+    }
+}
+
+/// The first executable file called `name` in the directories of `PATH`
+pub(crate) fn find_in_path(name: &str) -> Option<PathBuf> {
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path)
+        .map(|dir| dir.join(name))
+        .find(|candidate| is_executable(candidate))
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    fs::metadata(path).map(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0).unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file() || path.with_extension("exe").is_file()
+}
@@ -0,0 +1,161 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::api::{FileList, FileListError, FileSummary};
+
+/// The sidecar in the files directory that listFiles is served from
+pub(crate) const INDEX_FILE: &str = "index.json";
+
+/// Written next to the index and renamed over it, so a crash never leaves half an index behind
+pub(crate) const INDEX_TEMP_FILE: &str = "index.json.tmp";
+
+const INDEX_VERSION: u32 = 2;
+
+#[derive(Serialize, Deserialize)]
+struct IndexFile {
+    version: u32,
+    files: Vec<Entry>,
+}
+
+/// A file's summary, and the commits HEAD and its branches pointed to when it was made. When they
+///   point anywhere else at the next start, the file changed without the index hearing about it
+///   (e.g. the server stopped part way through a save), and it's summarized again
+#[derive(Serialize, Deserialize, Clone)]
+struct Entry {
+    #[serde(flatten)]
+    summary: FileSummary,
+    tips: Vec<String>,
+}
+
+/// Every file's summary, kept up to date on create, save and delete so listing files doesn't need
+///   to read any repository
+///
+/// Files that couldn't be summarized are kept in memory only, so they're retried at the next start.
+pub(crate) struct Index {
+    path: PathBuf,
+    files: BTreeMap<Uuid, Entry>,
+    errors: BTreeMap<Uuid, String>,
+}
+
+impl Index {
+    /// An empty index, saved to `files_dir` on the first change
+    pub(crate) fn new(files_dir: &Path) -> Index {
+        return Index { path: files_dir.join(INDEX_FILE), files: BTreeMap::new(), errors: BTreeMap::new() };
+    }
+
+    /// Reads the index saved in `files_dir`, if there is one and it can be read
+    pub(crate) fn load(files_dir: &Path) -> Option<Index> {
+        let path = files_dir.join(INDEX_FILE);
+        let contents = fs::read(&path).ok()?;
+        let file = match serde_json::from_slice::<IndexFile>(&contents) {
+            Ok(file) if file.version == INDEX_VERSION => file,
+            Ok(file) => {
+                log::warn!(target: "remote_text_server::index", "Ignoring index with unknown version {}", file.version);
+                return None;
+            },
+            Err(err) => {
+                log::warn!(target: "remote_text_server::index", "Ignoring unreadable index: {}", err);
+                return None;
+            }
+        };
+        let files = file.files.into_iter().map(|entry| (entry.summary.id, entry)).collect();
+        return Some(Index { path, files, errors: BTreeMap::new() });
+    }
+
+    pub(crate) fn list(&self) -> FileList {
+        return FileList {
+            files: self.files.values().map(|entry| entry.summary.clone()).collect(),
+            errors: self.errors.iter().map(|(id, reason)| FileListError { id: *id, reason: reason.clone() }).collect(),
+            total: self.files.len(),
+            next: None,
+        };
+    }
+
+    /// Records a file's summary, made with its branches at `tips`, or why it couldn't be summarized
+    pub(crate) fn update(&mut self, uuid: Uuid, summary: Result<FileSummary, &str>, tips: Vec<String>) {
+        self.set(uuid, summary, tips);
+        self.save();
+    }
+
+    /// Brings the index in line with the files in `ids`: files that are gone are dropped, and files
+    ///   that aren't indexed yet (all of them, when there was no index) or whose branches have moved
+    ///   since they were indexed are summarized
+    ///
+    /// `tips` gives the commits a file's HEAD and branches point to now, as `files::tips` does.
+    ///
+    /// Returns the number of files that were summarized
+    pub(crate) fn reconcile<'a>(&mut self, ids: impl IntoIterator<Item = &'a Uuid>, mut tips: impl FnMut(&Uuid) -> Vec<String>, mut summarize: impl FnMut(&Uuid) -> Result<FileSummary, &'static str>) -> usize {
+        let ids = ids.into_iter().collect::<BTreeSet<_>>();
+        let before = self.files.len();
+        self.files.retain(|id, _| ids.contains(id));
+        let mut changed = self.files.len() != before;
+        let mut summarized = 0;
+        for uuid in ids {
+            let now = tips(uuid);
+            if self.files.get(uuid).is_some_and(|entry| entry.tips == now) {
+                continue;
+            }
+            if self.files.contains_key(uuid) {
+                log::warn!(target: "remote_text_server::index", "[{}] Changed since it was indexed", uuid);
+            }
+            self.set(*uuid, summarize(uuid), now);
+            summarized += 1;
+            changed = true;
+        }
+        if changed {
+            self.save();
+        }
+        return summarized;
+    }
+
+    fn set(&mut self, uuid: Uuid, summary: Result<FileSummary, &str>, tips: Vec<String>) {
+        match summary {
+            Ok(summary) => {
+                self.errors.remove(&uuid);
+                self.files.insert(uuid, Entry { summary, tips });
+            },
+            Err(reason) => {
+                self.files.remove(&uuid);
+                self.errors.insert(uuid, reason.to_string());
+            }
+        }
+    }
+
+    pub(crate) fn remove(&mut self, uuid: &Uuid) {
+        self.errors.remove(uuid);
+        if self.files.remove(uuid).is_some() {
+            self.save();
+        }
+    }
+
+    // A failure is only logged, since the index in memory is still right. The index on disk is
+    //   removed instead of being left stale, so it's rebuilt at the next start
+    fn save(&self) {
+        let file = IndexFile { version: INDEX_VERSION, files: self.files.values().cloned().collect() };
+        let temp = self.path.with_file_name(INDEX_TEMP_FILE);
+        let saved = serde_json::to_vec(&file)
+            .map_err(|err| err.to_string())
+            .and_then(|contents| write_synced(&temp, &contents).map_err(|err| err.to_string()))
+            .and_then(|_| fs::rename(&temp, &self.path).map_err(|err| err.to_string()));
+        match saved {
+            Ok(_) => log::trace!(target: "remote_text_server::index", "Saved index of {} file(s)", self.files.len()),
+            Err(err) => {
+                log::error!(target: "remote_text_server::index", "Unable to save index to {:?}: {}", self.path, err);
+                let _ = fs::remove_file(&self.path);
+            }
+        }
+    }
+}
+
+/// Writes `contents` to `path` and waits for it to reach the disk, so renaming it over the index
+///   afterwards can't leave a truncated index behind if the machine goes down
+fn write_synced(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut file = File::create(path)?;
+    file.write_all(contents)?;
+    return file.sync_all();
+}
@@ -0,0 +1,119 @@
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+
+use listenfd::ListenFd;
+use tokio::net::TcpListener;
+#[cfg(unix)]
+use tokio::net::UnixListener;
+
+use crate::config::Config;
+
+/// The socket the server accepts connections on
+pub(crate) enum Listener {
+    Tcp(TcpListener),
+    /// The socket file is `None` when the socket was passed in by systemd, which owns the file
+    #[cfg(unix)]
+    Unix(UnixListener, Option<SocketFile>),
+}
+
+/// Removes the socket file we created once the listener is done with it
+#[cfg(unix)]
+pub(crate) struct SocketFile(PathBuf);
+
+#[cfg(unix)]
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        if let Err(err) = std::fs::remove_file(&self.0) {
+            log::warn!(target: "remote_text_server::listener", "Unable to remove socket {:?}: {err}", self.0);
+        }
+    }
+}
+
+impl Listener {
+    /// Takes the listener passed in through systemd socket activation (`LISTEN_FDS`) if there is one.
+    ///   Otherwise binds `config.unix_socket` if it is set, or `config.address` and `config.port`
+    pub(crate) async fn bind(config: &Config) -> Result<Listener, String> {
+        if let Some(listener) = from_systemd()? {
+            return Ok(listener);
+        }
+        #[cfg(unix)]
+        if let Some(path) = &config.unix_socket {
+            return bind_unix(path);
+        }
+        #[cfg(not(unix))]
+        if config.unix_socket.is_some() {
+            return Err("Unix domain sockets are only supported on Unix".to_string());
+        }
+        let addr = config.socket_addr();
+        let listener = TcpListener::bind(addr).await
+            .map_err(|err| format!("Unable to listen on {addr}: {err}"))?;
+        Ok(Listener::Tcp(listener))
+    }
+
+    /// Where the listener is listening, for log messages, e.g. `http://0.0.0.0:3030` or `http+unix:/run/remote-text.sock`
+    pub(crate) fn url(&self, scheme: &str) -> String {
+        match self {
+            Listener::Tcp(listener) => match listener.local_addr() {
+                Ok(addr) => format!("{scheme}://{addr}"),
+                Err(_) => format!("{scheme} (TCP)"),
+            },
+            #[cfg(unix)]
+            Listener::Unix(listener, _) => match listener.local_addr().ok().as_ref().and_then(|addr| addr.as_pathname()) {
+                Some(path) => format!("{scheme}+unix:{}", path.display()),
+                None => format!("{scheme}+unix (unnamed socket)"),
+            },
+        }
+    }
+}
+
+/// Takes the first socket systemd passed us, if any
+///
+/// Only stream sockets are supported. With several `ListenStream=` entries, all but the first are ignored.
+fn from_systemd() -> Result<Option<Listener>, String> {
+    let mut fds = ListenFd::from_env();
+    if fds.len() == 0 {
+        return Ok(None);
+    }
+    if fds.len() > 1 {
+        log::warn!(target: "remote_text_server::listener", "systemd passed {} sockets; only the first one is used", fds.len());
+    }
+    if let Ok(Some(listener)) = fds.take_tcp_listener(0) {
+        listener.set_nonblocking(true)
+            .map_err(|err| format!("Unable to use the socket passed by systemd: {err}"))?;
+        let listener = TcpListener::from_std(listener)
+            .map_err(|err| format!("Unable to use the socket passed by systemd: {err}"))?;
+        log::info!(target: "remote_text_server::listener", "Using the TCP socket passed by systemd");
+        return Ok(Some(Listener::Tcp(listener)));
+    }
+    #[cfg(unix)]
+    if let Ok(Some(listener)) = fds.take_unix_listener(0) {
+        listener.set_nonblocking(true)
+            .map_err(|err| format!("Unable to use the socket passed by systemd: {err}"))?;
+        let listener = UnixListener::from_std(listener)
+            .map_err(|err| format!("Unable to use the socket passed by systemd: {err}"))?;
+        log::info!(target: "remote_text_server::listener", "Using the Unix domain socket passed by systemd");
+        return Ok(Some(Listener::Unix(listener, None)));
+    }
+    Err("The socket passed by systemd is not a TCP or Unix stream socket".to_string())
+}
+
+/// Binds a Unix domain socket at `path`, replacing a stale socket file left behind by a previous run
+#[cfg(unix)]
+fn bind_unix(path: &Path) -> Result<Listener, String> {
+    use std::os::unix::fs::FileTypeExt;
+
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            return Err(format!("Unable to listen on {:?}: the file exists and is not a socket", path));
+        }
+        if std::os::unix::net::UnixStream::connect(path).is_ok() {
+            return Err(format!("Unable to listen on {:?}: another server is already listening there", path));
+        }
+        log::info!(target: "remote_text_server::listener", "Removing stale socket {:?}", path);
+        std::fs::remove_file(path)
+            .map_err(|err| format!("Unable to remove stale socket {:?}: {err}", path))?;
+    }
+    let listener = UnixListener::bind(path)
+        .map_err(|err| format!("Unable to listen on {:?}: {err}", path))?;
+    Ok(Listener::Unix(listener, Some(SocketFile(path.to_path_buf()))))
+}
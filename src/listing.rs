@@ -0,0 +1,154 @@
+use std::cmp::Ordering;
+use std::path::Path;
+
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::api::{FileList, FileSummary};
+use crate::error::{ApiError, ErrorCode};
+
+/// The legacy listFiles sends a bare array of the page's files, as the original clients expect, so
+///   the count and the next page's cursor are sent in these headers instead
+pub(crate) const TOTAL_HEADER: &str = "x-total-count";
+pub(crate) const NEXT_HEADER: &str = "x-next-cursor";
+
+/// What listFiles sorts by
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum SortBy {
+    /// The file's name, ignoring case
+    #[default]
+    Name,
+    EditedTime,
+    CreatedTime,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Direction {
+    #[default]
+    Asc,
+    Desc,
+}
+
+/// listFiles' query string. Every parameter is optional: without any, every file is listed by name
+#[derive(Deserialize, Clone, Debug, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct ListQuery {
+    /// `name` (the default), `edited_time` or `created_time`
+    #[param(inline)]
+    pub(crate) sort: Option<SortBy>,
+    /// `asc` (the default) or `desc`
+    #[param(inline)]
+    pub(crate) direction: Option<Direction>,
+    /// Only files whose name contains this, ignoring case
+    pub(crate) name_contains: Option<String>,
+    /// Only files whose name starts with this, ignoring case
+    pub(crate) name_prefix: Option<String>,
+    /// Only files with this extension, with or without the `.`, ignoring case
+    pub(crate) extension: Option<String>,
+    /// Only files last edited after this time (RFC 3339)
+    pub(crate) edited_after: Option<DateTime<Utc>>,
+    /// Only files last edited before this time (RFC 3339)
+    pub(crate) edited_before: Option<DateTime<Utc>>,
+    /// At most this many files per page. Without it, every file is on one page
+    pub(crate) limit: Option<usize>,
+    /// The previous page's `next`, to get the page after it
+    pub(crate) cursor: Option<String>,
+}
+
+/// Where a page ended. The next page starts after this file in the same order, rather than at an
+///   offset, so files created or deleted in between don't shift pages
+#[derive(Serialize, Deserialize)]
+struct Cursor {
+    sort: SortBy,
+    direction: Direction,
+    after: FileSummary,
+}
+
+fn invalid(message: &str, details: serde_json::Value) -> ApiError {
+    return ApiError::new(ErrorCode::InvalidQuery, message).with_details(details);
+}
+
+impl ListQuery {
+    fn matches(&self, file: &FileSummary) -> bool {
+        let name = file.name.to_lowercase();
+        if self.name_contains.as_ref().is_some_and(|part| !name.contains(&part.to_lowercase())) {
+            return false;
+        }
+        if self.name_prefix.as_ref().is_some_and(|prefix| !name.starts_with(&prefix.to_lowercase())) {
+            return false;
+        }
+        if let Some(extension) = &self.extension {
+            let wanted = extension.trim_start_matches('.').to_lowercase();
+            if Path::new(&name).extension().and_then(|found| found.to_str()) != Some(wanted.as_str()) {
+                return false;
+            }
+        }
+        if self.edited_after.is_some_and(|after| file.edited_time <= after) {
+            return false;
+        }
+        if self.edited_before.is_some_and(|before| file.edited_time >= before) {
+            return false;
+        }
+        return true;
+    }
+
+    fn cursor(&self, sort: SortBy, direction: Direction) -> Result<Option<FileSummary>, ApiError> {
+        let Some(token) = &self.cursor else {
+            return Ok(None);
+        };
+        let cursor = URL_SAFE_NO_PAD.decode(token).ok()
+            .and_then(|json| serde_json::from_slice::<Cursor>(&json).ok())
+            .ok_or_else(|| invalid("Not a cursor from listFiles", json!({ "cursor": token })))?;
+        if (cursor.sort, cursor.direction) != (sort, direction) {
+            return Err(invalid("Cursor is for a different sort", json!({ "cursor": token })));
+        }
+        return Ok(Some(cursor.after));
+    }
+}
+
+fn compare(sort: SortBy, a: &FileSummary, b: &FileSummary) -> Ordering {
+    let order = match sort {
+        SortBy::Name => a.name.to_lowercase().cmp(&b.name.to_lowercase()).then_with(|| a.name.cmp(&b.name)),
+        SortBy::EditedTime => a.edited_time.cmp(&b.edited_time),
+        SortBy::CreatedTime => a.created_time.cmp(&b.created_time),
+    };
+    // IDs break ties, so files are always in the same order and pages never overlap
+    return order.then_with(|| a.id.cmp(&b.id));
+}
+
+/// The page of `list` the query asks for. `total` counts every file that matches the filters, on
+///   any page, and `next` is set when there are more
+///
+/// Files that can't be read aren't sorted, filtered or paged, and are on every page.
+pub(crate) fn page(list: FileList, query: &ListQuery) -> Result<FileList, ApiError> {
+    if query.limit == Some(0) {
+        return Err(invalid("limit must be at least 1", json!({ "limit": 0 })));
+    }
+    let sort = query.sort.unwrap_or_default();
+    let direction = query.direction.unwrap_or_default();
+    let after = query.cursor(sort, direction)?;
+    let order = |a: &FileSummary, b: &FileSummary| match direction {
+        Direction::Asc => compare(sort, a, b),
+        Direction::Desc => compare(sort, b, a),
+    };
+
+    let mut files = list.files.into_iter().filter(|file| query.matches(file)).collect::<Vec<_>>();
+    let total = files.len();
+    files.sort_by(order);
+    if let Some(after) = &after {
+        files.retain(|file| order(file, after) == Ordering::Greater);
+    }
+    let mut next = None;
+    if let Some(limit) = query.limit.filter(|limit| files.len() > *limit) {
+        files.truncate(limit);
+        let cursor = Cursor { sort, direction, after: files[limit - 1].clone() };
+        next = Some(URL_SAFE_NO_PAD.encode(serde_json::to_vec(&cursor).unwrap_or_default()));
+    }
+    return Ok(FileList { files, errors: list.errors, total, next });
+}
@@ -0,0 +1,192 @@
+use std::backtrace::Backtrace;
+use std::future::Future;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use chrono::{SecondsFormat, Utc};
+use log::kv::{self, Key, Value, VisitSource};
+use log::{Log, Metadata, Record};
+use serde_json::{Map, Value as JsonValue};
+use uuid::Uuid;
+use warp::http::{HeaderMap, Method, StatusCode, Version};
+
+use crate::config::{Config, LogFormat};
+
+/// The header a request ID is read from (when a proxy in front of us set one) and returned in
+pub(crate) const REQUEST_ID_HEADER: &str = "x-request-id";
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// Runs `future` with `id` as the current request ID, so every record it logs is tagged with it
+pub(crate) async fn with_request_id<F: Future>(id: String, future: F) -> F::Output {
+    REQUEST_ID.scope(id, future).await
+}
+
+/// The ID of the request being handled by the current task, if any
+pub(crate) fn request_id() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+/// Runs blocking work (git, filesystem walks) on tokio's blocking pool, keeping the current
+///   request ID so that its logs are still tagged with it
+///
+/// A panic in `f` is resumed in the calling task.
+pub(crate) async fn spawn_blocking<F, R>(f: F) -> R where F: FnOnce() -> R + Send + 'static, R: Send + 'static {
+    let id = request_id();
+    let result = tokio::task::spawn_blocking(move || match id {
+        Some(id) => REQUEST_ID.sync_scope(id, f),
+        None => f(),
+    }).await;
+    match result {
+        Ok(result) => result,
+        Err(err) => std::panic::resume_unwind(err.into_panic()),
+    }
+}
+
+/// Reuses the request ID set by a proxy, if it is short and tame enough to log, or makes a new one
+pub(crate) fn request_id_for(headers: &HeaderMap) -> String {
+    headers.get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| !id.is_empty() && id.len() <= 64)
+        .filter(|id| id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.'))
+        .map(|id| id.to_string())
+        .unwrap_or_else(|| format!("{:016x}", rand::random::<u64>()))
+}
+
+/// One line of the access log
+pub(crate) struct Access<'a> {
+    pub(crate) remote_addr: Option<SocketAddr>,
+    pub(crate) method: &'a Method,
+    pub(crate) path: &'a str,
+    pub(crate) version: Version,
+    pub(crate) status: StatusCode,
+    pub(crate) referer: Option<&'a str>,
+    pub(crate) user_agent: Option<&'a str>,
+    pub(crate) elapsed: Duration,
+}
+
+/// Logs a finished request, in the same format as `warp::log` with every part also as a field
+pub(crate) fn log_access(access: &Access) {
+    let remote_addr = access.remote_addr.map(|addr| addr.to_string()).unwrap_or_else(|| "-".to_string());
+    log::info!(
+        target: "remote_text_server::api",
+        remote_addr = remote_addr.as_str(),
+        method = access.method.as_str(),
+        path = access.path,
+        status = access.status.as_u16(),
+        referer = access.referer.unwrap_or("-"),
+        user_agent = access.user_agent.unwrap_or("-"),
+        duration_ms = access.elapsed.as_secs_f64() * 1000.0;
+        "{} \"{} {} {:?}\" {} \"{}\" \"{}\" {:?}",
+        remote_addr, access.method, access.path, access.version, access.status.as_u16(),
+        access.referer.unwrap_or("-"), access.user_agent.unwrap_or("-"), access.elapsed
+    );
+}
+
+/// Sets up the global logger in the configured format
+///
+/// `RUST_LOG`, when set, takes priority over `config.log_level`.
+pub(crate) fn init(config: &Config) {
+    // Set `RUST_LOG=remote_text_server=debug` (or `--log-level debug`) to see debug logs,
+    // by default this only shows warnings and errors.
+    let filters = std::env::var("RUST_LOG").unwrap_or_else(|_| format!("remote_text_server={}", config.log_level));
+
+    // Start from pretty_env_logger's builder so we keep its colorful and organized output
+    let mut builder = pretty_env_logger::formatted_builder();
+    builder.parse_filters(&filters);
+    if config.log_format == LogFormat::Json {
+        builder.format(|f, record| writeln!(f, "{}", json_record(record)));
+    }
+    let logger = builder.build();
+    let max_level = logger.filter();
+    let logger: Box<dyn Log> = match config.log_format {
+        LogFormat::Pretty => Box::new(RequestIdPrefix(Box::new(logger))),
+        LogFormat::Json => Box::new(logger),
+    };
+    if log::set_boxed_logger(logger).is_ok() {
+        log::set_max_level(max_level);
+    }
+}
+
+/// Logs panics as errors, with a backtrace and the request ID, instead of printing them to standard
+///   error. Call after `init`
+pub(crate) fn log_panics() {
+    std::panic::set_hook(Box::new(|info| {
+        let backtrace = Backtrace::force_capture();
+        log::error!(target: "remote_text_server::panic", "{}\nstack backtrace:\n{}", info, backtrace);
+    }));
+}
+
+/// Prefixes every message logged while handling a request with `req=<request ID>`
+struct RequestIdPrefix(Box<dyn Log>);
+
+impl Log for RequestIdPrefix {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.0.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        match request_id() {
+            Some(id) => self.0.log(&record.to_builder().args(format_args!("req={} {}", id, record.args())).build()),
+            None => self.0.log(record)
+        }
+    }
+
+    fn flush(&self) {
+        self.0.flush()
+    }
+}
+
+/// One JSON object per record
+///
+/// Messages starting with the `[<file ID>]` prefix used throughout `handlers` and `files` get it
+///   moved into a `file_id` field. Key-value pairs on the record become fields too.
+pub(crate) fn json_record(record: &Record) -> JsonValue {
+    let mut fields = Map::new();
+    fields.insert("timestamp".to_string(), JsonValue::from(Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)));
+    fields.insert("level".to_string(), JsonValue::from(record.level().as_str()));
+    fields.insert("target".to_string(), JsonValue::from(record.target()));
+    if let Some(id) = request_id() {
+        fields.insert("request_id".to_string(), JsonValue::from(id));
+    }
+
+    let message = record.args().to_string();
+    let file_id = message.strip_prefix('[')
+        .and_then(|rest| rest.get(..36).zip(rest.get(36..)))
+        .and_then(|(id, rest)| Some((Uuid::parse_str(id).ok()?, rest.strip_prefix("] ")?)));
+    match file_id {
+        Some((id, rest)) => {
+            fields.insert("file_id".to_string(), JsonValue::from(id.to_string()));
+            fields.insert("message".to_string(), JsonValue::from(rest));
+        },
+        None => {
+            fields.insert("message".to_string(), JsonValue::from(message));
+        }
+    }
+
+    let _ = record.key_values().visit(&mut JsonFields(&mut fields));
+    JsonValue::Object(fields)
+}
+
+struct JsonFields<'a>(&'a mut Map<String, JsonValue>);
+
+impl<'kvs> VisitSource<'kvs> for JsonFields<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        let value = if let Some(number) = value.to_u64() {
+            JsonValue::from(number)
+        } else if let Some(number) = value.to_i64() {
+            JsonValue::from(number)
+        } else if let Some(number) = value.to_f64() {
+            JsonValue::from(number)
+        } else if let Some(boolean) = value.to_bool() {
+            JsonValue::from(boolean)
+        } else {
+            JsonValue::from(value.to_string())
+        };
+        self.0.insert(key.as_str().to_string(), value);
+        Ok(())
+    }
+}
@@ -1,10 +1,21 @@
-#[macro_use] extern crate log;
+// Explicit `return`s and upper-case enum variants (which are part of the JSON API) are house style
+#![allow(clippy::needless_return, clippy::upper_case_acronyms)]
+
 extern crate pretty_env_logger;
 
-use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 
+use clap::Parser;
 use warp::Filter;
 
+use crate::cli::{Cli, Command};
+use crate::config::{Config, ConfigArgs, StorageKind};
+use crate::files::Repos;
+use crate::memory::MemoryStorage;
+use crate::shutdown::Shutdown;
+use crate::storage::{GitStorage, Store};
+
 /* // EXTERNAL CRATE USAGE //
 
 pretty_env_logger:
@@ -18,6 +29,30 @@ serde:
 uuid:
     This crate provides the generation and parsing of Unique Universal Identifiers
 
+clap:
+    Parses command-line flags (and their environment variable equivalents) into rust structs
+
+toml:
+    Parses the optional configuration file
+
+tokio-rustls, rustls-pemfile:
+    Terminate TLS connections when a certificate and key are configured
+
+listenfd:
+    Picks up sockets passed in by systemd socket activation
+
+prometheus:
+    Collects the metrics served at /metrics, in the Prometheus text format
+
+utoipa:
+    Generates the OpenAPI document served at /api/openapi.json from the API's types and routes
+
+serde_urlencoded:
+    Parses query strings, such as listFiles' sorting, filtering and paging parameters
+
+percent-encoding:
+    Decodes branch names in v2 API paths, where a `/` in the name is sent as %2F
+
 warp:
     Based off of the popular networking crate hyper, warp provides a simple and fast
     web server framework
@@ -29,122 +64,145 @@ mod routes;
 mod handlers;
 mod api;
 mod files;
+mod config;
+mod cli;
+mod admin;
+mod error;
+mod fsck;
+mod migrate;
+mod health;
+mod index;
+mod listing;
+mod storage;
+mod memory;
+mod server;
+mod logging;
+mod metrics;
+mod openapi;
+mod listener;
+mod shutdown;
+mod tls;
 mod tests;
 
-#[allow(non_snake_case)]
-fn FILES_DIR() -> PathBuf {
-    Path::new(".").join("files")
-}
-#[allow(non_snake_case)]
-fn PREVIEWS_DIR() -> PathBuf {
-    Path::new(".").join("previews")
-}
-
-#[tokio::main]
-async fn main() {
-    if std::env::var_os("RUST_LOG").is_none() {
-        // Set `RUST_LOG=todos=debug` to see debug logs,
-        // this only shows access logs.
-        std::env::set_var("RUST_LOG", "remote_text_server=warn");
+/// How long to wait, once the server has stopped, for blocking work (usually a compile) that
+///   overran the shutdown deadline before leaving it behind
+const RUNTIME_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(1);
+
+fn main() {
+    let cli = Cli::parse();
+    match cli.command.unwrap_or(Command::Serve(cli.serve)) {
+        Command::Serve(args) => {
+            let config = load_config(&args.config);
+            logging::init(&config);
+            logging::log_panics();
+            let runtime = match tokio::runtime::Runtime::new() {
+                Ok(runtime) => runtime,
+                Err(err) => {
+                    log::error!(target: "remote_text_server::main", "Unable to start the runtime: {err}");
+                    std::process::exit(1);
+                }
+            };
+            runtime.block_on(serve(Arc::new(config)));
+            // Dropping the runtime would wait on requests that overran the deadline for as long as they take
+            runtime.shutdown_timeout(RUNTIME_SHUTDOWN_TIMEOUT);
+            log::info!(target: "remote_text_server::main", "Shut down");
+        },
+        Command::Version(args) => {
+            if args.verbose {
+                println!("{}", serde_json::to_string_pretty(&cli::verbose_version()).unwrap());
+            } else {
+                println!("{}", cli::short_version());
+            }
+        },
+        Command::Admin(args) => {
+            let config = load_config(&args.config);
+            logging::init(&config);
+            if let Err(msg) = admin::run(args.command, &config) {
+                eprintln!("{msg}");
+                std::process::exit(1);
+            }
+        }
     }
+}
 
-    // Initialize pretty_env_logger so we can get organized/colorful logs
-    pretty_env_logger::init();
-
-    #[allow(non_snake_case)]
-    if std::env::args().collect::<Vec<String>>().get(1) == Some(&"-vv".to_string()) {
-        // log::trace!(target: "remote_text_server::main", "ENV_VARS");
-        let VERGEN_BUILD_DATE = env!("VERGEN_BUILD_DATE");
-        let VERGEN_BUILD_TIMESTAMP = env!("VERGEN_BUILD_TIMESTAMP");
-        let VERGEN_CARGO_DEBUG = env!("VERGEN_CARGO_DEBUG");
-        let VERGEN_CARGO_FEATURES = env!("VERGEN_CARGO_FEATURES");
-        let VERGEN_CARGO_OPT_LEVEL = env!("VERGEN_CARGO_OPT_LEVEL");
-        let VERGEN_CARGO_TARGET_TRIPLE = env!("VERGEN_CARGO_TARGET_TRIPLE");
-        let VERGEN_GIT_BRANCH = env!("VERGEN_GIT_BRANCH");
-        let VERGEN_GIT_COMMIT_AUTHOR_EMAIL = env!("VERGEN_GIT_COMMIT_AUTHOR_EMAIL");
-        let VERGEN_GIT_COMMIT_AUTHOR_NAME = env!("VERGEN_GIT_COMMIT_AUTHOR_NAME");
-        let VERGEN_GIT_COMMIT_COUNT = env!("VERGEN_GIT_COMMIT_COUNT");
-        let VERGEN_GIT_COMMIT_DATE = env!("VERGEN_GIT_COMMIT_DATE");
-        let VERGEN_GIT_COMMIT_MESSAGE = env!("VERGEN_GIT_COMMIT_MESSAGE");
-        let VERGEN_GIT_COMMIT_TIMESTAMP = env!("VERGEN_GIT_COMMIT_TIMESTAMP");
-        let VERGEN_GIT_DESCRIBE = env!("VERGEN_GIT_DESCRIBE");
-        let VERGEN_GIT_SHA = env!("VERGEN_GIT_SHA");
-        let VERGEN_RUSTC_CHANNEL = env!("VERGEN_RUSTC_CHANNEL");
-        let VERGEN_RUSTC_COMMIT_DATE = env!("VERGEN_RUSTC_COMMIT_DATE");
-        let VERGEN_RUSTC_COMMIT_HASH = env!("VERGEN_RUSTC_COMMIT_HASH");
-        let VERGEN_RUSTC_HOST_TRIPLE = env!("VERGEN_RUSTC_HOST_TRIPLE");
-        let VERGEN_RUSTC_LLVM_VERSION = env!("VERGEN_RUSTC_LLVM_VERSION");
-        let VERGEN_RUSTC_SEMVER = env!("VERGEN_RUSTC_SEMVER");
-        let VERGEN_SYSINFO_CPU_BRAND = env!("VERGEN_SYSINFO_CPU_BRAND");
-        let VERGEN_SYSINFO_CPU_CORE_COUNT = env!("VERGEN_SYSINFO_CPU_CORE_COUNT");
-        let VERGEN_SYSINFO_CPU_FREQUENCY = env!("VERGEN_SYSINFO_CPU_FREQUENCY");
-        let VERGEN_SYSINFO_CPU_NAME = env!("VERGEN_SYSINFO_CPU_NAME");
-        let VERGEN_SYSINFO_CPU_VENDOR = env!("VERGEN_SYSINFO_CPU_VENDOR");
-        let VERGEN_SYSINFO_NAME = env!("VERGEN_SYSINFO_NAME");
-        let VERGEN_SYSINFO_OS_VERSION = env!("VERGEN_SYSINFO_OS_VERSION");
-        let VERGEN_SYSINFO_TOTAL_MEMORY = env!("VERGEN_SYSINFO_TOTAL_MEMORY");
-        let VERGEN_SYSINFO_USER = env!("VERGEN_SYSINFO_USER");
-
-        println!("\
-VERGEN_BUILD_DATE: {VERGEN_BUILD_DATE}
-VERGEN_BUILD_TIMESTAMP: {VERGEN_BUILD_TIMESTAMP}
-VERGEN_CARGO_DEBUG: {VERGEN_CARGO_DEBUG}
-VERGEN_CARGO_FEATURES: {VERGEN_CARGO_FEATURES}
-VERGEN_CARGO_OPT_LEVEL: {VERGEN_CARGO_OPT_LEVEL}
-VERGEN_CARGO_TARGET_TRIPLE: {VERGEN_CARGO_TARGET_TRIPLE}
-VERGEN_GIT_BRANCH: {VERGEN_GIT_BRANCH}
-VERGEN_GIT_COMMIT_AUTHOR_EMAIL: {VERGEN_GIT_COMMIT_AUTHOR_EMAIL}
-VERGEN_GIT_COMMIT_AUTHOR_NAME: {VERGEN_GIT_COMMIT_AUTHOR_NAME}
-VERGEN_GIT_COMMIT_COUNT: {VERGEN_GIT_COMMIT_COUNT}
-VERGEN_GIT_COMMIT_DATE: {VERGEN_GIT_COMMIT_DATE}
-VERGEN_GIT_COMMIT_MESSAGE: {VERGEN_GIT_COMMIT_MESSAGE}
-VERGEN_GIT_COMMIT_TIMESTAMP: {VERGEN_GIT_COMMIT_TIMESTAMP}
-VERGEN_GIT_DESCRIBE: {VERGEN_GIT_DESCRIBE}
-VERGEN_GIT_SHA: {VERGEN_GIT_SHA}
-VERGEN_RUSTC_CHANNEL: {VERGEN_RUSTC_CHANNEL}
-VERGEN_RUSTC_COMMIT_DATE: {VERGEN_RUSTC_COMMIT_DATE}
-VERGEN_RUSTC_COMMIT_HASH: {VERGEN_RUSTC_COMMIT_HASH}
-VERGEN_RUSTC_HOST_TRIPLE: {VERGEN_RUSTC_HOST_TRIPLE}
-VERGEN_RUSTC_LLVM_VERSION: {VERGEN_RUSTC_LLVM_VERSION}
-VERGEN_RUSTC_SEMVER: {VERGEN_RUSTC_SEMVER}
-VERGEN_SYSINFO_CPU_BRAND: {VERGEN_SYSINFO_CPU_BRAND}
-VERGEN_SYSINFO_CPU_CORE_COUNT: {VERGEN_SYSINFO_CPU_CORE_COUNT}
-VERGEN_SYSINFO_CPU_FREQUENCY: {VERGEN_SYSINFO_CPU_FREQUENCY}
-VERGEN_SYSINFO_CPU_NAME: {VERGEN_SYSINFO_CPU_NAME}
-VERGEN_SYSINFO_CPU_VENDOR: {VERGEN_SYSINFO_CPU_VENDOR}
-VERGEN_SYSINFO_NAME: {VERGEN_SYSINFO_NAME}
-VERGEN_SYSINFO_OS_VERSION: {VERGEN_SYSINFO_OS_VERSION}
-VERGEN_SYSINFO_TOTAL_MEMORY: {VERGEN_SYSINFO_TOTAL_MEMORY}
-VERGEN_SYSINFO_USER: {VERGEN_SYSINFO_USER}
-    ");
-
-        std::process::exit(0);
+fn load_config(args: &ConfigArgs) -> Config {
+    match Config::load(args) {
+        Ok(config) => config,
+        Err(msg) => {
+            eprintln!("{msg}");
+            std::process::exit(1);
+        }
     }
+}
 
-    log::info!(target: "remote_text_server::main", "Searching for repositories");
-    let repositories = files::repos();
+async fn serve(config: Arc<Config>) {
+    let store: Store = match config.storage {
+        StorageKind::Git | StorageKind::Bare => Arc::new(GitStorage::new(git_repositories(&config), config.clone())),
+        StorageKind::Memory => {
+            log::warn!(target: "remote_text_server::main", "Keeping files in memory; they will be lost when the server stops");
+            Arc::new(MemoryStorage::new())
+        }
+    };
+    files::remove_partial_previews(&config);
 
     log::trace!(target: "remote_text_server::main", "Setting up filters");
     // Set up the warp wrapper with CORS (Cross-Origin Resource Sharing), allowing any origin point
     let cors = warp::cors()
         .allow_any_origin()
         .allow_header("content-type")
-        .allow_methods(vec!["GET", "POST", "PUT", "PATCH", "DELETE"]);
-    // Sets up logging for api requests
-    let log = warp::log("remote_text_server::api");
+        .allow_methods(vec!["GET", "POST", "PUT", "PATCH", "DELETE"])
+        .expose_header(logging::REQUEST_ID_HEADER);
+    // Requests are logged by `server`, which knows the client address and the request ID
     // Sets up the root path for the api
     let api_root = warp::path("api");
 
     log::trace!(target: "remote_text_server::main", "Setting up routes");
     // Creates a chain of filters that checks/runs each function in the API
-    let routes = api_root.and(routes::get_routes(repositories.clone()))
+    let routes = api_root.and(routes::get_routes(store.clone(), config.clone()))
+        .or(routes::metrics(store.clone(), config.clone()))
+        // Anything no route answered (bad JSON, unknown paths...) gets the same JSON errors as the handlers
+        .recover(error::handle_rejection)
         // .map(|reply| warp::reply::with_header(reply, "Access-Control-Allow-Origin", "*"))
-        .with(cors)
-        .with(log);
+        .with(cors);
+
+    // Looks up the preview tools in the background, for the readiness check
+    tokio::spawn(health::check_tools());
+
+    let shutdown = Shutdown::new();
+    if let Err(msg) = shutdown::trigger_on_signals(shutdown.clone()) {
+        log::error!(target: "remote_text_server::main", "{msg}");
+        std::process::exit(1);
+    }
 
     log::info!(target: "remote_text_server::main", "Running server");
-    // Runs the server with the set up filters
-    warp::serve(routes)
-        .run(([0, 0, 0, 0], 3030))
-        .await;
+    // Runs the server with the set up filters until we're told to stop
+    if let Err(msg) = server::run(routes, &config, shutdown).await {
+        log::error!(target: "remote_text_server::main", "{msg}");
+        std::process::exit(1);
+    }
+
+    // Anything still compiling was cut off by the deadline, so don't leave half-written previews behind
+    files::remove_partial_previews(&config);
+}
+
+// Checks the files directory, then finds the repositories that can be served
+fn git_repositories(config: &Config) -> Repos {
+    log::info!(target: "remote_text_server::main", "Checking files");
+    let report = fsck::check(config);
+    report.log();
+    if config.fsck_repair {
+        fsck::repair(&report, config);
+    }
+
+    log::info!(target: "remote_text_server::main", "Searching for repositories");
+    let repositories = files::repos(config);
+    // Without the repair, broken repositories are still on disk; don't let them take down listFiles
+    for (id, reason) in report.broken() {
+        if files::lock_repos(&repositories).remove(&id).is_some() {
+            // Still listed, in `errors`, so clients know the file exists
+            files::flag(&id, &reason, &repositories);
+            log::warn!(target: "remote_text_server::main", "[{}] Not serving this file until it is repaired (see `admin fsck`)", id);
+        }
+    }
+    return repositories;
 }
@@ -0,0 +1,196 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::net::SocketAddr;
+use std::sync::{Mutex, MutexGuard, TryLockError};
+
+use chrono::{DateTime, SubsecRound, Utc};
+use uuid::Uuid;
+
+use crate::api::{CreateFileResult, FileList, FileSummary, GitCommit, GitHistory, GitRef};
+use crate::error::{ApiError, ErrorCode};
+use crate::storage::{check_file_name, Storage, TreeExport};
+
+/// The branch a new file's first commit is on, as `git init` would name it
+const INITIAL_BRANCH: &str = "master";
+
+/// Every file and its history in memory, lost when the server stops. For tests, and for embedding
+///   the server where there's no files directory
+#[derive(Default)]
+pub(crate) struct MemoryStorage {
+    files: Mutex<HashMap<Uuid, MemoryFile>>,
+}
+
+struct MemoryFile {
+    commits: HashMap<String, MemoryCommit>,
+    /// Each branch's name and the hash of the commit it points to
+    branches: BTreeMap<String, String>,
+}
+
+struct MemoryCommit {
+    name: String,
+    content: String,
+    parent: Option<String>,
+    time: DateTime<Utc>,
+    /// Orders commits made within the same second
+    sequence: usize,
+}
+
+impl MemoryStorage {
+    pub(crate) fn new() -> MemoryStorage {
+        return MemoryStorage::default();
+    }
+
+    // Every change is a single insert or remove, so a panicked request can't have left the files
+    //   half-updated
+    fn lock(&self) -> MutexGuard<'_, HashMap<Uuid, MemoryFile>> {
+        return self.files.lock().unwrap_or_else(|poisoned| {
+            self.files.clear_poison();
+            poisoned.into_inner()
+        });
+    }
+}
+
+impl MemoryFile {
+    fn summary(&self, uuid: &Uuid) -> Option<FileSummary> {
+        let newest = self.commits.values().max_by_key(|commit| (commit.time, commit.sequence))?;
+        let oldest = self.commits.values().min_by_key(|commit| (commit.time, commit.sequence))?;
+        return Some(FileSummary { name: newest.name.clone(), id: *uuid, edited_time: newest.time, created_time: oldest.time });
+    }
+
+    fn commit(&self, uuid: &Uuid, hash: &str) -> Result<&MemoryCommit, ApiError> {
+        let Some(commit) = self.commits.get(hash) else {
+            log::info!(target: "remote_text_server::memory", "[{}] No commit with hash {}", uuid, hash);
+            return Err(ApiError::invalid_hash(hash));
+        };
+        return Ok(commit);
+    }
+}
+
+// Hashes look like git's, but are random rather than derived from the content
+fn new_hash() -> String {
+    return format!("{:016x}{:016x}{:08x}", rand::random::<u64>(), rand::random::<u64>(), rand::random::<u32>());
+}
+
+// Git stores commit times to the second
+fn now() -> DateTime<Utc> {
+    return Utc::now().trunc_subsecs(0);
+}
+
+impl Storage for MemoryStorage {
+    fn list_files(&self) -> FileList {
+        let files = self.lock().iter().filter_map(|(uuid, file)| file.summary(uuid)).collect::<Vec<_>>();
+        return FileList { total: files.len(), files, errors: vec![], next: None };
+    }
+
+    fn create_file(&self, name: String, content: Option<String>, _addr: Option<SocketAddr>) -> Result<CreateFileResult, ApiError> {
+        let uuid = Uuid::new_v4();
+        if let Err(err) = check_file_name(&name) {
+            log::info!(target: "remote_text_server::memory", "[{}] Invalid file name ({})", uuid, name);
+            return Err(err);
+        }
+        let hash = new_hash();
+        let time = now();
+        let commit = MemoryCommit { name: name.clone(), content: content.unwrap_or_default(), parent: None, time, sequence: 0 };
+        let file = MemoryFile {
+            commits: HashMap::from([(hash.clone(), commit)]),
+            branches: BTreeMap::from([(INITIAL_BRANCH.to_string(), hash.clone())]),
+        };
+        self.lock().insert(uuid, file);
+        log::info!(target: "remote_text_server::memory", "[{}] Created file ({})", uuid, hash);
+        return Ok(CreateFileResult { name, id: uuid, hash, created_time: time });
+    }
+
+    fn read_file(&self, id: &Uuid, hash: &str) -> Result<(String, String), ApiError> {
+        let files = self.lock();
+        let commit = find(&files, id)?.commit(id, hash)?;
+        return Ok((commit.name.clone(), commit.content.clone()));
+    }
+
+    fn save_file(&self, id: &Uuid, name: &str, content: &str, parent: &str, branch: &str, force: bool, _addr: Option<SocketAddr>) -> Result<GitCommit, ApiError> {
+        let mut files = self.lock();
+        let file = find_mut(&mut files, id)?;
+        file.commit(id, parent)?;
+        if let Err(err) = check_file_name(name) {
+            log::info!(target: "remote_text_server::memory", "[{}] Invalid file name ({})", id, name);
+            return Err(err);
+        }
+        if !git2::Reference::is_valid_name(&format!("refs/heads/{}", branch)) {
+            log::info!(target: "remote_text_server::memory", "[{}] Invalid branch name ({})", id, branch);
+            return Err(ApiError::invalid_branch_name(branch));
+        }
+        if let Some(head) = file.branches.get(branch).filter(|head| *head != parent && !force) {
+            log::info!(target: "remote_text_server::memory", "[{}] {} is at {}, not {}", id, branch, head, parent);
+            return Err(ApiError::branch_conflict(branch, head));
+        }
+        let hash = new_hash();
+        let commit = MemoryCommit { name: name.to_string(), content: content.to_string(), parent: Some(parent.to_string()), time: now(), sequence: file.commits.len() };
+        file.commits.insert(hash.clone(), commit);
+        file.branches.insert(branch.to_string(), hash.clone());
+        log::info!(target: "remote_text_server::memory", "[{}] Saved {} on {}", id, hash, branch);
+        return Ok(GitCommit { hash, parent: Some(parent.to_string()) });
+    }
+
+    fn get_history(&self, id: &Uuid) -> Result<GitHistory, ApiError> {
+        let files = self.lock();
+        let file = find(&files, id)?;
+        return Ok(GitHistory {
+            commits: file.commits.iter().map(|(hash, commit)| GitCommit { hash: hash.clone(), parent: commit.parent.clone() }).collect(),
+            refs: file.branches.iter().map(|(name, hash)| GitRef { name: name.clone(), hash: hash.clone() }).collect(),
+        });
+    }
+
+    fn delete_file(&self, id: &Uuid) -> Result<(), ApiError> {
+        if self.lock().remove(id).is_none() {
+            log::info!(target: "remote_text_server::memory", "[{}] Request made to delete nonexistent file", id);
+            return Err(ApiError::file_not_found(id));
+        }
+        log::info!(target: "remote_text_server::memory", "[{}] Deleted file", id);
+        return Ok(());
+    }
+
+    fn export(&self, id: &Uuid, hash: &str) -> Result<TreeExport, ApiError> {
+        let (name, content) = self.read_file(id, hash)?;
+        let Ok(export) = TreeExport::new(id) else {
+            log::error!(target: "remote_text_server::memory", "[{}] Cannot create temporary directory", id);
+            return Err(ApiError::new(ErrorCode::PreviewStorageError, "Cannot create temporary directory"));
+        };
+        if fs::write(export.dir().join(&name), content).is_err() {
+            log::error!(target: "remote_text_server::memory", "[{}] Cannot write {} to {:?}", id, name, export.dir());
+            return Err(ApiError::new(ErrorCode::PreviewStorageError, "Cannot export the file for compiling"));
+        }
+        return Ok(export);
+    }
+
+    fn file_count(&self) -> usize {
+        self.lock().len()
+    }
+
+    // Nothing is opened to read a file kept in memory
+    fn open_count(&self) -> usize {
+        0
+    }
+
+    fn try_file_count(&self) -> Result<Option<usize>, String> {
+        match self.files.try_lock() {
+            Ok(files) => Ok(Some(files.len())),
+            Err(TryLockError::WouldBlock) => Ok(None),
+            Err(TryLockError::Poisoned(poisoned)) => Ok(Some(poisoned.into_inner().len())),
+        }
+    }
+}
+
+fn find<'f>(files: &'f HashMap<Uuid, MemoryFile>, id: &Uuid) -> Result<&'f MemoryFile, ApiError> {
+    let Some(file) = files.get(id) else {
+        log::info!(target: "remote_text_server::memory", "[{}] Request made for nonexistent file", id);
+        return Err(ApiError::file_not_found(id));
+    };
+    return Ok(file);
+}
+
+fn find_mut<'f>(files: &'f mut HashMap<Uuid, MemoryFile>, id: &Uuid) -> Result<&'f mut MemoryFile, ApiError> {
+    let Some(file) = files.get_mut(id) else {
+        log::info!(target: "remote_text_server::memory", "[{}] Request made for nonexistent file", id);
+        return Err(ApiError::file_not_found(id));
+    };
+    return Ok(file);
+}
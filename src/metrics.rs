@@ -0,0 +1,159 @@
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use prometheus::{Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry, TextEncoder};
+
+use crate::config::Config;
+use crate::openapi;
+
+/// The routes we label request metrics with: every path in the OpenAPI document, which has one for
+///   each route the server serves, and its label, which is the path without the leading `/api/`
+///   (e.g. `listFiles` or `v2/files/{id}/history`). Anything else is counted as `other`, so that
+///   clients requesting random paths can't create unbounded numbers of series
+fn routes() -> &'static [(String, String)] {
+    static ROUTES: OnceLock<Vec<(String, String)>> = OnceLock::new();
+    ROUTES.get_or_init(|| openapi::spec().paths.paths.into_keys()
+        .map(|path| {
+            let label = path.strip_prefix("/api/").unwrap_or(&path).trim_start_matches('/').to_string();
+            (path, label)
+        })
+        .collect())
+}
+
+/// Every metric the server exports, registered in one registry
+pub(crate) struct Metrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    request_duration: HistogramVec,
+    compiles: IntCounterVec,
+    compile_duration: HistogramVec,
+    preview_cache: IntCounterVec,
+    repositories: IntGauge,
+    open_repositories: IntGauge,
+    disk_usage: IntGaugeVec,
+}
+
+/// The outcome of running a compiler
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum CompileOutcome {
+    /// The compiler ran and succeeded
+    Success,
+    /// The compiler ran and reported an error in the document
+    Failure,
+    /// The compiler couldn't be run, or its output couldn't be read
+    Error,
+}
+
+impl CompileOutcome {
+    fn as_str(&self) -> &'static str {
+        match self {
+            CompileOutcome::Success => "success",
+            CompileOutcome::Failure => "failure",
+            CompileOutcome::Error => "error",
+        }
+    }
+}
+
+/// The process-wide metrics
+pub(crate) fn metrics() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    METRICS.get_or_init(Metrics::new)
+}
+
+impl Metrics {
+    fn new() -> Metrics {
+        let registry = Registry::new_custom(Some("remote_text".to_string()), None).unwrap();
+        let requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests handled, by route and status code"),
+            &["route", "status"]).unwrap();
+        let request_duration = HistogramVec::new(
+            HistogramOpts::new("http_request_duration_seconds", "Time taken to handle HTTP requests, by route and status code"),
+            &["route", "status"]).unwrap();
+        let compiles = IntCounterVec::new(
+            Opts::new("compiles_total", "Compiler runs for previews, by compiler and outcome (success, failure or error)"),
+            &["compiler", "outcome"]).unwrap();
+        let compile_duration = HistogramVec::new(
+            HistogramOpts::new("compile_duration_seconds", "Time taken by compiler runs for previews, by compiler")
+                .buckets(vec![0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 20.0, 30.0, 60.0, 120.0]),
+            &["compiler"]).unwrap();
+        let preview_cache = IntCounterVec::new(
+            Opts::new("preview_cache_total", "previewFile requests answered from an earlier compile (hit) or by compiling (miss)"),
+            &["result"]).unwrap();
+        let repositories = IntGauge::new("repositories", "Repositories being served").unwrap();
+        let open_repositories = IntGauge::new("open_repositories", "Repositories with an open handle").unwrap();
+        let disk_usage = IntGaugeVec::new(
+            Opts::new("disk_usage_bytes", "Bytes used on disk, by directory (files or previews)"),
+            &["directory"]).unwrap();
+
+        registry.register(Box::new(requests.clone())).unwrap();
+        registry.register(Box::new(request_duration.clone())).unwrap();
+        registry.register(Box::new(compiles.clone())).unwrap();
+        registry.register(Box::new(compile_duration.clone())).unwrap();
+        registry.register(Box::new(preview_cache.clone())).unwrap();
+        registry.register(Box::new(repositories.clone())).unwrap();
+        registry.register(Box::new(open_repositories.clone())).unwrap();
+        registry.register(Box::new(disk_usage.clone())).unwrap();
+
+        Metrics { registry, requests, request_duration, compiles, compile_duration, preview_cache, repositories, open_repositories, disk_usage }
+    }
+
+    pub(crate) fn record_request(&self, path: &str, status: u16, elapsed: Duration) {
+        let route = route_label(path);
+        let status = status.to_string();
+        self.requests.with_label_values(&[route, &status]).inc();
+        self.request_duration.with_label_values(&[route, &status]).observe(elapsed.as_secs_f64());
+    }
+
+    pub(crate) fn record_compile(&self, compiler: &str, outcome: CompileOutcome, elapsed: Duration) {
+        self.compiles.with_label_values(&[compiler, outcome.as_str()]).inc();
+        self.compile_duration.with_label_values(&[compiler]).observe(elapsed.as_secs_f64());
+    }
+
+    pub(crate) fn record_preview_cache(&self, hit: bool) {
+        self.preview_cache.with_label_values(&[if hit { "hit" } else { "miss" }]).inc();
+    }
+
+    /// Renders every metric in the Prometheus text format
+    ///
+    /// The repository counts and disk usage are only measured here, when they're asked for.
+    pub(crate) fn render(&self, repositories: usize, open_repositories: usize, config: &Config) -> String {
+        self.repositories.set(repositories as i64);
+        self.open_repositories.set(open_repositories as i64);
+        self.disk_usage.with_label_values(&["files"]).set(disk_usage(&config.files_dir) as i64);
+        self.disk_usage.with_label_values(&["previews"]).set(disk_usage(&config.previews_dir) as i64);
+
+        let mut buffer = vec![];
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer).unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+}
+
+/// The label for requests to `path`: that of the route it matches (see `routes`), or `other`
+pub(crate) fn route_label(path: &str) -> &'static str {
+    let segments = path.trim_end_matches('/').split('/').collect::<Vec<_>>();
+    let matches = |route: &str| {
+        let route = route.split('/').collect::<Vec<_>>();
+        route.len() == segments.len() && route.iter().zip(&segments).all(|(expected, segment)| {
+            expected == segment || (expected.starts_with('{') && !segment.is_empty())
+        })
+    };
+    return routes().iter().find(|(route, _)| matches(route)).map(|(_, label)| label.as_str()).unwrap_or("other");
+}
+
+/// The total size of the files under `path`. Symlinks aren't followed
+fn disk_usage(path: &Path) -> u64 {
+    let Ok(metadata) = fs::symlink_metadata(path) else {
+        return 0;
+    };
+    if !metadata.is_dir() {
+        return metadata.len();
+    }
+    fs::read_dir(path)
+        .map(|entries| entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| disk_usage(&entry.path()))
+            .sum())
+        .unwrap_or(0)
+}
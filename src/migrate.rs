@@ -0,0 +1,123 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use git2::Repository;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::config::Config;
+
+/// Added to a repository's directory name while it's converted. A directory left behind by an
+///   interrupted migration is finished off by the next one
+pub(crate) const STAGING_SUFFIX: &str = ".bare";
+
+/// What [`to_bare`] did
+#[derive(Serialize, Clone, Debug, Default)]
+pub(crate) struct Migration {
+    /// Repositories converted to bare repositories
+    pub(crate) converted: Vec<Uuid>,
+    /// Repositories that were bare already
+    pub(crate) skipped: Vec<Uuid>,
+    /// Repositories that could not be converted, with the reason
+    pub(crate) failed: Vec<(Uuid, String)>,
+}
+
+/// Converts every repository in the files directory into a bare repository, in place
+///
+/// The `.git` directory, with every object, branch and reflog, becomes the repository's directory,
+///   and the working tree, which the server never reads, is removed. Branches and HEAD are checked
+///   to be unchanged afterwards.
+pub(crate) fn to_bare(config: &Config) -> Migration {
+    let mut migration = Migration::default();
+    let Ok(entries) = fs::read_dir(&config.files_dir) else {
+        return migration;
+    };
+    let ids = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.file_name().to_str().map(str::to_string))
+        .filter_map(|name| Uuid::parse_str(name.strip_suffix(STAGING_SUFFIX).unwrap_or(&name)).ok())
+        .collect::<BTreeSet<_>>();
+    for id in ids {
+        match convert(&config.files_dir.join(id.to_string())) {
+            Ok(true) => {
+                log::info!(target: "remote_text_server::migrate", "[{}] Converted to a bare repository", id);
+                migration.converted.push(id);
+            },
+            Ok(false) => migration.skipped.push(id),
+            Err(msg) => {
+                log::error!(target: "remote_text_server::migrate", "[{}] Unable to convert to a bare repository: {msg}", id);
+                migration.failed.push((id, msg));
+            }
+        }
+    }
+    return migration;
+}
+
+fn staging_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(STAGING_SUFFIX);
+    return path.with_file_name(name);
+}
+
+// Returns whether the repository was converted, rather than being bare already
+fn convert(path: &Path) -> Result<bool, String> {
+    let git_dir = path.join(".git");
+    let staging = staging_path(path);
+    let before = if staging.exists() {
+        if git_dir.exists() {
+            return Err(format!("Both {:?} and {:?} exist", git_dir, staging));
+        }
+        // Interrupted after the `.git` directory was moved out, so there's nothing to compare with
+        None
+    } else {
+        let repo = Repository::open(path).map_err(|err| format!("Not a repository: {}", err.message()))?;
+        if repo.is_bare() {
+            return Ok(false);
+        }
+        if !fs::symlink_metadata(&git_dir).map(|metadata| metadata.is_dir()).unwrap_or(false) {
+            return Err(format!("{:?} is not a directory", git_dir));
+        }
+        let refs = refs(&repo)?;
+        drop(repo);
+        fs::rename(&git_dir, &staging).map_err(|err| format!("Unable to move {:?}: {err}", git_dir))?;
+        Some(refs)
+    };
+
+    git2::Config::open(&staging.join("config"))
+        .and_then(|mut config| config.set_bool("core.bare", true))
+        .map_err(|err| format!("Unable to set core.bare: {}", err.message()))?;
+    // The staging area only matters with a working tree
+    let _ = fs::remove_file(staging.join("index"));
+    if path.exists() {
+        fs::remove_dir_all(path).map_err(|err| format!("Unable to remove the working tree: {err}"))?;
+    }
+    fs::rename(&staging, path).map_err(|err| format!("Unable to move {:?}: {err}", staging))?;
+
+    let repo = Repository::open(path).map_err(|err| format!("Converted repository can't be opened: {}", err.message()))?;
+    if !repo.is_bare() {
+        return Err("Converted repository isn't bare".to_string());
+    }
+    if before.is_some_and(|before| before != refs(&repo).unwrap_or_default()) {
+        return Err("Branches changed during conversion".to_string());
+    }
+    return Ok(true);
+}
+
+// Every ref, including HEAD, with the commit or ref it points to
+fn refs(repo: &Repository) -> Result<BTreeMap<String, String>, String> {
+    let references = repo.references().map_err(|err| format!("Unable to read refs: {}", err.message()))?;
+    let mut refs = references
+        .filter_map(|reference| reference.ok())
+        .filter_map(|reference| {
+            let target = reference.symbolic_target().map(str::to_string).or(reference.target().map(|oid| oid.to_string()))?;
+            Some((reference.name()?.to_string(), target))
+        })
+        .collect::<BTreeMap<_, _>>();
+    if let Ok(head) = repo.find_reference("HEAD") {
+        if let Some(target) = head.symbolic_target() {
+            refs.insert("HEAD".to_string(), target.to_string());
+        }
+    }
+    return Ok(refs);
+}
@@ -0,0 +1,47 @@
+use utoipa::OpenApi;
+
+use crate::routes;
+
+/// The OpenAPI 3 document for the API, generated from the request and response types and the
+///   `#[utoipa::path]` attribute on each route. Served at `/api/openapi.json`
+///
+/// A route added to `routes::get_routes` needs adding here too; `openapi_tests` checks that every
+///   route is documented, and every documented operation against the real handlers.
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Remote Text server", description = "Stores text files with their git history, and compiles previews of them"),
+    paths(
+        routes::list_files,
+        routes::create_file,
+        routes::get_file,
+        routes::save_file,
+        routes::delete_file,
+        routes::preview_file,
+        routes::get_preview,
+        routes::get_history,
+        routes::health,
+        routes::ready,
+        routes::openapi,
+        routes::v2_list_files,
+        routes::v2_create_file,
+        routes::v2_delete_file,
+        routes::v2_get_history,
+        routes::v2_get_file,
+        routes::v2_save_file,
+        routes::v2_preview_file,
+        routes::v2_get_preview,
+        routes::metrics,
+    ),
+    tags(
+        (name = "files", description = "The original calls, used by the clients: POST with the IDs in the JSON body"),
+        (name = "v2", description = "The same operations as resources"),
+        (name = "status", description = "Health checks and metrics"),
+    )
+)]
+pub(crate) struct ApiDoc;
+
+pub(crate) fn spec() -> utoipa::openapi::OpenApi {
+    let mut spec = ApiDoc::openapi();
+    spec.info.version = env!("CARGO_PKG_VERSION").to_string();
+    return spec;
+}
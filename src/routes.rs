@@ -1,12 +1,21 @@
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 
-use git2::Repository;
+use percent_encoding::percent_decode_str;
 use serde::de::DeserializeOwned;
+use serde_json::json;
 use uuid::Uuid;
 use warp::Filter;
+use warp::http::{header, HeaderValue};
+use warp::reply::Response;
 
-use crate::handlers;
+use crate::api::{CompilationOutput, CreateFileResult, File, FileList, FileSummary, GitCommit, GitHistory};
+use crate::config::Config;
+use crate::error::{ApiError, ErrorCode};
+use crate::health::{Health, Readiness};
+use crate::listing::ListQuery;
+use crate::{handlers, server};
+use crate::handlers::{FileAndHashAndBranchName, FileIDAndGitHash, IdOnly, NameAndContentAndParent, NameAndOptionalContent};
+use crate::storage::Store;
 
 // Filter that limits requests to 4KiB and deserializes JSON
 pub(crate) fn json_body_small<T: DeserializeOwned + Send>() -> impl Filter<Extract = (T,), Error = warp::Rejection> + Clone {
@@ -20,71 +29,439 @@ pub(crate) fn json_body_big<T: DeserializeOwned + Send>() -> impl Filter<Extract
         .and(warp::body::json())
 }
 
-// Filter that maps to the list_files api call, then tries to fulfill the request
-pub(crate) fn list_files(repos: Arc<Mutex<HashMap<Uuid, Repository>>>) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+// Filter that deserializes the query string, treating a missing one as empty so that every
+//   parameter can be left out
+pub(crate) fn optional_query<T: DeserializeOwned + Send>() -> impl Filter<Extract = (T,), Error = warp::Rejection> + Clone {
+    warp::query::raw()
+        .or(warp::any().map(String::new))
+        .unify()
+        .and_then(|raw: String| async move {
+            serde_urlencoded::from_str::<T>(&raw).map_err(|err| warp::reject::custom(
+                ApiError::new(ErrorCode::InvalidQuery, "Query string is not valid for this endpoint").with_details(json!({ "reason": err.to_string() }))
+            ))
+        })
+}
+
+// Filter that maps to the list_files api call, with the sorting, filtering and paging in the query
+//   string, then tries to fulfill the request
+#[utoipa::path(
+    post,
+    path = "/api/listFiles",
+    params(ListQuery),
+    responses(
+        (status = 200, description = "A page of the files, sorted and filtered. Files that can't be read are left out; `GET /api/v2/files` lists them in `errors`", body = [FileSummary],
+            headers(
+                ("x-total-count" = usize, description = "How many files match, on every page"),
+                ("x-next-cursor" = String, description = "The `cursor` for the next page; not sent on the last one"),
+            )
+        ),
+        (status = 400, description = "INVALID_QUERY", body = ApiError),
+    ),
+    tag = "files"
+)]
+pub(crate) fn list_files(store: Store) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
     warp::path("listFiles")
-        .and_then(move || handlers::list_files(repos.clone()))
+        .and(optional_query())
+        .and_then(move |query| handlers::list_files_array(query, store.clone()))
 }
 
 // Filter that maps to the create_file api call, uses the json_body to restrict file size, then tries to fulfill the request
-pub(crate) fn create_file(repos: Arc<Mutex<HashMap<Uuid, Repository>>>) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+#[utoipa::path(
+    post,
+    path = "/api/createFile",
+    request_body = NameAndOptionalContent,
+    responses(
+        (status = 200, description = "The new file and its first commit", body = CreateFileResult),
+        (status = 400, description = "INVALID_FILE_NAME or INVALID_BODY", body = ApiError),
+        (status = 413, description = "BODY_TOO_LARGE", body = ApiError),
+        (status = 500, description = "STORAGE_ERROR", body = ApiError),
+    ),
+    tag = "files"
+)]
+pub(crate) fn create_file(store: Store) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
     warp::path("createFile")
         .and(json_body_big())
-        .and(warp::filters::addr::remote())
-        .and_then(move |obj, addr| handlers::create_file(obj, addr, repos.clone()))
+        .and(server::remote())
+        .and_then(move |obj, addr| handlers::create_file(obj, addr, store.clone()))
 }
 
 // Filter that maps to the get_file api call, uses the json_body to restrict file size, then tries to fulfill the request
-pub(crate) fn get_file(repos: Arc<Mutex<HashMap<Uuid, Repository>>>) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+#[utoipa::path(
+    post,
+    path = "/api/getFile",
+    request_body = FileIDAndGitHash,
+    responses(
+        (status = 200, description = "The file's name and content at the commit", body = File),
+        (status = 400, description = "INVALID_HASH or INVALID_BODY", body = ApiError),
+        (status = 404, description = "FILE_NOT_FOUND", body = ApiError),
+        (status = 500, description = "STORAGE_ERROR", body = ApiError),
+    ),
+    tag = "files"
+)]
+pub(crate) fn get_file(store: Store) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
     warp::path("getFile")
         .and(json_body_small())
-        .and_then(move |obj| handlers::get_file(obj, repos.clone()))
+        .and_then(move |obj| handlers::get_file(obj, store.clone()))
 }
 
 // Filter that maps to the save_file api call, uses the json_body to restrict file size, then tries to fulfill the request
-pub(crate) fn save_file(repos: Arc<Mutex<HashMap<Uuid, Repository>>>) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+#[utoipa::path(
+    post,
+    path = "/api/saveFile",
+    request_body = FileAndHashAndBranchName,
+    responses(
+        (status = 200, description = "The new commit", body = GitCommit),
+        (status = 400, description = "INVALID_HASH, INVALID_FILE_NAME, INVALID_BRANCH_NAME or INVALID_BODY", body = ApiError),
+        (status = 404, description = "FILE_NOT_FOUND", body = ApiError),
+        (status = 409, description = "BRANCH_CONFLICT", body = ApiError),
+        (status = 413, description = "BODY_TOO_LARGE", body = ApiError),
+        (status = 500, description = "STORAGE_ERROR", body = ApiError),
+    ),
+    tag = "files"
+)]
+pub(crate) fn save_file(store: Store) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
     warp::path("saveFile")
         .and(json_body_big())
-        .and(warp::filters::addr::remote())
-        .and_then(move |obj, addr| handlers::save_file(obj, addr, repos.clone()))
+        .and(server::remote())
+        .and_then(move |obj, addr| handlers::save_file(obj, addr, store.clone()))
 }
 
 // Filter that maps to the delete_file api call, then attempts to fufill the request using handler code
-pub(crate) fn delete_file(repos: Arc<Mutex<HashMap<Uuid, Repository>>>) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+#[utoipa::path(
+    post,
+    path = "/api/deleteFile",
+    request_body = IdOnly,
+    responses(
+        (status = 200, description = "The file, its history and its previews were deleted"),
+        (status = 400, description = "INVALID_BODY", body = ApiError),
+        (status = 404, description = "FILE_NOT_FOUND", body = ApiError),
+        (status = 500, description = "STORAGE_ERROR", body = ApiError),
+    ),
+    tag = "files"
+)]
+pub(crate) fn delete_file(store: Store, config: Arc<Config>) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
     warp::path("deleteFile")
         .and(json_body_small())
-        .and_then(move |obj| handlers::delete_file(obj, repos.clone()))
+        .and_then(move |obj| handlers::delete_file(obj, store.clone(), config.clone()))
 }
 
 // Filter that maps to the preview_file api call, uses the json_body to restrict file size, then tries to fulfill the request
-pub(crate) fn preview_file(repos: Arc<Mutex<HashMap<Uuid, Repository>>>) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+#[utoipa::path(
+    post,
+    path = "/api/previewFile",
+    request_body = FileIDAndGitHash,
+    responses(
+        (status = 200, description = "Whether compiling succeeded, and the compiler's log", body = CompilationOutput),
+        (status = 400, description = "INVALID_HASH, MISSING_FILE_EXTENSION or INVALID_BODY", body = ApiError),
+        (status = 404, description = "FILE_NOT_FOUND", body = ApiError),
+        (status = 500, description = "COMPILER_UNAVAILABLE, PREVIEW_STORAGE_ERROR or STORAGE_ERROR", body = ApiError),
+    ),
+    tag = "files"
+)]
+pub(crate) fn preview_file(store: Store, config: Arc<Config>) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
     warp::path("previewFile")
         .and(json_body_small())
-        .and_then(move |obj| handlers::preview_file(obj, repos.clone()))
+        .and_then(move |obj| handlers::preview_file(obj, store.clone(), config.clone()))
 }
 
 // Filter that maps to the get_preview api call, uses the json_body to restrict file size, then tries to fulfill the request
-pub(crate) fn get_preview(repos: Arc<Mutex<HashMap<Uuid, Repository>>>) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+#[utoipa::path(
+    post,
+    path = "/api/getPreview",
+    request_body = FileIDAndGitHash,
+    responses(
+        (status = 200, description = "The compiled preview: a PDF for TeX files, HTML otherwise", content((String = "application/pdf"), (String = "text/html"))),
+        (status = 400, description = "INVALID_HASH or INVALID_BODY", body = ApiError),
+        (status = 404, description = "FILE_NOT_FOUND, PREVIEW_NOT_FOUND or PREVIEW_FAILED", body = ApiError),
+        (status = 500, description = "STORAGE_ERROR or PREVIEW_STORAGE_ERROR", body = ApiError),
+    ),
+    tag = "files"
+)]
+pub(crate) fn get_preview(store: Store, config: Arc<Config>) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
     warp::path("getPreview")
         .and(json_body_small())
-        .and_then(move |obj| handlers::get_preview(obj, repos.clone()))
+        .and_then(move |obj| handlers::get_preview(obj, store.clone(), config.clone()))
 }
 
 // Filter that maps to the get_history api call, uses the json_body to restrict file size, then tries to fulfill the request
-pub(crate) fn get_history(repos: Arc<Mutex<HashMap<Uuid, Repository>>>) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+#[utoipa::path(
+    post,
+    path = "/api/getHistory",
+    request_body = IdOnly,
+    responses(
+        (status = 200, description = "Every commit and branch of the file", body = GitHistory),
+        (status = 400, description = "INVALID_BODY", body = ApiError),
+        (status = 404, description = "FILE_NOT_FOUND", body = ApiError),
+        (status = 500, description = "STORAGE_ERROR", body = ApiError),
+    ),
+    tag = "files"
+)]
+pub(crate) fn get_history(store: Store) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
     warp::path("getHistory")
         .and(json_body_small())
-        .and_then(move |obj| handlers::get_history(obj, repos.clone()))
+        .and_then(move |obj| handlers::get_history(obj, store.clone()))
+}
+
+// Filter that answers GET health as long as the server is running, for liveness probes
+#[utoipa::path(
+    get,
+    path = "/api/health",
+    responses(
+        (status = 200, description = "The server is running", body = Health),
+    ),
+    tag = "status"
+)]
+pub(crate) fn health() -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path("health")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and_then(handlers::health)
+}
+
+// Filter that maps GET ready to the readiness checks, which respond 503 until every check passes
+#[utoipa::path(
+    get,
+    path = "/api/ready",
+    responses(
+        (status = 200, description = "Every check passed", body = Readiness),
+        (status = 503, description = "A check failed", body = Readiness),
+    ),
+    tag = "status"
+)]
+pub(crate) fn ready(store: Store, config: Arc<Config>) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path("ready")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and_then(move || handlers::ready(store.clone(), config.clone()))
+}
+
+// Commits never change, so what's read at one can be cached for good
+fn immutable(reply: impl warp::Reply) -> Response {
+    let mut response = reply.into_response();
+    if response.status().is_success() {
+        response.headers_mut().insert(header::CACHE_CONTROL, HeaderValue::from_static("public, max-age=31536000, immutable"));
+    }
+    return response;
+}
+
+// Filter that maps GET v2/files to the list_files api call
+#[utoipa::path(
+    get,
+    path = "/api/v2/files",
+    params(ListQuery),
+    responses(
+        (status = 200, description = "A page of the files, sorted and filtered, and the ones that can't be read", body = FileList),
+        (status = 400, description = "INVALID_QUERY", body = ApiError),
+    ),
+    tag = "v2"
+)]
+pub(crate) fn v2_list_files(store: Store) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("v2" / "files")
+        .and(warp::get())
+        .and(optional_query())
+        .and_then(move |query| handlers::list_files(query, store.clone()))
+}
+
+// Filter that maps POST v2/files, with the name and optional content as JSON, to the create_file api call
+#[utoipa::path(
+    post,
+    path = "/api/v2/files",
+    request_body = NameAndOptionalContent,
+    responses(
+        (status = 200, description = "The new file and its first commit", body = CreateFileResult),
+        (status = 400, description = "INVALID_FILE_NAME or INVALID_BODY", body = ApiError),
+        (status = 413, description = "BODY_TOO_LARGE", body = ApiError),
+        (status = 500, description = "STORAGE_ERROR", body = ApiError),
+    ),
+    tag = "v2"
+)]
+pub(crate) fn v2_create_file(store: Store) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("v2" / "files")
+        .and(warp::post())
+        .and(json_body_big())
+        .and(server::remote())
+        .and_then(move |obj, addr| handlers::create_file(obj, addr, store.clone()))
+}
+
+// Filter that maps DELETE v2/files/{id} to the delete_file api call
+#[utoipa::path(
+    delete,
+    path = "/api/v2/files/{id}",
+    params(("id" = Uuid, Path, description = "The file's ID")),
+    responses(
+        (status = 200, description = "The file, its history and its previews were deleted"),
+        (status = 404, description = "FILE_NOT_FOUND", body = ApiError),
+        (status = 500, description = "STORAGE_ERROR", body = ApiError),
+    ),
+    tag = "v2"
+)]
+pub(crate) fn v2_delete_file(store: Store, config: Arc<Config>) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("v2" / "files" / Uuid)
+        .and(warp::delete())
+        .and_then(move |id| handlers::delete_file(IdOnly { id }, store.clone(), config.clone()))
+}
+
+// Filter that maps GET v2/files/{id}/history to the get_history api call
+#[utoipa::path(
+    get,
+    path = "/api/v2/files/{id}/history",
+    params(("id" = Uuid, Path, description = "The file's ID")),
+    responses(
+        (status = 200, description = "Every commit and branch of the file", body = GitHistory),
+        (status = 404, description = "FILE_NOT_FOUND", body = ApiError),
+        (status = 500, description = "STORAGE_ERROR", body = ApiError),
+    ),
+    tag = "v2"
+)]
+pub(crate) fn v2_get_history(store: Store) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("v2" / "files" / Uuid / "history")
+        .and(warp::get())
+        .and_then(move |id| handlers::get_history(IdOnly { id }, store.clone()))
+}
+
+// Filter that maps GET v2/files/{id}/commits/{hash} to the get_file api call
+#[utoipa::path(
+    get,
+    path = "/api/v2/files/{id}/commits/{hash}",
+    params(("id" = Uuid, Path, description = "The file's ID"), ("hash" = String, Path, description = "A commit of the file")),
+    responses(
+        (status = 200, description = "The file's name and content at the commit", body = File),
+        (status = 400, description = "INVALID_HASH", body = ApiError),
+        (status = 404, description = "FILE_NOT_FOUND", body = ApiError),
+        (status = 500, description = "STORAGE_ERROR", body = ApiError),
+    ),
+    tag = "v2"
+)]
+pub(crate) fn v2_get_file(store: Store) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("v2" / "files" / Uuid / "commits" / String)
+        .and(warp::get())
+        .and_then(move |id, hash| handlers::get_file(FileIDAndGitHash { id, hash }, store.clone()))
+        .map(immutable)
+}
+
+// Filter that maps PUT v2/files/{id}/branches/{name}, with the file name, content and parent commit
+//   as JSON, to the save_file api call
+#[utoipa::path(
+    put,
+    path = "/api/v2/files/{id}/branches/{branch}",
+    params(("id" = Uuid, Path, description = "The file's ID"), ("branch" = String, Path, description = "The branch to point at the new commit, with `/` escaped as `%2F`")),
+    request_body = NameAndContentAndParent,
+    responses(
+        (status = 200, description = "The new commit", body = GitCommit),
+        (status = 400, description = "INVALID_HASH, INVALID_FILE_NAME, INVALID_BRANCH_NAME or INVALID_BODY", body = ApiError),
+        (status = 404, description = "FILE_NOT_FOUND", body = ApiError),
+        (status = 409, description = "BRANCH_CONFLICT", body = ApiError),
+        (status = 413, description = "BODY_TOO_LARGE", body = ApiError),
+        (status = 500, description = "STORAGE_ERROR", body = ApiError),
+    ),
+    tag = "v2"
+)]
+pub(crate) fn v2_save_file(store: Store) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("v2" / "files" / Uuid / "branches" / String)
+        .and(warp::put())
+        .and(json_body_big())
+        .and(server::remote())
+        .and_then(move |id, branch: String, obj: NameAndContentAndParent, addr| {
+            let branch = percent_decode_str(&branch).decode_utf8_lossy().into_owned();
+            handlers::save_file(FileAndHashAndBranchName { name: obj.name, id, content: obj.content, parent: obj.parent, branch, force: obj.force }, addr, store.clone())
+        })
+}
+
+// Filter that maps POST v2/files/{id}/commits/{hash}/preview to the preview_file api call
+#[utoipa::path(
+    post,
+    path = "/api/v2/files/{id}/commits/{hash}/preview",
+    params(("id" = Uuid, Path, description = "The file's ID"), ("hash" = String, Path, description = "A commit of the file")),
+    responses(
+        (status = 200, description = "Whether compiling succeeded, and the compiler's log", body = CompilationOutput),
+        (status = 400, description = "INVALID_HASH or MISSING_FILE_EXTENSION", body = ApiError),
+        (status = 404, description = "FILE_NOT_FOUND", body = ApiError),
+        (status = 500, description = "COMPILER_UNAVAILABLE, PREVIEW_STORAGE_ERROR or STORAGE_ERROR", body = ApiError),
+    ),
+    tag = "v2"
+)]
+pub(crate) fn v2_preview_file(store: Store, config: Arc<Config>) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("v2" / "files" / Uuid / "commits" / String / "preview")
+        .and(warp::post())
+        .and_then(move |id, hash| handlers::preview_file(FileIDAndGitHash { id, hash }, store.clone(), config.clone()))
+}
+
+// Filter that maps GET v2/files/{id}/commits/{hash}/preview to the get_preview api call
+#[utoipa::path(
+    get,
+    path = "/api/v2/files/{id}/commits/{hash}/preview",
+    params(("id" = Uuid, Path, description = "The file's ID"), ("hash" = String, Path, description = "A commit of the file")),
+    responses(
+        (status = 200, description = "The compiled preview: a PDF for TeX files, HTML otherwise", content((String = "application/pdf"), (String = "text/html"))),
+        (status = 400, description = "INVALID_HASH", body = ApiError),
+        (status = 404, description = "FILE_NOT_FOUND, PREVIEW_NOT_FOUND or PREVIEW_FAILED", body = ApiError),
+        (status = 500, description = "STORAGE_ERROR or PREVIEW_STORAGE_ERROR", body = ApiError),
+    ),
+    tag = "v2"
+)]
+pub(crate) fn v2_get_preview(store: Store, config: Arc<Config>) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("v2" / "files" / Uuid / "commits" / String / "preview")
+        .and(warp::get())
+        .and_then(move |id, hash| handlers::get_preview(FileIDAndGitHash { id, hash }, store.clone(), config.clone()))
+}
+
+// Filter that contains the resource-oriented v2 routes. They call the same handlers as the routes
+//   above, with the IDs taken from the path instead of the body
+pub(crate) fn v2(store: Store, config: Arc<Config>) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    v2_list_files(store.clone())
+        .or(v2_create_file(store.clone()))
+        .or(v2_delete_file(store.clone(), config.clone()))
+        .or(v2_get_history(store.clone()))
+        .or(v2_get_file(store.clone()))
+        .or(v2_save_file(store.clone()))
+        .or(v2_preview_file(store.clone(), config.clone()))
+        .or(v2_get_preview(store.clone(), config.clone()))
+}
+
+// Filter that serves the OpenAPI document describing every route
+#[utoipa::path(
+    get,
+    path = "/api/openapi.json",
+    responses(
+        (status = 200, description = "This document", body = Object, content_type = "application/json"),
+    ),
+    tag = "status"
+)]
+pub(crate) fn openapi() -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path("openapi.json")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and_then(handlers::openapi)
 }
 
 // Filter that contains all other relevant filters, allowing for the use of any filter through this one
-pub(crate) fn get_routes(repos: Arc<Mutex<HashMap<Uuid, Repository>>>) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
-    list_files(repos.clone())
-        .or(create_file(repos.clone()))
-        .or(get_file(repos.clone()))
-        .or(save_file(repos.clone()))
-        .or(delete_file(repos.clone()))
-        .or(preview_file(repos.clone()))
-        .or(get_preview(repos.clone()))
-        .or(get_history(repos.clone()))
+pub(crate) fn get_routes(store: Store, config: Arc<Config>) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    list_files(store.clone())
+        .or(create_file(store.clone()))
+        .or(get_file(store.clone()))
+        .or(save_file(store.clone()))
+        .or(delete_file(store.clone(), config.clone()))
+        .or(preview_file(store.clone(), config.clone()))
+        .or(get_preview(store.clone(), config.clone()))
+        .or(get_history(store.clone()))
+        .or(health())
+        .or(ready(store.clone(), config.clone()))
+        .or(openapi())
+        .or(v2(store.clone(), config.clone()))
+}
+
+// Filter that serves Prometheus metrics at GET /metrics, outside of the API root
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    responses(
+        (status = 200, description = "Prometheus metrics", body = String, content_type = "text/plain"),
+    ),
+    tag = "status"
+)]
+pub(crate) fn metrics(store: Store, config: Arc<Config>) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path("metrics")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and_then(move || handlers::metrics_report(store.clone(), config.clone()))
 }
@@ -0,0 +1,284 @@
+use std::convert::Infallible;
+use std::io;
+use std::net::SocketAddr;
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures::{FutureExt, Stream, StreamExt};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::mpsc;
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+use warp::{Filter, Rejection, Reply};
+use warp::http::{header, HeaderValue};
+use warp::hyper::{Body, Request, Response};
+use warp::hyper::server::accept;
+use warp::hyper::service::{make_service_fn, service_fn, Service};
+
+use crate::config::Config;
+use crate::error::{ApiError, ErrorCode};
+use crate::listener::Listener;
+use crate::logging::{self, Access};
+use crate::metrics::metrics;
+use crate::shutdown::Shutdown;
+use crate::tls;
+
+/// How long a client gets to complete the TLS handshake before its connection is dropped
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The address of the client, stored in the request extensions
+///
+/// `warp::serve` can only report client addresses for its own TCP listener, so connections we
+///   accept ourselves pass the address along this way instead. Use [`remote`] to read it.
+#[derive(Clone, Copy)]
+pub(crate) struct RemoteAddr(pub(crate) SocketAddr);
+
+/// A connection accepted by one of our listeners
+pub(crate) trait Connection: AsyncRead + AsyncWrite + Send + Unpin + 'static {
+    fn remote_addr(&self) -> Option<SocketAddr>;
+}
+
+impl Connection for TcpStream {
+    fn remote_addr(&self) -> Option<SocketAddr> {
+        self.peer_addr().ok()
+    }
+}
+
+// Unix domain socket peers have no IP address
+#[cfg(unix)]
+impl Connection for UnixStream {
+    fn remote_addr(&self) -> Option<SocketAddr> {
+        None
+    }
+}
+
+impl<C: Connection> Connection for TlsStream<C> {
+    fn remote_addr(&self) -> Option<SocketAddr> {
+        self.get_ref().0.remote_addr()
+    }
+}
+
+/// Filter that extracts the address of the client, if the connection has one
+pub(crate) fn remote() -> impl Filter<Extract = (Option<SocketAddr>,), Error = Infallible> + Clone {
+    warp::addr::remote()
+        .and(warp::ext::optional::<RemoteAddr>())
+        .map(|addr: Option<SocketAddr>, ext: Option<RemoteAddr>| addr.or(ext.map(|RemoteAddr(addr)| addr)))
+}
+
+/// Binds the configured listener and serves `filter` on it until `shutdown` is triggered
+///
+/// After the shutdown is triggered, no new connections are accepted and new requests on open
+///   connections get a 503. This returns once every running request has finished, or when
+///   `config.shutdown_timeout` has passed, whichever comes first.
+pub(crate) async fn run<F, R>(filter: F, config: &Config, shutdown: Arc<Shutdown>) -> Result<(), String>
+where
+    F: Filter<Extract = (R,), Error = Rejection> + Clone + Send + Sync + 'static,
+    R: Reply,
+{
+    let listener = Listener::bind(config).await?;
+    let acceptor = match &config.tls {
+        Some(tls_config) => {
+            let cert = Arc::new(tls::ReloadableCert::load(tls_config)?);
+            tls::reload_on_sighup(cert.clone())?;
+            Some(tls::acceptor(cert))
+        },
+        None => None
+    };
+    log::info!(target: "remote_text_server::server", "Listening on {}", listener.url(if acceptor.is_some() { "https" } else { "http" }));
+
+    match (listener, acceptor) {
+        (Listener::Tcp(listener), None) => serve(filter, tcp_incoming(listener), config, shutdown).await,
+        (Listener::Tcp(listener), Some(acceptor)) => serve(filter, tls_incoming(tcp_incoming(listener), acceptor), config, shutdown).await,
+        // The socket file is held here rather than by the stream, since the TLS accept task can
+        //   outlive `serve`. It's removed when this returns
+        #[cfg(unix)]
+        (Listener::Unix(listener, socket_file), None) => {
+            let result = serve(filter, unix_incoming(listener), config, shutdown).await;
+            drop(socket_file);
+            result
+        },
+        #[cfg(unix)]
+        (Listener::Unix(listener, socket_file), Some(acceptor)) => {
+            let result = serve(filter, tls_incoming(unix_incoming(listener), acceptor), config, shutdown).await;
+            drop(socket_file);
+            result
+        },
+    }
+}
+
+async fn serve<F, R, C>(filter: F, incoming: impl Stream<Item = Result<C, io::Error>> + Send + 'static, config: &Config, shutdown: Arc<Shutdown>) -> Result<(), String>
+where
+    F: Filter<Extract = (R,), Error = Rejection> + Clone + Send + Sync + 'static,
+    R: Reply,
+    C: Connection,
+{
+    let service = warp::service(filter);
+    let service_shutdown = shutdown.clone();
+    let make_service = make_service_fn(move |conn: &C| {
+        let remote_addr = conn.remote_addr();
+        let service = service.clone();
+        let shutdown = service_shutdown.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |mut req| {
+                let mut service = service.clone();
+                let shutdown = shutdown.clone();
+                let request_id = logging::request_id_for(req.headers());
+                let header_value = HeaderValue::from_str(&request_id).ok();
+                logging::with_request_id(request_id, async move {
+                    let started = Instant::now();
+                    let method = req.method().clone();
+                    let path = req.uri().path().to_string();
+                    let version = req.version();
+                    let referer = req.headers().get(header::REFERER).and_then(|value| value.to_str().ok()).map(str::to_string);
+                    let user_agent = req.headers().get(header::USER_AGENT).and_then(|value| value.to_str().ok()).map(str::to_string);
+
+                    let mut response = if shutdown.is_triggered() {
+                        // Keep-alive connections can still send requests while we drain
+                        unavailable()
+                    } else {
+                        let _in_flight = shutdown.track();
+                        if let Some(addr) = remote_addr {
+                            req.extensions_mut().insert(RemoteAddr(addr));
+                        }
+                        call_catching_panics(&mut service, req).await
+                    };
+                    if let Some(value) = header_value {
+                        response.headers_mut().insert(logging::REQUEST_ID_HEADER, value);
+                    }
+                    let elapsed = started.elapsed();
+                    metrics().record_request(&path, response.status().as_u16(), elapsed);
+                    logging::log_access(&Access {
+                        remote_addr,
+                        method: &method,
+                        path: &path,
+                        version,
+                        status: response.status(),
+                        referer: referer.as_deref(),
+                        user_agent: user_agent.as_deref(),
+                        elapsed,
+                    });
+                    Ok::<_, Infallible>(response)
+                })
+            }))
+        }
+    });
+
+    let graceful_shutdown = shutdown.clone();
+    let server = warp::hyper::Server::builder(accept::from_stream(incoming))
+        .serve(make_service)
+        .with_graceful_shutdown(async move { graceful_shutdown.triggered().await });
+
+    let timeout = config.shutdown_timeout;
+    let deadline = async {
+        shutdown.triggered().await;
+        log::info!(target: "remote_text_server::server", "Waiting up to {}s for {} running request(s)", timeout.as_secs(), shutdown.in_flight());
+        tokio::time::sleep(timeout).await;
+    };
+    tokio::select! {
+        result = server => result.map_err(|err| format!("Server error: {err}")),
+        _ = deadline => {
+            log::warn!(target: "remote_text_server::server", "Shutdown deadline passed with {} request(s) still running", shutdown.in_flight());
+            Ok(())
+        }
+    }
+}
+
+/// Calls `service`, answering 500 if it panics so that one bad request doesn't take down the
+///   connection. The panic itself, with its backtrace, is logged by `logging::log_panics`
+pub(crate) async fn call_catching_panics<S>(service: &mut S, req: Request<Body>) -> Response<Body>
+where
+    S: Service<Request<Body>, Response = Response<Body>, Error = Infallible>,
+{
+    // Filters can run synchronously in `call` as well as when the future is polled
+    match AssertUnwindSafe(async { service.call(req).await }).catch_unwind().await {
+        Ok(Ok(response)) => response,
+        Ok(Err(never)) => match never {},
+        Err(_) => {
+            log::error!(target: "remote_text_server::server", "Request handler panicked; responding with 500");
+            ApiError::new(ErrorCode::InternalError, "Internal server error").into_response()
+        }
+    }
+}
+
+fn unavailable() -> Response<Body> {
+    let mut response = ApiError::new(ErrorCode::ShuttingDown, "Server is shutting down").into_response();
+    response.headers_mut().insert(header::CONNECTION, HeaderValue::from_static("close"));
+    response
+}
+
+async fn accept_tcp(listener: &TcpListener) -> TcpStream {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let _ = stream.set_nodelay(true);
+                return stream;
+            },
+            Err(err) => {
+                // Usually out of file descriptors; back off instead of spinning or shutting down
+                log::error!(target: "remote_text_server::server", "Unable to accept connection: {err}");
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        }
+    }
+}
+
+fn tcp_incoming(listener: TcpListener) -> impl Stream<Item = Result<TcpStream, io::Error>> + Send + 'static {
+    futures::stream::unfold(listener, |listener| async move {
+        let stream = accept_tcp(&listener).await;
+        Some((Ok(stream), listener))
+    })
+}
+
+#[cfg(unix)]
+async fn accept_unix(listener: &UnixListener) -> UnixStream {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => return stream,
+            Err(err) => {
+                log::error!(target: "remote_text_server::server", "Unable to accept connection: {err}");
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+fn unix_incoming(listener: UnixListener) -> impl Stream<Item = Result<UnixStream, io::Error>> + Send + 'static {
+    futures::stream::unfold(listener, |listener| async move {
+        let stream = accept_unix(&listener).await;
+        Some((Ok(stream), listener))
+    })
+}
+
+// Handshakes run in their own tasks, so one slow client can't hold up everyone else's connections
+fn tls_incoming<C: Connection>(incoming: impl Stream<Item = Result<C, io::Error>> + Send + 'static, acceptor: TlsAcceptor) -> impl Stream<Item = Result<TlsStream<C>, io::Error>> + Send + 'static {
+    let (sender, receiver) = mpsc::channel(64);
+    tokio::spawn(async move {
+        let mut incoming = Box::pin(incoming);
+        while let Some(Ok(stream)) = incoming.next().await {
+            if sender.is_closed() {
+                // The server has stopped
+                return;
+            }
+            let acceptor = acceptor.clone();
+            let sender = sender.clone();
+            tokio::spawn(async move {
+                match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                    Ok(Ok(tls_stream)) => {
+                        let _ = sender.send(tls_stream).await;
+                    },
+                    Ok(Err(err)) => log::debug!(target: "remote_text_server::server", "TLS handshake failed: {err}"),
+                    Err(_) => log::debug!(target: "remote_text_server::server", "TLS handshake timed out")
+                }
+            });
+        }
+    });
+    futures::stream::unfold(receiver, |mut receiver| async move {
+        let stream = receiver.recv().await?;
+        Some((Ok(stream), receiver))
+    })
+}
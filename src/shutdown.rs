@@ -0,0 +1,84 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use tokio::sync::watch;
+
+/// Coordinates a graceful shutdown between the signal handlers and the server
+///
+/// Once triggered, the server stops accepting connections and refuses new requests, while
+///   requests that are already running are tracked so they can be given time to finish.
+pub(crate) struct Shutdown {
+    triggered: watch::Sender<bool>,
+    in_flight: AtomicUsize,
+}
+
+/// Marks a request as in flight for as long as it is alive
+pub(crate) struct InFlight(Arc<Shutdown>);
+
+impl Shutdown {
+    pub(crate) fn new() -> Arc<Shutdown> {
+        let (triggered, _) = watch::channel(false);
+        Arc::new(Shutdown { triggered, in_flight: AtomicUsize::new(0) })
+    }
+
+    pub(crate) fn trigger(&self) {
+        self.triggered.send_replace(true);
+    }
+
+    pub(crate) fn is_triggered(&self) -> bool {
+        *self.triggered.borrow()
+    }
+
+    /// Completes once the shutdown has been triggered
+    pub(crate) async fn triggered(&self) {
+        let mut receiver = self.triggered.subscribe();
+        // `wait_for` only fails if the sender is dropped, which can't happen while `self` is alive
+        let _ = receiver.wait_for(|triggered| *triggered).await;
+    }
+
+    pub(crate) fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn track(self: &Arc<Self>) -> InFlight {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        InFlight(self.clone())
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Triggers `shutdown` when the process receives SIGTERM or SIGINT
+#[cfg(unix)]
+pub(crate) fn trigger_on_signals(shutdown: Arc<Shutdown>) -> Result<(), String> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate())
+        .map_err(|err| format!("Unable to listen for SIGTERM: {err}"))?;
+    let mut interrupt = signal(SignalKind::interrupt())
+        .map_err(|err| format!("Unable to listen for SIGINT: {err}"))?;
+    tokio::spawn(async move {
+        let name = tokio::select! {
+            _ = terminate.recv() => "SIGTERM",
+            _ = interrupt.recv() => "SIGINT",
+        };
+        log::warn!(target: "remote_text_server::shutdown", "Received {name}; shutting down");
+        shutdown.trigger();
+    });
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub(crate) fn trigger_on_signals(shutdown: Arc<Shutdown>) -> Result<(), String> {
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            log::warn!(target: "remote_text_server::shutdown", "Received Ctrl-C; shutting down");
+            shutdown.trigger();
+        }
+    });
+    Ok(())
+}
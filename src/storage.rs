@@ -0,0 +1,154 @@
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, TryLockError};
+
+use uuid::Uuid;
+
+use crate::api::{CreateFileResult, FileList, GitCommit, GitHistory};
+use crate::config::Config;
+use crate::error::ApiError;
+use crate::files::{self, Repos};
+
+/// Where files and their histories are kept. The handlers only go through this, so the server can
+///   run on the git repositories in the files directory or entirely in memory
+///
+/// Every method may block, so handlers call them on the blocking pool. Errors are the responses to
+///   send, and are logged by the implementation.
+pub(crate) trait Storage: Send + Sync {
+    /// Every file's summary, with the files that can't be summarized in `errors`
+    fn list_files(&self) -> FileList;
+
+    /// Makes a new file with a single commit
+    fn create_file(&self, name: String, content: Option<String>, addr: Option<SocketAddr>) -> Result<CreateFileResult, ApiError>;
+
+    /// The file's name and content at commit `hash`
+    fn read_file(&self, id: &Uuid, hash: &str) -> Result<(String, String), ApiError>;
+
+    /// Commits `content`, named `name`, on top of `parent` and points `branch` at the new commit
+    ///
+    /// A branch that exists must point at `parent`, so saves based on an old commit can't silently
+    ///   discard newer ones, unless `force` is set.
+    #[allow(clippy::too_many_arguments)]
+    fn save_file(&self, id: &Uuid, name: &str, content: &str, parent: &str, branch: &str, force: bool, addr: Option<SocketAddr>) -> Result<GitCommit, ApiError>;
+
+    /// Every commit of the file, and every branch (ref) with the commit it points to
+    fn get_history(&self, id: &Uuid) -> Result<GitHistory, ApiError>;
+
+    fn delete_file(&self, id: &Uuid) -> Result<(), ApiError>;
+
+    /// Writes the file at commit `hash` into a temporary directory, for compilers that need a real file
+    fn export(&self, id: &Uuid, hash: &str) -> Result<TreeExport, ApiError>;
+
+    /// How many files there are
+    fn file_count(&self) -> usize;
+
+    /// How many files have something open (such as a repository handle), for the metrics
+    fn open_count(&self) -> usize;
+
+    /// How many files there are, without waiting: `Ok(None)` while the storage is busy, and an error
+    ///   when it can't be used at all. For the readiness check
+    fn try_file_count(&self) -> Result<Option<usize>, String>;
+}
+
+pub(crate) type Store = Arc<dyn Storage>;
+
+/// Refuses the names git refuses as a tree entry, so every storage accepts the same names, and a
+///   file in a working tree can't end up outside its repository
+///
+/// Storages call this before writing anything.
+pub(crate) fn check_file_name(name: &str) -> Result<(), ApiError> {
+    let reserved = ["", ".", ".."].contains(&name) || name.eq_ignore_ascii_case(".git");
+    if reserved || name.contains(['/', '\0']) {
+        return Err(ApiError::invalid_file_name(name));
+    }
+    return Ok(());
+}
+
+/// A temporary export of a commit's tree, for compilers that need real files. The storage doesn't
+///   need to stay locked while they run. Removed when dropped
+pub(crate) struct TreeExport {
+    dir: PathBuf,
+}
+
+impl TreeExport {
+    /// A new, empty, directory in the system's temporary directory
+    pub(crate) fn new(uuid: &Uuid) -> std::io::Result<TreeExport> {
+        let export = TreeExport { dir: std::env::temp_dir().join(format!("remote-text-{}-{:016x}", uuid, rand::random::<u64>())) };
+        fs::create_dir_all(&export.dir)?;
+        return Ok(export);
+    }
+
+    pub(crate) fn dir(&self) -> &Path {
+        &self.dir
+    }
+}
+
+impl Drop for TreeExport {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Every file as a git repository in the files directory
+pub(crate) struct GitStorage {
+    repos: Repos,
+    config: Arc<Config>,
+}
+
+impl GitStorage {
+    pub(crate) fn new(repos: Repos, config: Arc<Config>) -> GitStorage {
+        return GitStorage { repos, config };
+    }
+}
+
+impl Storage for GitStorage {
+    fn list_files(&self) -> FileList {
+        files::list_files(&self.repos)
+    }
+
+    fn create_file(&self, name: String, content: Option<String>, addr: Option<SocketAddr>) -> Result<CreateFileResult, ApiError> {
+        files::create_file(name, content, addr, &self.repos, &self.config)
+    }
+
+    fn read_file(&self, id: &Uuid, hash: &str) -> Result<(String, String), ApiError> {
+        files::with_repo(id, &self.repos, |repo| files::get_file_contents(id, hash, repo))
+    }
+
+    fn save_file(&self, id: &Uuid, name: &str, content: &str, parent: &str, branch: &str, force: bool, addr: Option<SocketAddr>) -> Result<GitCommit, ApiError> {
+        files::with_repo(id, &self.repos, |repo| {
+            let commit = files::save_file(id, name, content, parent, branch, force, addr, repo)?;
+            files::reindex(id, repo, &self.repos);
+            return Ok(commit);
+        })
+    }
+
+    fn get_history(&self, id: &Uuid) -> Result<GitHistory, ApiError> {
+        files::with_repo(id, &self.repos, |repo| files::get_history(id, repo))
+    }
+
+    fn delete_file(&self, id: &Uuid) -> Result<(), ApiError> {
+        files::delete_file(id, &self.repos, &self.config)
+    }
+
+    fn export(&self, id: &Uuid, hash: &str) -> Result<TreeExport, ApiError> {
+        files::with_repo(id, &self.repos, |repo| files::export_tree(id, hash, repo))
+    }
+
+    fn file_count(&self) -> usize {
+        files::lock_repos(&self.repos).len()
+    }
+
+    fn open_count(&self) -> usize {
+        files::lock_repos(&self.repos).open_count()
+    }
+
+    fn try_file_count(&self) -> Result<Option<usize>, String> {
+        match self.repos.try_lock() {
+            Ok(repos) => Ok(Some(repos.len())),
+            Err(TryLockError::WouldBlock) => Ok(None),
+            // Recovered when it's next locked
+            Err(TryLockError::Poisoned(poisoned)) => Ok(Some(poisoned.into_inner().len())),
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use std::fs;
+
+use git2::Repository;
+use uuid::Uuid;
+
+use crate::admin::{self, AdminCommand};
+use crate::config::Config;
+use crate::{files, migrate};
+use crate::tests::TempConfig;
+
+fn create(config: &Config, content: &str) -> Uuid {
+    fs::create_dir_all(&config.files_dir).unwrap();
+    let repos = files::repos(config);
+    files::create_file("doc.md".to_string(), Some(content.to_string()), None, &repos, config).unwrap().id
+}
+
+#[test]
+fn export_copies_history_and_branches() {
+    let config = TempConfig::new();
+    let id = create(&config, "hello");
+    let dest = config.files_dir.parent().unwrap().join("export");
+
+    admin::run(AdminCommand::Export { id, dest: dest.clone() }, &config).unwrap();
+
+    let export = Repository::open(&dest).unwrap();
+    assert!(export.find_branch("master", git2::BranchType::Local).is_ok());
+    assert_eq!(fs::read_to_string(dest.join("doc.md")).unwrap(), "hello");
+    // Exporting over a non-empty directory is refused
+    assert!(admin::run(AdminCommand::Export { id, dest }, &config).is_err());
+}
+
+#[test]
+fn delete_removes_repository_and_previews() {
+    let config = TempConfig::new();
+    let id = create(&config, "hello");
+    let previews = config.previews_dir.join(id.to_string());
+    fs::create_dir_all(&previews).unwrap();
+
+    admin::run(AdminCommand::Delete { id }, &config).unwrap();
+
+    assert!(!config.files_dir.join(id.to_string()).exists());
+    assert!(!previews.exists());
+    assert!(admin::run(AdminCommand::Delete { id }, &config).is_err());
+}
+
+#[test]
+fn history_and_cat_report_unknown_files() {
+    let config = TempConfig::new();
+    let id = create(&config, "hello");
+    let history = files::with_repo(&id, &files::repos(&config), |repo| files::get_history(&id, repo)).unwrap();
+
+    assert_eq!(history.commits.len(), 1);
+    assert!(admin::run(AdminCommand::Cat { id, hash: history.commits[0].hash.clone() }, &config).is_ok());
+    assert!(admin::run(AdminCommand::Cat { id, hash: "not a hash".to_string() }, &config).is_err());
+    assert!(admin::run(AdminCommand::History { id: Uuid::new_v4(), json: false }, &config).is_err());
+}
+
+#[test]
+fn migrate_bare_keeps_history_and_branches() {
+    let config = TempConfig::new();
+    let id = create(&config, "hello");
+    let repos = files::repos(&config);
+    let first = files::with_repo(&id, &repos, |repo| files::get_history(&id, repo)).unwrap().commits[0].hash.clone();
+    files::with_repo(&id, &repos, |repo| files::save_file(&id, "doc.md", "draft", &first, "draft", false, None, repo)).unwrap();
+    let before = files::with_repo(&id, &repos, |repo| files::get_history(&id, repo)).unwrap();
+    drop(repos);
+
+    admin::run(AdminCommand::MigrateBare { json: false }, &config).unwrap();
+
+    let path = config.files_dir.join(id.to_string());
+    assert!(Repository::open(&path).unwrap().is_bare());
+    assert!(!path.join("doc.md").exists() && !path.join(".git").exists());
+    let repos = files::repos(&config);
+    let after = files::with_repo(&id, &repos, |repo| files::get_history(&id, repo)).unwrap();
+    assert_eq!(serde_json::to_value(&after).unwrap(), serde_json::to_value(&before).unwrap());
+    let (_, content) = files::with_repo(&id, &repos, |repo| files::get_file_contents(&id, &first, repo)).unwrap();
+    assert_eq!(content, "hello");
+    assert_eq!(files::list_files(&repos).files.len(), 1);
+    // Converting again leaves it alone
+    assert!(migrate::to_bare(&config).skipped.contains(&id));
+}
+
+#[test]
+fn interrupted_migration_is_finished() {
+    let config = TempConfig::new();
+    let id = create(&config, "hello");
+    let path = config.files_dir.join(id.to_string());
+    // Interrupted right after moving the repository out of the working tree
+    fs::rename(path.join(".git"), config.files_dir.join(format!("{id}{}", migrate::STAGING_SUFFIX))).unwrap();
+
+    let migration = migrate::to_bare(&config);
+
+    assert_eq!(migration.converted, vec![id]);
+    assert!(Repository::open(&path).unwrap().is_bare());
+    assert_eq!(fs::read_dir(&config.files_dir).unwrap().count(), 2); // the repository and index.json
+}
@@ -0,0 +1,65 @@
+use clap::{CommandFactory, Parser};
+
+use crate::admin::AdminCommand;
+use crate::cli::{self, Cli, Command};
+
+#[test]
+fn cli_definition_is_valid() {
+    Cli::command().debug_assert();
+}
+
+#[test]
+fn no_subcommand_means_serve() {
+    let cli = Cli::try_parse_from(["remote-text-server", "--port", "7870"]).unwrap();
+
+    assert!(cli.command.is_none());
+    assert_eq!(cli.serve.config.port, Some(7870));
+}
+
+#[test]
+fn serve_subcommand_takes_config_flags() {
+    let cli = Cli::try_parse_from(["remote-text-server", "serve", "--address", "::", "--files-dir", "/srv/files"]).unwrap();
+
+    let Some(Command::Serve(args)) = cli.command else {
+        panic!("expected the serve subcommand");
+    };
+    assert_eq!(args.config.address.unwrap().to_string(), "::");
+    assert_eq!(args.config.files_dir.unwrap().to_str(), Some("/srv/files"));
+}
+
+#[test]
+fn unknown_flags_are_errors() {
+    assert!(Cli::try_parse_from(["remote-text-server", "--bogus"]).is_err());
+    assert!(Cli::try_parse_from(["remote-text-server", "-vv"]).is_err());
+    assert!(Cli::try_parse_from(["remote-text-server", "version", "--bogus"]).is_err());
+}
+
+#[test]
+fn version_verbose_flag() {
+    let cli = Cli::try_parse_from(["remote-text-server", "version", "--verbose"]).unwrap();
+
+    let Some(Command::Version(args)) = cli.command else {
+        panic!("expected the version subcommand");
+    };
+    assert!(args.verbose);
+}
+
+#[test]
+fn verbose_version_contains_build_info() {
+    let info = cli::verbose_version();
+
+    assert_eq!(info["version"], env!("CARGO_PKG_VERSION"));
+    assert_eq!(info["git_sha"], env!("VERGEN_GIT_SHA"));
+    assert_eq!(info["rustc_semver"], env!("VERGEN_RUSTC_SEMVER"));
+}
+
+#[test]
+fn admin_requires_a_subcommand() {
+    assert!(Cli::try_parse_from(["remote-text-server", "admin"]).is_err());
+
+    let cli = Cli::try_parse_from(["remote-text-server", "admin", "--files-dir", "/srv/files", "list", "--json"]).unwrap();
+    let Some(Command::Admin(args)) = cli.command else {
+        panic!("expected the admin subcommand");
+    };
+    assert!(matches!(args.command, AdminCommand::List { json: true }));
+}
@@ -0,0 +1,78 @@
+use std::fs;
+use std::net::{IpAddr, Ipv6Addr};
+use std::path::{Path, PathBuf};
+
+use uuid::Uuid;
+
+use crate::config::{Config, ConfigArgs, StorageKind};
+
+fn write_config_file(contents: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("remote-text-config-{}.toml", Uuid::new_v4()));
+    fs::write(&path, contents).unwrap();
+    path
+}
+
+#[test]
+fn defaults_without_config_file() {
+    let config = Config::load(&ConfigArgs::default()).unwrap();
+
+    assert_eq!(config.socket_addr().to_string(), "0.0.0.0:3030");
+    assert_eq!(config.files_dir, Path::new(".").join("files"));
+    assert_eq!(config.previews_dir, Path::new(".").join("previews"));
+    assert_eq!(config.log_level, "warn");
+    assert_eq!(config.unix_socket, None);
+    assert_eq!(config.storage, StorageKind::Git);
+}
+
+#[test]
+fn flags_override_config_file() {
+    let path = write_config_file(r#"
+        address = "::1"
+        port = 8080
+        files_dir = "/srv/remote-text/files"
+        log_level = "debug"
+        unix_socket = "/run/remote-text/server.sock"
+    "#);
+
+    let args = ConfigArgs {
+        config: Some(path.clone()),
+        port: Some(9090),
+        previews_dir: Some(PathBuf::from("/tmp/previews")),
+        ..ConfigArgs::default()
+    };
+    let config = Config::load(&args).unwrap();
+    fs::remove_file(path).unwrap();
+
+    assert_eq!(config.address, IpAddr::V6(Ipv6Addr::LOCALHOST));
+    assert_eq!(config.port, 9090);
+    assert_eq!(config.files_dir, PathBuf::from("/srv/remote-text/files"));
+    assert_eq!(config.previews_dir, PathBuf::from("/tmp/previews"));
+    assert_eq!(config.log_level, "debug");
+    assert_eq!(config.unix_socket, Some(PathBuf::from("/run/remote-text/server.sock")));
+    assert_eq!(config.socket_addr().to_string(), "[::1]:9090");
+}
+
+#[test]
+fn unknown_config_keys_are_rejected() {
+    let path = write_config_file("prot = 8080\n");
+
+    let args = ConfigArgs { config: Some(path.clone()), ..ConfigArgs::default() };
+    let result = Config::load(&args);
+    fs::remove_file(path).unwrap();
+
+    assert!(result.is_err());
+}
+
+#[test]
+fn missing_config_file_is_an_error() {
+    let args = ConfigArgs { config: Some(PathBuf::from("./does-not-exist.toml")), ..ConfigArgs::default() };
+
+    assert!(Config::load(&args).is_err());
+}
+
+#[test]
+fn open_repositories_must_be_positive() {
+    let args = ConfigArgs { open_repositories: Some(0), ..ConfigArgs::default() };
+
+    assert!(Config::load(&args).is_err());
+}
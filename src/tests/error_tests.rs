@@ -0,0 +1,69 @@
+use std::fs;
+use std::sync::Arc;
+
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::memory::MemoryStorage;
+use crate::storage::{Storage, Store};
+use crate::tests::{call, reply, TempConfig};
+
+// Sends `body` as it is, which doesn't have to be valid JSON
+async fn call_raw(store: &Store, config: &Config, method: &str, path: &str, body: &[u8]) -> (u16, Value) {
+    let request = warp::test::request().method(method).path(path).header("content-type", "application/json").body(body);
+    let response = reply(store, config, request).await;
+    (response.status().as_u16(), serde_json::from_slice(response.body()).unwrap_or(Value::Null))
+}
+
+#[tokio::test]
+async fn handler_errors_have_stable_codes() {
+    let config = TempConfig::new();
+    let memory = MemoryStorage::new();
+    let created = memory.create_file("README".to_string(), Some("no extension".to_string()), None).unwrap();
+    let store: Store = Arc::new(memory);
+    let unknown = Uuid::new_v4();
+
+    let (status, body) = call(&store, &config, "POST", "/getFile", Some(json!({ "id": unknown, "hash": created.hash }))).await;
+    assert_eq!((status, body), (404, json!({ "code": "FILE_NOT_FOUND", "message": "No file with this ID", "details": { "id": unknown } })));
+    let (status, body) = call(&store, &config, "POST", "/getFile", Some(json!({ "id": created.id, "hash": "nope" }))).await;
+    assert_eq!((status, body["code"].as_str(), body["details"]["hash"].as_str()), (400, Some("INVALID_HASH"), Some("nope")));
+    let (status, body) = call(&store, &config, "POST", "/saveFile", Some(json!({ "id": created.id, "name": "README", "content": "", "parent": created.hash, "branch": "" }))).await;
+    assert_eq!((status, body["code"].as_str()), (400, Some("INVALID_BRANCH_NAME")));
+    let (status, body) = call(&store, &config, "POST", "/saveFile", Some(json!({ "id": created.id, "name": "a/b", "content": "", "parent": created.hash, "branch": "main" }))).await;
+    assert_eq!((status, body["code"].as_str()), (400, Some("INVALID_FILE_NAME")));
+    let (status, body) = call(&store, &config, "POST", "/previewFile", Some(json!({ "id": created.id, "hash": created.hash }))).await;
+    assert_eq!((status, body["code"].as_str(), body["details"]["name"].as_str()), (400, Some("MISSING_FILE_EXTENSION"), Some("README")));
+    let (status, body) = call(&store, &config, "POST", "/getPreview", Some(json!({ "id": created.id, "hash": created.hash }))).await;
+    assert_eq!((status, body["code"].as_str()), (404, Some("PREVIEW_NOT_FOUND")));
+    // The hash is part of the preview's path, so one that leaves the previews directory is refused
+    let outside = config.previews_dir.join(created.id.to_string()).join(format!("../{unknown}/{}", created.hash));
+    fs::create_dir_all(&outside).unwrap();
+    fs::write(outside.join("README.status"), "SUCCESS").unwrap();
+    let (status, body) = call(&store, &config, "POST", "/getPreview", Some(json!({ "id": created.id, "hash": format!("../{unknown}/{}", created.hash) }))).await;
+    assert_eq!((status, body["code"].as_str()), (400, Some("INVALID_HASH")));
+    let (status, body) = call(&store, &config, "POST", "/getPreview", Some(json!({ "id": unknown, "hash": created.hash }))).await;
+    assert_eq!((status, body["code"].as_str()), (404, Some("FILE_NOT_FOUND")));
+    let (status, body) = call(&store, &config, "POST", "/deleteFile", Some(json!({ "id": unknown }))).await;
+    assert_eq!((status, body["code"].as_str()), (404, Some("FILE_NOT_FOUND")));
+}
+
+#[tokio::test]
+async fn rejections_use_the_same_format() {
+    let config = TempConfig::new();
+    let store: Store = Arc::new(MemoryStorage::new());
+
+    let (status, body) = call_raw(&store, &config, "POST", "/getFile", b"{not json").await;
+    assert_eq!((status, body["code"].as_str()), (400, Some("INVALID_BODY")));
+    assert!(body["details"]["reason"].is_string());
+    let (status, body) = call(&store, &config, "POST", "/getFile", Some(json!({ "hash": "abc" }))).await;
+    assert_eq!((status, body["code"].as_str()), (400, Some("INVALID_BODY")));
+    assert!(body["details"]["reason"].as_str().unwrap().contains("id"));
+    let (status, body) = call(&store, &config, "POST", "/getFile", Some(json!({ "id": Uuid::new_v4(), "hash": "a".repeat(8192) }))).await;
+    assert_eq!((status, body["code"].as_str()), (413, Some("BODY_TOO_LARGE")));
+    let (status, body) = call(&store, &config, "POST", "/noSuchEndpoint", None).await;
+    assert_eq!((status, body["code"].as_str()), (404, Some("NOT_FOUND")));
+    let (status, body) = call_raw(&store, &config, "POST", "/health", b"").await;
+    assert_eq!((status, body["code"].as_str()), (405, Some("METHOD_NOT_ALLOWED")));
+    assert!(body["message"].is_string() && body.get("details").is_some());
+}
@@ -0,0 +1,350 @@
+use std::fs;
+use std::sync::{mpsc, Arc};
+use std::time::Duration;
+
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::error::ErrorCode;
+use crate::files;
+use crate::storage::{GitStorage, Store};
+use crate::handlers::{FileAndHashAndBranchName, IdOnly};
+use crate::tests::TempConfig;
+use warp::Reply;
+
+fn store(repos: &files::Repos, config: &Config) -> Store {
+    Arc::new(GitStorage::new(repos.clone(), Arc::new(config.clone())))
+}
+
+fn create(config: &Config, repos: &files::Repos, name: &str) -> Uuid {
+    files::create_file(name.to_string(), Some("hello".to_string()), None, repos, config).unwrap().id
+}
+
+// Hold one repository's lock from another thread, as a long compile would, until `release` is sent
+fn hold_lock(repo: files::RepoHandle) -> (mpsc::Sender<()>, std::thread::JoinHandle<()>) {
+    let (locked_tx, locked_rx) = mpsc::channel();
+    let (release_tx, release_rx) = mpsc::channel::<()>();
+    let holder = std::thread::spawn(move || {
+        let _guard = repo.lock().unwrap();
+        locked_tx.send(()).unwrap();
+        let _ = release_rx.recv();
+    });
+    locked_rx.recv().unwrap();
+    (release_tx, holder)
+}
+
+#[tokio::test]
+async fn busy_repository_does_not_block_other_files() {
+    let config = TempConfig::new();
+    let repos = files::repos(&config);
+    let busy = create(&config, &repos, "busy.tex");
+    let other = create(&config, &repos, "other.md");
+
+    let (release, holder) = hold_lock(files::find_repo(&busy, &repos).unwrap());
+    let filter = crate::routes::get_history(store(&repos, &config));
+    let response = tokio::time::timeout(Duration::from_secs(5), warp::test::request()
+        .method("POST")
+        .path("/getHistory")
+        .json(&IdOnly { id: other })
+        .reply(&filter)).await;
+    // The map itself is free while a repository is locked
+    let created = create(&config, &repos, "new.md");
+    release.send(()).unwrap();
+    holder.join().unwrap();
+
+    assert_eq!(response.expect("getHistory waited on another file's lock").status(), 200);
+    assert!(files::find_repo(&created, &repos).is_ok());
+}
+
+#[test]
+fn saving_the_index_does_not_block_lookups() {
+    let config = TempConfig::new();
+    let repos = files::repos(&config);
+    let id = create(&config, &repos, "doc.md");
+
+    // Hold the index's lock from another thread, as a slow write of index.json would
+    let (locked_tx, locked_rx) = mpsc::channel();
+    let (release_tx, release_rx) = mpsc::channel::<()>();
+    let holder = {
+        let repos = repos.clone();
+        std::thread::spawn(move || files::lock_index(&repos, |_| {
+            locked_tx.send(()).unwrap();
+            let _ = release_rx.recv();
+        }))
+    };
+    locked_rx.recv().unwrap();
+    let history = files::with_repo(&id, &repos, |repo| files::get_history(&id, repo));
+    release_tx.send(()).unwrap();
+    holder.join().unwrap();
+
+    assert!(history.is_ok());
+}
+
+#[test]
+fn delete_waits_for_repository_in_use() {
+    let config = TempConfig::new();
+    let repos = files::repos(&config);
+    let id = create(&config, &repos, "doc.md");
+
+    let (release, holder) = hold_lock(files::find_repo(&id, &repos).unwrap());
+    let deleter = {
+        let (repos, config) = (repos.clone(), config.clone());
+        std::thread::spawn(move || files::delete_file(&id, &repos, &config))
+    };
+    std::thread::sleep(Duration::from_millis(100));
+    let exists_while_locked = config.files_dir.join(id.to_string()).exists();
+    release.send(()).unwrap();
+    holder.join().unwrap();
+    let deleted = deleter.join().unwrap();
+    let exists_after = config.files_dir.join(id.to_string()).exists();
+
+    assert!(exists_while_locked);
+    assert!(deleted.is_ok());
+    assert!(!exists_after);
+    assert!(files::find_repo(&id, &repos).is_err());
+}
+
+#[tokio::test]
+async fn list_files_reports_broken_repositories_alongside_healthy_ones() {
+    let config = TempConfig::new();
+    let repos = files::repos(&config);
+    let healthy = create(&config, &repos, "doc.md");
+    // A repository without any commits, and one whose lock was poisoned by a panicking request,
+    //   which is still listed since listing doesn't touch repositories
+    let empty = Uuid::new_v4();
+    let repo = git2::Repository::init(config.files_dir.join(empty.to_string())).unwrap();
+    files::reindex(&empty, &repo, &repos);
+    repos.lock().unwrap().insert(empty, repo);
+    let poisoned = create(&config, &repos, "other.md");
+    let repo = files::find_repo(&poisoned, &repos).unwrap();
+    let _ = std::thread::spawn(move || {
+        let _guard = repo.lock().unwrap();
+        panic!("poisoning the lock");
+    }).join();
+
+    let response = warp::test::request()
+        .method("GET")
+        .path("/v2/files")
+        .reply(&crate::routes::v2_list_files(store(&repos, &config)))
+        .await;
+
+    assert_eq!(response.status(), 200);
+    let list: crate::api::FileList = serde_json::from_slice(response.body()).unwrap();
+    let mut files = list.files.iter().map(|file| file.id).collect::<Vec<_>>();
+    files.sort();
+    let mut expected = vec![healthy, poisoned];
+    expected.sort();
+    assert_eq!(files, expected);
+    let errors = list.errors.iter().map(|error| (error.id, error.reason.as_str())).collect::<Vec<_>>();
+    assert_eq!(errors, vec![(empty, "Repository has no commits")]);
+}
+
+#[tokio::test]
+async fn reads_leave_head_and_working_tree_untouched() {
+    let config = TempConfig::new();
+    let repos = files::repos(&config);
+    let created = files::create_file("doc.md".to_string(), Some("first".to_string()), None, &repos, &config).unwrap();
+    let saved = crate::handlers::save_file(FileAndHashAndBranchName {
+        name: "doc.md".to_string(),
+        id: created.id,
+        content: "second".to_string(),
+        parent: created.hash.clone(),
+        branch: "main".to_string(),
+        force: false,
+    }, Some("127.0.0.1:1234".parse().unwrap()), store(&repos, &config)).await.unwrap();
+    assert_eq!(saved.into_response().status(), 200);
+
+    let (name, content) = files::with_repo(&created.id, &repos, |repo| files::get_file_contents(&created.id, &created.hash, repo)).unwrap();
+    let list = files::list_files(&repos);
+    let head = files::with_repo(&created.id, &repos, |repo| Ok(repo.head().unwrap().name().unwrap().to_string())).unwrap();
+    let on_disk = fs::read_to_string(config.files_dir.join(created.id.to_string()).join("doc.md")).unwrap();
+    let clean = files::with_repo(&created.id, &repos, |repo| Ok(repo.statuses(None).unwrap().is_empty())).unwrap();
+
+    assert_eq!((name.as_str(), content.as_str()), ("doc.md", "first"));
+    assert_eq!(list.files.len(), 1);
+    assert_eq!(head, "refs/heads/main");
+    // The working tree follows the save, so git doesn't see any changes
+    assert_eq!(on_disk, "second");
+    assert!(clean);
+}
+
+#[test]
+fn renaming_save_leaves_only_the_new_file_checked_out() {
+    let config = TempConfig::new();
+    let repos = files::repos(&config);
+    let created = files::create_file("doc.md".to_string(), Some("first".to_string()), None, &repos, &config).unwrap();
+
+    let saved = files::with_repo(&created.id, &repos, |repo| files::save_file(&created.id, "renamed.md", "second", &created.hash, "master", false, None, repo));
+    let dir = config.files_dir.join(created.id.to_string());
+    let clean = files::with_repo(&created.id, &repos, |repo| Ok(repo.statuses(None).unwrap().is_empty())).unwrap();
+
+    assert!(saved.is_ok());
+    assert!(!dir.join("doc.md").exists());
+    assert_eq!(fs::read_to_string(dir.join("renamed.md")).unwrap(), "second");
+    assert!(clean);
+}
+
+#[test]
+fn exported_tree_is_removed_when_dropped() {
+    let config = TempConfig::new();
+    let repos = files::repos(&config);
+    let created = files::create_file("doc.tex".to_string(), Some("\\relax".to_string()), None, &repos, &config).unwrap();
+
+    let export = files::with_repo(&created.id, &repos, |repo| files::export_tree(&created.id, &created.hash, repo)).unwrap();
+    let dir = export.dir().to_path_buf();
+    let exported = fs::read_to_string(dir.join("doc.tex")).unwrap();
+    drop(export);
+    let unknown = files::with_repo(&created.id, &repos, |repo| files::export_tree(&created.id, &"0".repeat(40), repo).map(|_| ()));
+
+    assert_eq!(exported, "\\relax");
+    assert!(!dir.exists());
+    assert_eq!(unknown.unwrap_err().code, ErrorCode::InvalidHash);
+}
+
+#[test]
+fn least_recently_used_repositories_are_closed_over_capacity() {
+    let config = TempConfig::with(|config| config.open_repositories = 1);
+    let repos = files::repos(&config);
+    let first = create(&config, &repos, "first.md");
+    let second = create(&config, &repos, "second.md");
+
+    let open_after_create = repos.lock().unwrap().open_count();
+    let first_history = files::with_repo(&first, &repos, |repo| files::get_history(&first, repo));
+    let second_history = files::with_repo(&second, &repos, |repo| files::get_history(&second, repo));
+    let open_after_reads = repos.lock().unwrap().open_count();
+
+    assert_eq!(open_after_create, 1);
+    assert!(first_history.is_ok());
+    assert!(second_history.is_ok());
+    assert_eq!(open_after_reads, 1);
+}
+
+#[tokio::test]
+async fn index_follows_saves_and_deletes_across_restarts() {
+    let config = TempConfig::new();
+    let repos = files::repos(&config);
+    let created = files::create_file("doc.md".to_string(), Some("first".to_string()), None, &repos, &config).unwrap();
+    let deleted = create(&config, &repos, "gone.md");
+    let saved = crate::handlers::save_file(FileAndHashAndBranchName {
+        name: "renamed.md".to_string(),
+        id: created.id,
+        content: "second".to_string(),
+        parent: created.hash.clone(),
+        branch: "main".to_string(),
+        force: false,
+    }, Some("127.0.0.1:1234".parse().unwrap()), store(&repos, &config)).await.unwrap();
+    assert_eq!(saved.into_response().status(), 200);
+    files::delete_file(&deleted, &repos, &config).unwrap();
+
+    let reloaded = files::list_files(&files::repos(&config));
+
+    assert!(reloaded.errors.is_empty());
+    assert_eq!(reloaded.files.iter().map(|file| (file.id, file.name.as_str())).collect::<Vec<_>>(), vec![(created.id, "renamed.md")]);
+}
+
+#[test]
+fn missing_index_is_rebuilt() {
+    let config = TempConfig::new();
+    let repos = files::repos(&config);
+    let mut ids = vec![create(&config, &repos, "one.md"), create(&config, &repos, "two.tex")];
+    ids.sort();
+    let index = config.files_dir.join(crate::index::INDEX_FILE);
+    let existed = index.exists();
+    fs::remove_file(&index).unwrap();
+
+    let list = files::list_files(&files::repos(&config));
+    let rebuilt = index.exists();
+
+    assert!(existed);
+    assert_eq!(list.files.iter().map(|file| file.id).collect::<Vec<_>>(), ids);
+    assert!(rebuilt);
+}
+
+#[test]
+fn index_catches_up_with_commits_made_while_the_server_was_down() {
+    let config = TempConfig::new();
+    let repos = files::repos(&config);
+    let created = files::create_file("doc.md".to_string(), Some("first".to_string()), None, &repos, &config).unwrap();
+    drop(repos);
+    let repo = git2::Repository::open(config.files_dir.join(created.id.to_string())).unwrap();
+    let saved = files::save_file(&created.id, "moved.md", "second", &created.hash, "main", false, None, &repo);
+
+    let reloaded = files::list_files(&files::repos(&config));
+
+    assert!(saved.is_ok());
+    assert_eq!(reloaded.files.iter().map(|file| (file.id, file.name.as_str())).collect::<Vec<_>>(), vec![(created.id, "moved.md")]);
+}
+
+#[test]
+fn save_without_remote_address_is_committed() {
+    let config = TempConfig::new();
+    let repos = files::repos(&config);
+    let created = files::create_file("doc.md".to_string(), Some("first".to_string()), None, &repos, &config).unwrap();
+
+    let saved = files::with_repo(&created.id, &repos, |repo| files::save_file(&created.id, "doc.md", "second", &created.hash, "main", false, None, repo));
+    let author = saved.as_ref().ok().map(|commit| files::with_repo(&created.id, &repos, |repo| {
+        let commit = repo.find_commit(git2::Oid::from_str(&commit.hash).unwrap()).unwrap();
+        let author = commit.author().name().unwrap().to_string();
+        Ok(author)
+    }).unwrap());
+
+    assert_eq!(saved.unwrap().parent, Some(created.hash));
+    assert_eq!(author.as_deref(), Some("Non Socket Remote User"));
+}
+
+#[test]
+fn failed_saves_leave_branches_untouched() {
+    let config = TempConfig::new();
+    let repos = files::repos(&config);
+    let created = files::create_file("doc.md".to_string(), Some("first".to_string()), None, &repos, &config).unwrap();
+    let save = |name: &str, parent: &str, branch: &str| files::with_repo(&created.id, &repos, |repo| {
+        files::save_file(&created.id, name, "second", parent, branch, false, None, repo)
+    }).err().map(|err| err.code);
+
+    let bad_name = save("dir/doc.md", &created.hash, "master");
+    let bad_parent = save("doc.md", "0123456789012345678901234567890123456789", "master");
+    let bad_branch = save("doc.md", &created.hash, "two..dots");
+    let (branches, head) = files::with_repo(&created.id, &repos, |repo| {
+        let branches = repo.branches(None).unwrap()
+            .map(|branch| {
+                let (branch, _) = branch.unwrap();
+                (branch.name().unwrap().unwrap().to_string(), branch.get().target().unwrap().to_string())
+            })
+            .collect::<Vec<_>>();
+        Ok((branches, repo.head().unwrap().target().unwrap().to_string()))
+    }).unwrap();
+
+    assert_eq!(bad_name, Some(ErrorCode::InvalidFileName));
+    assert_eq!(bad_parent, Some(ErrorCode::InvalidHash));
+    assert_eq!(bad_branch, Some(ErrorCode::InvalidBranchName));
+    assert_eq!(branches, vec![("master".to_string(), created.hash.clone())]);
+    assert_eq!(head, created.hash);
+}
+
+#[tokio::test]
+async fn repositories_recover_from_panicked_requests() {
+    let config = TempConfig::new();
+    let repos = files::repos(&config);
+    let id = create(&config, &repos, "doc.md");
+    let repo = files::find_repo(&id, &repos).unwrap();
+    let _ = std::thread::spawn(move || {
+        let _guard = repo.lock().unwrap();
+        panic!("poisoning the lock");
+    }).join();
+    let map = repos.clone();
+    let _ = std::thread::spawn(move || {
+        let _guard = map.lock().unwrap();
+        panic!("poisoning the lock");
+    }).join();
+
+    let store = store(&repos, &config);
+    let history = store.get_history(&id).unwrap();
+    let other = store.create_file("other.md".to_string(), None, None).unwrap();
+    let listed = store.list_files().files.len();
+
+    assert_eq!(history.commits.len(), 1);
+    assert_eq!(listed, 2);
+    assert!(!repos.is_poisoned());
+    assert!(!files::find_repo(&id, &repos).unwrap().is_poisoned());
+    assert_ne!(other.id, id);
+}
@@ -1,34 +1,28 @@
-#[cfg(test)]
-
 // #[macro_use] extern crate log;
 extern crate pretty_env_logger;
 
 use std::fs;
-use std::ops::Deref;
-use std::path::{Path, PathBuf};
+use std::path::Path;
+use std::sync::Arc;
 
-use futures::task::Spawn;
-use serde_json;
 use uuid::Uuid;
 use warp::test;
 
-use crate::{api, handlers, routes};
-use crate::files::repos;
+use crate::{api, files, handlers, routes};
+use crate::config::Config;
+use crate::storage::{GitStorage, Store};
 use crate::handlers::{FileIDAndGitHash, IdOnly};
 use crate::routes::get_file;
+use crate::tests::TempConfig;
 
-#[allow(non_snake_case)]
-fn FILES_DIR() -> PathBuf {
-    Path::new(".").join("files")
-}
-#[allow(non_snake_case)]
-fn PREVIEWS_DIR() -> PathBuf {
-    Path::new(".").join("previews")
+
+fn repos(config: &Config) -> Store {
+    Arc::new(GitStorage::new(files::repos(config), Arc::new(config.clone())))
 }
 
 
-fn clear_files_directory(test_name: &str, obj_id: Uuid) {
-    match fs::remove_dir_all(FILES_DIR().join(obj_id.to_string())){
+fn clear_files_directory(files_dir: &Path, test_name: &str, obj_id: Uuid) {
+    match fs::remove_dir_all(files_dir.join(obj_id.to_string())){
       Ok(_) => {
           log::info!(target: "remote_text_server::tests", "[{}][{}] Test has finished and test files have been deleted", test_name, obj_id.to_string());
       }, Err(_) => {
@@ -43,7 +37,8 @@ async fn test_list_files_filter() {
 
     let _ = pretty_env_logger::try_init();
 
-    let repositories = repos();
+    let config = TempConfig::new();
+    let repositories = repos(&config);
     let filter = routes::list_files(repositories);
 
     let result = test::request()
@@ -63,7 +58,8 @@ async fn test_create_file_filter() {
 
     let _ = pretty_env_logger::try_init();
 
-    let repositories = repos();
+    let config = TempConfig::new();
+    let repositories = repos(&config);
     let filter = routes::create_file(repositories);
 
     let obj = handlers::NameAndOptionalContent{ name: "TestFile".to_string(), content: None };
@@ -80,7 +76,7 @@ async fn test_create_file_filter() {
     assert_eq!(result.status(), 200);
     assert_eq!(deserialized_result.name, "TestFile");
 
-    clear_files_directory("test_create_files_filter", deserialized_result.id);
+    clear_files_directory(&config.files_dir, "test_create_files_filter", deserialized_result.id);
 }
 
 #[tokio::test]
@@ -88,12 +84,13 @@ async fn create_file_too_large() {
 
     let _ = pretty_env_logger::try_init();
 
-    let repositories = repos();
+    let config = TempConfig::new();
+    let repositories = repos(&config);
     let filter = routes::create_file(repositories);
 
     let bytes = vec![b'a' ; (1024 * 1024 * 16) + 1];
     let body  = std::str::from_utf8(&bytes).unwrap();
-    let body = format!("{}", body);
+    let body = body.to_string();
     let body = Some(body);
     let obj = handlers::NameAndOptionalContent{ name: "TestFile".to_string(), content: body };
 
@@ -114,7 +111,8 @@ async fn test_get_file_filter() {
 
     let _ = pretty_env_logger::try_init();
 
-    let repositories = repos();
+    let config = TempConfig::new();
+    let repositories = repos(&config);
 
     // Finding a file requires both an ID and GitHash
 
@@ -136,22 +134,17 @@ async fn test_get_file_filter() {
 
     assert_eq!(deserialized_result.name, "TestFile");
 
-    let getFileInfo = {
-        let fileGitHash = repositories
-            .lock()
-            .unwrap();
-
-        let fileGitHash = fileGitHash.deref().get(&deserialized_result.id).unwrap();
+    let get_file_info = {
+        // A new file has a single branch, pointing at its only commit
+        let raw_git_hash = repositories.get_history(&deserialized_result.id).unwrap().refs[0].hash.clone();
 
-        let rawGitHash = fileGitHash.revparse_single("HEAD").unwrap();
-
-        FileIDAndGitHash { id: deserialized_result.id, hash: rawGitHash.id().to_string() }
+        FileIDAndGitHash { id: deserialized_result.id, hash: raw_git_hash }
     };
 
 
 
     let filter = get_file(repositories.clone());
-    let obj = getFileInfo;
+    let obj = get_file_info;
 
     let result = test::request()
         .method("POST")
@@ -162,7 +155,7 @@ async fn test_get_file_filter() {
 
     assert_eq!(result.status(), 200);
 
-    clear_files_directory("test_get_file_filter", obj.id);
+    clear_files_directory(&config.files_dir, "test_get_file_filter", obj.id);
 }
 
 // TODO: Ask Sam about the following:
@@ -177,7 +170,8 @@ async fn test_save_file_filter() {
     let _ = pretty_env_logger::try_init();
 
     // Create a file
-    let repositories = repos();
+    let config = TempConfig::new();
+    let repositories = repos(&config);
     let filter = routes::create_file(repositories.clone());
 
     let obj = handlers::NameAndOptionalContent{ name: "TestFile".to_string(), content: None };
@@ -194,26 +188,22 @@ async fn test_save_file_filter() {
     assert_eq!(result.status(), 200);
     assert_eq!(deserialized_result.name, "TestFile");
 
-    let getFileInfo = {
-        let fileGitHash = repositories
-            .lock()
-            .unwrap();
-
-        let fileGitHash = fileGitHash.deref().get(&deserialized_result.id).unwrap();
-
-        let rawGitHash = fileGitHash.revparse_single("HEAD").unwrap();
+    let _get_file_info = {
+        // A new file has a single branch, pointing at its only commit
+        let raw_git_hash = repositories.get_history(&deserialized_result.id).unwrap().refs[0].hash.clone();
 
-        FileIDAndGitHash { id: deserialized_result.id, hash: rawGitHash.id().to_string() }
+        FileIDAndGitHash { id: deserialized_result.id, hash: raw_git_hash }
     };
 
     // Save a new file as a child of the file we just created
-    let filter = routes::save_file(repositories.clone());
-    let childFileInfo = handlers::FileAndHashAndBranchName {
+    let _filter = routes::save_file(repositories.clone());
+    let _child_file_info = handlers::FileAndHashAndBranchName {
         name : "TestFileChild".to_string(),
         id : Uuid::new_v4(),
         content : "".to_string(),
         parent : "".to_string(),
-        branch : "".to_string()
+        branch : "".to_string(),
+        force : false
     };
 
     // clear_files_directory("test_save_file_filter");
@@ -224,7 +214,8 @@ async fn test_delete_file_filter() {
 
     let _ = pretty_env_logger::try_init();
 
-    let repositories = repos();
+    let config = TempConfig::new();
+    let repositories = repos(&config);
     let filter = routes::create_file(repositories.clone());
 
     let obj = handlers::NameAndOptionalContent{ name: "TestFile".to_string(), content: None };
@@ -241,7 +232,7 @@ async fn test_delete_file_filter() {
     assert_eq!(result.status(), 200);
     assert_eq!(deserialized_result.name, "TestFile");
 
-    let filter  = routes::delete_file(repositories.clone());
+    let filter  = routes::delete_file(repositories.clone(), config.arc());
 
     let obj = IdOnly { id: deserialized_result.id };
 
@@ -261,8 +252,9 @@ async fn delete_nonexistent_repo() {
 
     let _ = pretty_env_logger::try_init();
 
-    let repositories = repos();
-    let filter = routes::delete_file(repositories.clone());
+    let config = TempConfig::new();
+    let repositories = repos(&config);
+    let filter = routes::delete_file(repositories.clone(), config.arc());
 
     let obj = IdOnly { id : Uuid::new_v4()};
 
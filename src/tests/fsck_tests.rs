@@ -0,0 +1,126 @@
+use std::fs;
+use std::path::Path;
+
+use git2::{Repository, Signature};
+use uuid::Uuid;
+
+use crate::files;
+use crate::fsck::{self, Problem, Severity};
+use crate::tests::TempConfig;
+
+// Commits every given file in a new repository, bypassing the server's one-file rule
+fn commit_files(path: &Path, names: &[&str]) {
+    let repo = Repository::init(path).unwrap();
+    for name in names {
+        fs::write(path.join(name), "content").unwrap();
+    }
+    let mut index = repo.index().unwrap();
+    for name in names {
+        index.add_path(Path::new(name)).unwrap();
+    }
+    let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+    let sig = Signature::now("test", "test@example.com").unwrap();
+    repo.commit(Some("HEAD"), &sig, &sig, "", &tree, &[]).unwrap();
+}
+
+fn problems_for(report: &fsck::Report, id: Uuid) -> Vec<Problem> {
+    report.issues.iter()
+        .filter(|issue| issue.id == Some(id))
+        .map(|issue| issue.problem.clone())
+        .collect()
+}
+
+#[test]
+fn healthy_files_have_no_issues() {
+    let config = TempConfig::new();
+    let repos = files::repos(&config);
+    files::create_file("doc.tex".to_string(), Some("hello".to_string()), None, &repos, &config).unwrap();
+
+    let report = fsck::check(&config);
+
+    assert_eq!(report.repositories, 1);
+    assert!(report.issues.is_empty());
+}
+
+#[test]
+fn detects_broken_repositories() {
+    let config = TempConfig::new();
+    let empty = Uuid::new_v4();
+    Repository::init(config.files_dir.join(empty.to_string())).unwrap();
+    let not_a_repo = Uuid::new_v4();
+    fs::create_dir(config.files_dir.join(not_a_repo.to_string())).unwrap();
+    let no_file = Uuid::new_v4();
+    commit_files(&config.files_dir.join(no_file.to_string()), &[]);
+    let multiple = Uuid::new_v4();
+    commit_files(&config.files_dir.join(multiple.to_string()), &["a.md", "b.md"]);
+    fs::create_dir(config.files_dir.join("notes")).unwrap();
+    fs::create_dir_all(config.previews_dir.join(Uuid::new_v4().to_string())).unwrap();
+
+    let report = fsck::check(&config);
+
+    assert_eq!(report.repositories, 4);
+    assert_eq!(problems_for(&report, empty), vec![Problem::NoCommits]);
+    assert_eq!(problems_for(&report, not_a_repo), vec![Problem::NotARepository]);
+    assert!(matches!(problems_for(&report, no_file)[..], [Problem::NoTrackedFile { .. }]));
+    assert!(matches!(problems_for(&report, multiple)[..], [Problem::MultipleTrackedFiles { count: 2, .. }]));
+    assert!(report.issues.iter().any(|issue| issue.problem == Problem::UnknownEntry));
+    assert!(report.issues.iter().any(|issue| issue.problem == Problem::OrphanedPreviews));
+    assert_eq!(report.broken().len(), 3);
+    assert!(!report.broken().contains_key(&multiple));
+}
+
+#[test]
+fn detached_head_is_only_informational() {
+    let config = TempConfig::new();
+    let id = Uuid::new_v4();
+    let path = config.files_dir.join(id.to_string());
+    commit_files(&path, &["doc.md"]);
+    let repo = Repository::open(&path).unwrap();
+    repo.set_head_detached(repo.head().unwrap().target().unwrap()).unwrap();
+
+    let report = fsck::check(&config);
+
+    assert_eq!(problems_for(&report, id), vec![Problem::DetachedHead]);
+    assert_eq!(report.issues[0].severity, Severity::Info);
+}
+
+#[test]
+fn repair_quarantines_broken_repositories() {
+    let config = TempConfig::new();
+    let healthy = Uuid::new_v4();
+    commit_files(&config.files_dir.join(healthy.to_string()), &["doc.md"]);
+    let broken = Uuid::new_v4();
+    Repository::init(config.files_dir.join(broken.to_string())).unwrap();
+    fs::create_dir_all(config.previews_dir.join(broken.to_string())).unwrap();
+    let orphan = config.previews_dir.join(Uuid::new_v4().to_string());
+    fs::create_dir_all(&orphan).unwrap();
+
+    let repair = fsck::repair(&fsck::check(&config), &config);
+    let after = fsck::check(&config);
+
+    assert_eq!(repair.quarantined.len(), 1);
+    assert!(repair.failed.is_empty());
+    assert!(config.quarantine_dir.join(broken.to_string()).join(".git").exists());
+    assert!(!config.files_dir.join(broken.to_string()).exists());
+    assert!(!config.previews_dir.join(broken.to_string()).exists());
+    assert!(!orphan.exists());
+    assert!(config.files_dir.join(healthy.to_string()).exists());
+    assert!(after.issues.is_empty());
+}
+
+#[test]
+fn broken_repositories_are_listed_as_errors_but_not_served() {
+    let config = TempConfig::new();
+    let repos = files::repos(&config);
+    let healthy = files::create_file("doc.md".to_string(), None, None, &repos, &config).unwrap().id;
+    let broken = Uuid::new_v4();
+    Repository::init(config.files_dir.join(broken.to_string())).unwrap();
+
+    let repos = crate::git_repositories(&config);
+    let list = files::list_files(&repos);
+
+    assert_eq!(list.files.iter().map(|file| file.id).collect::<Vec<_>>(), vec![healthy]);
+    let errors = list.errors.iter().map(|error| (error.id, error.reason.as_str())).collect::<Vec<_>>();
+    assert_eq!(errors, vec![(broken, "repository has no commits")]);
+    assert!(files::find_repo(&broken, &repos).is_err());
+}
@@ -0,0 +1,68 @@
+use std::fs;
+use std::sync::Arc;
+
+use crate::{files, health, routes};
+use crate::storage::GitStorage;
+use crate::tests::TempConfig;
+
+#[tokio::test]
+async fn health_route_is_always_ok() {
+    let response = warp::test::request().method("GET").path("/health").reply(&routes::health()).await;
+
+    assert_eq!(response.status(), 200);
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(body["status"], "ok");
+}
+
+#[tokio::test]
+async fn ready_route_reports_every_check() {
+    let config = TempConfig::new();
+    let filter = routes::ready(Arc::new(GitStorage::new(files::repos(&config), config.arc())), config.arc());
+
+    let response = warp::test::request().method("GET").path("/ready").reply(&filter).await;
+
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(body["files_dir"]["ok"], true);
+    assert_eq!(body["previews_dir"]["ok"], true);
+    assert_eq!(body["repositories"]["ok"], true);
+    assert_eq!(body["repositories"]["count"], 0);
+    for tool in health::TOOLS {
+        assert!(body["tools"][tool]["ok"].is_boolean());
+    }
+    // Whether the compilers are installed depends on the machine running the tests
+    let expected = if body["ready"] == true { 200 } else { 503 };
+    assert_eq!(response.status(), expected);
+}
+
+#[tokio::test]
+async fn unwritable_directories_and_held_lock_are_not_ready() {
+    let config = TempConfig::new();
+    let dir = config.files_dir.parent().unwrap();
+    fs::create_dir_all(dir).unwrap();
+    // A regular file where the files directory should be, so it can't be created
+    fs::write(&config.files_dir, "").unwrap();
+    let repos = files::repos(&config);
+
+    // Hold the lock from another thread, as a long request would
+    let (locked_tx, locked_rx) = std::sync::mpsc::channel();
+    let (release_tx, release_rx) = std::sync::mpsc::channel::<()>();
+    let holder = {
+        let repos = repos.clone();
+        std::thread::spawn(move || {
+            let _guard = repos.lock().unwrap();
+            locked_tx.send(()).unwrap();
+            let _ = release_rx.recv();
+        })
+    };
+    locked_rx.recv().unwrap();
+    let readiness = health::readiness(Arc::new(GitStorage::new(repos.clone(), config.arc())), &config).await;
+    release_tx.send(()).unwrap();
+    holder.join().unwrap();
+
+    assert!(!readiness.ready);
+    assert!(!readiness.files_dir.ok);
+    assert!(readiness.files_dir.error.is_some());
+    assert!(readiness.previews_dir.ok);
+    assert!(!readiness.repositories.ok);
+    assert!(readiness.repositories.count.is_none());
+}
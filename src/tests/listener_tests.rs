@@ -0,0 +1,127 @@
+use std::fs;
+use std::path::PathBuf;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixStream;
+use uuid::Uuid;
+use warp::Filter;
+
+use crate::config::{Config, TlsConfig};
+use crate::listener::Listener;
+use crate::server;
+use crate::shutdown::Shutdown;
+
+fn temp_socket_config() -> Config {
+    let dir = std::env::temp_dir().join(format!("remote-text-socket-{}", Uuid::new_v4()));
+    fs::create_dir_all(&dir).unwrap();
+    Config { unix_socket: Some(dir.join("server.sock")), ..Config::default() }
+}
+
+fn socket_path(config: &Config) -> &PathBuf {
+    config.unix_socket.as_ref().unwrap()
+}
+
+fn remove_socket_dir(config: &Config) {
+    fs::remove_dir_all(socket_path(config).parent().unwrap()).unwrap();
+}
+
+#[tokio::test]
+async fn socket_file_is_removed_with_listener() {
+    let config = temp_socket_config();
+
+    let listener = Listener::bind(&config).await.unwrap();
+    assert_eq!(listener.url("http"), format!("http+unix:{}", socket_path(&config).display()));
+    assert!(socket_path(&config).exists());
+
+    drop(listener);
+    assert!(!socket_path(&config).exists());
+    remove_socket_dir(&config);
+}
+
+#[tokio::test]
+async fn stale_socket_is_replaced() {
+    let config = temp_socket_config();
+    // A socket nobody is listening on, as left behind by a crash
+    drop(std::os::unix::net::UnixListener::bind(socket_path(&config)).unwrap());
+
+    let listener = Listener::bind(&config).await;
+    assert!(listener.is_ok());
+
+    drop(listener);
+    remove_socket_dir(&config);
+}
+
+#[tokio::test]
+async fn live_socket_and_regular_files_are_not_replaced() {
+    let config = temp_socket_config();
+    let listener = Listener::bind(&config).await.unwrap();
+    assert!(Listener::bind(&config).await.is_err());
+
+    let other = temp_socket_config();
+    fs::write(socket_path(&other), "not a socket").unwrap();
+    assert!(Listener::bind(&other).await.is_err());
+    assert!(socket_path(&other).exists());
+
+    remove_socket_dir(&other);
+    drop(listener);
+    remove_socket_dir(&config);
+}
+
+#[tokio::test]
+async fn serves_requests_over_unix_socket() {
+    let config = temp_socket_config();
+    let shutdown = Shutdown::new();
+    let routes = warp::path("ping").map(|| "pong");
+    let server = tokio::spawn({
+        let config = config.clone();
+        let shutdown = shutdown.clone();
+        async move { server::run(routes, &config, shutdown).await }
+    });
+
+    let mut stream = loop {
+        match UnixStream::connect(socket_path(&config)).await {
+            Ok(stream) => break stream,
+            Err(_) => tokio::task::yield_now().await
+        }
+    };
+    stream.write_all(b"GET /ping HTTP/1.0\r\n\r\n").await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+
+    assert!(response.starts_with("HTTP/1.0 200"));
+    assert!(response.ends_with("pong"));
+
+    shutdown.trigger();
+    server.await.unwrap().unwrap();
+    assert!(!socket_path(&config).exists());
+    remove_socket_dir(&config);
+}
+
+#[tokio::test]
+async fn socket_file_is_removed_when_serving_tls() {
+    let dir = std::env::temp_dir().join(format!("remote-text-socket-{}", Uuid::new_v4()));
+    fs::create_dir_all(&dir).unwrap();
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    fs::write(dir.join("cert.pem"), cert.serialize_pem().unwrap()).unwrap();
+    fs::write(dir.join("key.pem"), cert.serialize_private_key_pem()).unwrap();
+    let config = Config {
+        unix_socket: Some(dir.join("server.sock")),
+        tls: Some(TlsConfig { cert_path: dir.join("cert.pem"), key_path: dir.join("key.pem") }),
+        ..Config::default()
+    };
+    let shutdown = Shutdown::new();
+    let server = tokio::spawn({
+        let config = config.clone();
+        let shutdown = shutdown.clone();
+        async move { server::run(warp::path("ping").map(|| "pong"), &config, shutdown).await }
+    });
+    while !socket_path(&config).exists() {
+        tokio::task::yield_now().await;
+    }
+
+    shutdown.trigger();
+    server.await.unwrap().unwrap();
+
+    assert!(!socket_path(&config).exists());
+    remove_socket_dir(&config);
+}
@@ -0,0 +1,125 @@
+use std::sync::Arc;
+
+use chrono::{DateTime, Duration, Utc};
+use serde_json::Value;
+use uuid::Uuid;
+use warp::Filter;
+
+use crate::api::{FileList, FileSummary};
+use crate::config::Config;
+use crate::error::{handle_rejection, ErrorCode};
+use crate::listing::{self, Direction, ListQuery, SortBy};
+use crate::memory::MemoryStorage;
+use crate::routes;
+use crate::storage::{Storage, Store};
+
+fn day(n: i64) -> DateTime<Utc> {
+    "2024-01-01T00:00:00Z".parse::<DateTime<Utc>>().unwrap() + Duration::days(n)
+}
+
+// Created on the days in the order given, and edited in the reverse order
+fn list(names: &[&str]) -> FileList {
+    let files = names.iter().enumerate()
+        .map(|(i, name)| FileSummary { name: name.to_string(), id: Uuid::new_v4(), created_time: day(i as i64), edited_time: day(100 - i as i64) })
+        .collect::<Vec<_>>();
+    FileList { total: files.len(), files, errors: vec![], next: None }
+}
+
+fn names(list: &FileList) -> Vec<&str> {
+    list.files.iter().map(|file| file.name.as_str()).collect()
+}
+
+#[test]
+fn sorts_and_filters() {
+    let all = list(&["b.md", "A.tex", "c.TEX", "notes.md", "Draft.txt"]);
+    let page = |query: ListQuery| listing::page(all.clone(), &query).unwrap();
+
+    assert_eq!(names(&page(ListQuery::default())), vec!["A.tex", "b.md", "c.TEX", "Draft.txt", "notes.md"]);
+    let by_edit = page(ListQuery { sort: Some(SortBy::EditedTime), direction: Some(Direction::Desc), ..ListQuery::default() });
+    assert_eq!(names(&by_edit), vec!["b.md", "A.tex", "c.TEX", "notes.md", "Draft.txt"]);
+    let by_creation = page(ListQuery { sort: Some(SortBy::CreatedTime), direction: Some(Direction::Desc), ..ListQuery::default() });
+    assert_eq!(names(&by_creation), vec!["Draft.txt", "notes.md", "c.TEX", "A.tex", "b.md"]);
+
+    let tex = page(ListQuery { extension: Some(".tex".to_string()), ..ListQuery::default() });
+    assert_eq!((names(&tex), tex.total), (vec!["A.tex", "c.TEX"], 2));
+    assert_eq!(names(&page(ListQuery { extension: Some("MD".to_string()), ..ListQuery::default() })), vec!["b.md", "notes.md"]);
+    assert_eq!(names(&page(ListQuery { name_prefix: Some("d".to_string()), ..ListQuery::default() })), vec!["Draft.txt"]);
+    assert_eq!(names(&page(ListQuery { name_contains: Some("T".to_string()), ..ListQuery::default() })), vec!["A.tex", "c.TEX", "Draft.txt", "notes.md"]);
+    // Edited on days 100 to 96; both ends are exclusive
+    let edited = page(ListQuery { edited_after: Some(day(96)), edited_before: Some(day(99)), ..ListQuery::default() });
+    assert_eq!(names(&edited), vec!["c.TEX", "notes.md"]);
+}
+
+#[test]
+fn pages_cover_every_match_once() {
+    let mut all = list(&["one.md", "two.md", "three.md", "four.md", "five.md"]);
+    let mut query = ListQuery { sort: Some(SortBy::CreatedTime), limit: Some(2), ..ListQuery::default() };
+    let mut pages = vec![];
+    loop {
+        let page = listing::page(all.clone(), &query).unwrap();
+        assert_eq!(page.total, 5);
+        pages.push(names(&page).join(" "));
+        let Some(next) = page.next else { break };
+        query.cursor = Some(next);
+    }
+    assert_eq!(pages, vec!["one.md two.md", "three.md four.md", "five.md"]);
+
+    // A file deleted between pages doesn't shift the next page
+    query.cursor = None;
+    let first = listing::page(all.clone(), &query).unwrap();
+    all.files.retain(|file| file.name != "three.md");
+    let second = listing::page(all, &ListQuery { cursor: first.next, ..query }).unwrap();
+    assert_eq!(names(&second), vec!["four.md", "five.md"]);
+    assert_eq!(second.next, None);
+}
+
+#[test]
+fn cursors_only_work_with_their_own_sort() {
+    let all = list(&["one.md", "two.md", "three.md"]);
+    let first = listing::page(all.clone(), &ListQuery { limit: Some(1), ..ListQuery::default() }).unwrap();
+    let next = first.next.unwrap();
+
+    let reversed = ListQuery { direction: Some(Direction::Desc), cursor: Some(next.clone()), ..ListQuery::default() };
+    let garbage = ListQuery { cursor: Some("not-a-cursor".to_string()), ..ListQuery::default() };
+    let empty = ListQuery { limit: Some(0), ..ListQuery::default() };
+
+    assert_eq!(listing::page(all.clone(), &reversed).err().map(|err| err.code), Some(ErrorCode::InvalidQuery));
+    assert_eq!(listing::page(all.clone(), &garbage).err().map(|err| err.code), Some(ErrorCode::InvalidQuery));
+    assert_eq!(listing::page(all, &empty).err().map(|err| err.code), Some(ErrorCode::InvalidQuery));
+}
+
+#[tokio::test]
+async fn query_string_through_the_api() {
+    let memory = Arc::new(MemoryStorage::new());
+    for name in ["b.md", "a.md", "c.tex"] {
+        memory.create_file(name.to_string(), None, None).unwrap();
+    }
+    let store: Store = memory;
+    let routes = routes::get_routes(store, Arc::new(Config::default())).recover(handle_rejection);
+    let call = |method: &'static str, path: String| {
+        let routes = routes.clone();
+        async move {
+            let response = warp::test::request().method(method).path(&path).reply(&routes).await;
+            let header = |name: &str| response.headers().get(name).map(|value| value.to_str().unwrap().to_string());
+            let headers = (header(listing::TOTAL_HEADER), header(listing::NEXT_HEADER));
+            (response.status().as_u16(), serde_json::from_slice::<Value>(response.body()).unwrap(), headers)
+        }
+    };
+
+    // The original route still answers with a bare array, with the paging in headers
+    let (status, first, (total, next)) = call("POST", "/listFiles?extension=md&limit=1".to_string()).await;
+    assert_eq!(status, 200);
+    assert_eq!((first[0]["name"].as_str(), total.as_deref()), (Some("a.md"), Some("2")));
+    let next = next.unwrap();
+    let (_, second, _) = call("GET", format!("/v2/files?extension=md&limit=1&cursor={next}")).await;
+    assert_eq!((second["files"][0]["name"].as_str(), &second["next"]), (Some("b.md"), &Value::Null));
+
+    let (status, everything, (total, next)) = call("POST", "/listFiles".to_string()).await;
+    assert_eq!((status, everything.as_array().map(Vec::len), total.as_deref(), next), (200, Some(3), Some("3"), None));
+    let (status, page, _) = call("GET", "/v2/files?limit=2".to_string()).await;
+    assert_eq!((status, page["total"].as_u64(), page["errors"].as_array().map(Vec::len)), (200, Some(3), Some(0)));
+    let (status, error, _) = call("GET", "/v2/files?sort=size".to_string()).await;
+    assert_eq!((status, error["code"].as_str()), (400, Some("INVALID_QUERY")));
+    let (status, error, _) = call("POST", "/listFiles?edited_after=yesterday".to_string()).await;
+    assert_eq!((status, error["code"].as_str()), (400, Some("INVALID_QUERY")));
+}
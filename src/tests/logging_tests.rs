@@ -0,0 +1,67 @@
+use log::{Level, Record};
+use warp::http::{HeaderMap, HeaderValue};
+
+use crate::logging::{self, REQUEST_ID_HEADER};
+
+fn headers_with_request_id(id: &str) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(REQUEST_ID_HEADER, HeaderValue::from_str(id).unwrap());
+    headers
+}
+
+#[test]
+fn request_id_from_proxy_is_reused() {
+    assert_eq!(logging::request_id_for(&headers_with_request_id("a1b2-c3_d4.e5")), "a1b2-c3_d4.e5");
+}
+
+#[test]
+fn unusable_request_ids_are_replaced() {
+    for id in ["", "has spaces", "quote\"d", &"x".repeat(65)] {
+        let generated = logging::request_id_for(&headers_with_request_id(id));
+        assert_eq!(generated.len(), 16);
+        assert!(generated.chars().all(|c| c.is_ascii_hexdigit()));
+    }
+    assert_ne!(logging::request_id_for(&HeaderMap::new()), logging::request_id_for(&HeaderMap::new()));
+}
+
+#[tokio::test]
+async fn json_records_carry_request_and_file_ids() {
+    let record = logging::with_request_id("abc123".to_string(), async {
+        logging::json_record(&Record::builder()
+            .args(format_args!("[7d444840-9dc0-11d1-b245-5ffdce74fad2] Made initial commit"))
+            .level(Level::Info)
+            .target("remote_text_server::create_file")
+            .key_values(&[("status", 200u16)])
+            .build())
+    }).await;
+
+    assert_eq!(record["request_id"], "abc123");
+    assert_eq!(record["file_id"], "7d444840-9dc0-11d1-b245-5ffdce74fad2");
+    assert_eq!(record["message"], "Made initial commit");
+    assert_eq!(record["level"], "INFO");
+    assert_eq!(record["target"], "remote_text_server::create_file");
+    assert_eq!(record["status"], 200);
+}
+
+#[test]
+fn json_records_outside_requests_have_no_request_id() {
+    let record = logging::json_record(&Record::builder()
+        .args(format_args!("Running server"))
+        .level(Level::Info)
+        .build());
+
+    assert!(record.get("request_id").is_none());
+    assert!(record.get("file_id").is_none());
+    assert_eq!(record["message"], "Running server");
+}
+
+#[tokio::test]
+async fn blocking_work_keeps_the_request_id() {
+    let (inside, thread) = logging::with_request_id("abc123".to_string(), async {
+        logging::spawn_blocking(|| (logging::request_id(), std::thread::current().id())).await
+    }).await;
+
+    assert_eq!(inside.as_deref(), Some("abc123"));
+    assert_ne!(thread, std::thread::current().id());
+    assert_eq!(logging::spawn_blocking(logging::request_id).await, None);
+}
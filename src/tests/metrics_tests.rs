@@ -0,0 +1,71 @@
+use std::fs;
+use std::sync::Arc;
+use std::time::Duration;
+
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::metrics::{self, metrics, CompileOutcome};
+use crate::memory::MemoryStorage;
+use crate::routes;
+
+#[test]
+fn requests_are_labelled_by_route() {
+    assert_eq!(metrics::route_label("/api/listFiles"), "listFiles");
+    assert_eq!(metrics::route_label("/api/previewFile/"), "previewFile");
+    assert_eq!(metrics::route_label("/metrics"), "metrics");
+    assert_eq!(metrics::route_label("/api/notARoute"), "other");
+    assert_eq!(metrics::route_label("/listFiles"), "other");
+    assert_eq!(metrics::route_label("/api/metrics"), "other");
+}
+
+#[test]
+fn v2_and_openapi_requests_are_labelled_by_route() {
+    let id = Uuid::new_v4();
+    let hash = "0123456789abcdef0123456789abcdef01234567";
+
+    assert_eq!(metrics::route_label("/api/v2/files"), "v2/files");
+    assert_eq!(metrics::route_label(&format!("/api/v2/files/{id}")), "v2/files/{id}");
+    assert_eq!(metrics::route_label(&format!("/api/v2/files/{id}/history")), "v2/files/{id}/history");
+    assert_eq!(metrics::route_label(&format!("/api/v2/files/{id}/commits/{hash}")), "v2/files/{id}/commits/{hash}");
+    assert_eq!(metrics::route_label(&format!("/api/v2/files/{id}/commits/{hash}/preview/")), "v2/files/{id}/commits/{hash}/preview");
+    assert_eq!(metrics::route_label(&format!("/api/v2/files/{id}/branches/main")), "v2/files/{id}/branches/{branch}");
+    assert_eq!(metrics::route_label("/api/openapi.json"), "openapi.json");
+    assert_eq!(metrics::route_label("/api/v2/files//history"), "other");
+    assert_eq!(metrics::route_label(&format!("/api/v2/files/{id}/unknown")), "other");
+}
+
+#[test]
+fn render_reports_counters_and_disk_usage() {
+    let dir = std::env::temp_dir().join(format!("remote-text-metrics-{}", Uuid::new_v4()));
+    let config = Config { files_dir: dir.join("files"), previews_dir: dir.join("previews"), ..Config::default() };
+    fs::create_dir_all(config.files_dir.join("nested")).unwrap();
+    fs::write(config.files_dir.join("nested").join("a"), [0u8; 100]).unwrap();
+    fs::write(config.files_dir.join("b"), [0u8; 23]).unwrap();
+
+    metrics().record_compile("pandoc", CompileOutcome::Failure, Duration::from_millis(10));
+    metrics().record_preview_cache(true);
+    metrics().record_request("/api/getFile", 404, Duration::from_millis(1));
+    let output = metrics().render(7, 3, &config);
+    fs::remove_dir_all(dir).unwrap();
+
+    assert!(output.contains("remote_text_repositories 7"));
+    assert!(output.contains("remote_text_open_repositories 3"));
+    assert!(output.contains("remote_text_disk_usage_bytes{directory=\"files\"} 123"));
+    assert!(output.contains("remote_text_disk_usage_bytes{directory=\"previews\"} 0"));
+    assert!(output.contains("remote_text_compiles_total{compiler=\"pandoc\",outcome=\"failure\"}"));
+    assert!(output.contains("remote_text_preview_cache_total{result=\"hit\"}"));
+    assert!(output.contains("remote_text_http_requests_total{route=\"getFile\",status=\"404\"}"));
+}
+
+#[tokio::test]
+async fn metrics_route_serves_text_format() {
+    let config = Arc::new(Config::default());
+    let filter = routes::metrics(Arc::new(MemoryStorage::new()), config);
+
+    let response = warp::test::request().method("GET").path("/metrics").reply(&filter).await;
+
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["content-type"], "text/plain; version=0.0.4");
+    assert!(String::from_utf8_lossy(response.body()).contains("# TYPE remote_text_repositories gauge"));
+}
@@ -1,2 +1,123 @@
 #[cfg(test)]
-mod filter_tests;
\ No newline at end of file
+mod filter_tests;
+#[cfg(test)]
+mod config_tests;
+#[cfg(test)]
+mod cli_tests;
+#[cfg(test)]
+mod tls_tests;
+#[cfg(test)]
+mod shutdown_tests;
+#[cfg(all(test, unix))]
+mod listener_tests;
+#[cfg(test)]
+mod admin_tests;
+#[cfg(test)]
+mod fsck_tests;
+#[cfg(test)]
+mod logging_tests;
+#[cfg(test)]
+mod metrics_tests;
+#[cfg(test)]
+mod health_tests;
+#[cfg(test)]
+mod files_tests;
+#[cfg(test)]
+mod storage_tests;
+#[cfg(test)]
+mod server_tests;
+#[cfg(test)]
+mod error_tests;
+#[cfg(test)]
+mod v2_tests;
+#[cfg(test)]
+mod openapi_tests;
+#[cfg(test)]
+mod listing_tests;
+#[cfg(test)]
+mod preview_tests;
+
+#[cfg(test)]
+use std::ops::Deref;
+#[cfg(test)]
+use std::path::PathBuf;
+#[cfg(test)]
+use std::sync::Arc;
+
+#[cfg(test)]
+use serde_json::Value;
+#[cfg(test)]
+use warp::Filter;
+
+#[cfg(test)]
+use crate::config::Config;
+#[cfg(test)]
+use crate::storage::Store;
+
+/// A configuration whose files, previews and quarantine directories are in a new temporary
+///   directory, which is removed when this is dropped, even if the test panics
+#[cfg(test)]
+pub(crate) struct TempConfig {
+    root: PathBuf,
+    config: Config,
+}
+
+#[cfg(test)]
+impl TempConfig {
+    pub(crate) fn new() -> TempConfig {
+        return TempConfig::with(|_| {});
+    }
+
+    /// With some settings changed, e.g. `TempConfig::with(|config| config.storage = StorageKind::Bare)`
+    pub(crate) fn with(change: impl FnOnce(&mut Config)) -> TempConfig {
+        let root = std::env::temp_dir().join(format!("remote-text-test-{}", uuid::Uuid::new_v4()));
+        let mut config = Config {
+            files_dir: root.join("files"),
+            previews_dir: root.join("previews"),
+            quarantine_dir: root.join("quarantine"),
+            ..Config::default()
+        };
+        change(&mut config);
+        return TempConfig { root, config };
+    }
+
+    /// A copy of the configuration, for routes and storage that share it
+    pub(crate) fn arc(&self) -> Arc<Config> {
+        return Arc::new(self.config.clone());
+    }
+}
+
+#[cfg(test)]
+impl Deref for TempConfig {
+    type Target = Config;
+
+    fn deref(&self) -> &Config {
+        return &self.config;
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempConfig {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.root);
+    }
+}
+
+/// Sends `request` to every API route, with rejections answered as the server answers them
+#[cfg(test)]
+pub(crate) async fn reply(store: &Store, config: &Config, request: warp::test::RequestBuilder) -> warp::http::Response<warp::hyper::body::Bytes> {
+    let routes = crate::routes::get_routes(store.clone(), Arc::new(config.clone())).recover(crate::error::handle_rejection);
+    return request.reply(&routes).await;
+}
+
+/// Sends a request, with `body` as JSON, and returns the status and the JSON response (`null` if
+///   the response isn't JSON)
+#[cfg(test)]
+pub(crate) async fn call(store: &Store, config: &Config, method: &str, path: &str, body: Option<Value>) -> (u16, Value) {
+    let mut request = warp::test::request().method(method).path(path);
+    if let Some(body) = body {
+        request = request.json(&body);
+    }
+    let response = reply(store, config, request).await;
+    return (response.status().as_u16(), serde_json::from_slice(response.body()).unwrap_or(Value::Null));
+}
@@ -0,0 +1,209 @@
+use std::sync::Arc;
+
+use serde_json::{json, Map, Value};
+use uuid::Uuid;
+use warp::Filter;
+
+use crate::error::handle_rejection;
+use crate::memory::MemoryStorage;
+use crate::openapi;
+use crate::routes;
+use crate::storage::{Storage, Store};
+use crate::tests::TempConfig;
+
+fn resolve<'s>(spec: &'s Value, schema: &'s Value) -> &'s Value {
+    match schema["$ref"].as_str().and_then(|path| path.strip_prefix("#")) {
+        Some(pointer) => resolve(spec, spec.pointer(pointer).unwrap_or_else(|| panic!("Unresolved $ref {pointer}"))),
+        None => schema,
+    }
+}
+
+// Checks `value` against the subset of JSON Schema that utoipa generates. Objects are treated as
+//   closed, so a field the handlers send but the spec doesn't list is an error too
+fn validate(spec: &Value, schema: &Value, value: &Value, at: &str) -> Result<(), String> {
+    let schema = resolve(spec, schema);
+    if let Some(options) = schema["oneOf"].as_array().or(schema["anyOf"].as_array()) {
+        if options.iter().any(|option| validate(spec, option, value, at).is_ok()) {
+            return Ok(());
+        }
+        return Err(format!("{at}: {value} matches none of the documented schemas"));
+    }
+    if let Some(all) = schema["allOf"].as_array() {
+        for part in all {
+            validate(spec, part, value, at)?;
+        }
+    }
+    if let Some(allowed) = schema["enum"].as_array() {
+        if !allowed.contains(value) {
+            return Err(format!("{at}: {value} is not one of {allowed:?}"));
+        }
+    }
+    let types = match &schema["type"] {
+        Value::String(kind) => vec![kind.as_str()],
+        Value::Array(kinds) => kinds.iter().filter_map(Value::as_str).collect(),
+        _ => return Ok(()),
+    };
+    let matches = |kind: &str| match kind {
+        "null" => value.is_null(),
+        "boolean" => value.is_boolean(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "number" => value.is_number(),
+        "string" => value.is_string(),
+        "array" => value.is_array(),
+        "object" => value.is_object(),
+        _ => false,
+    };
+    if !types.iter().any(|kind| matches(kind)) {
+        return Err(format!("{at}: {value} is not of type {types:?}"));
+    }
+    if let Some(items) = value.as_array() {
+        for (i, item) in items.iter().enumerate() {
+            validate(spec, &schema["items"], item, &format!("{at}[{i}]"))?;
+        }
+    }
+    if let Some(fields) = value.as_object() {
+        for required in schema["required"].as_array().into_iter().flatten().filter_map(Value::as_str) {
+            if !fields.contains_key(required) {
+                return Err(format!("{at}: missing required field {required}"));
+            }
+        }
+        for (name, field) in fields {
+            let field_schema = match (schema["properties"].get(name), schema.get("additionalProperties")) {
+                (Some(field_schema), _) => field_schema,
+                (None, Some(additional)) if additional.is_object() => additional,
+                // A free-form object
+                (None, None) if schema.get("properties").is_none() => continue,
+                _ => return Err(format!("{at}: field {name} is not documented")),
+            };
+            validate(spec, field_schema, field, &format!("{at}.{name}"))?;
+        }
+    }
+    return Ok(());
+}
+
+// A request body with only the fields the spec says are required, filled in from `fixtures`. If
+//   the handler needs more than that, the call fails and so does the test
+fn request_body(spec: &Value, operation: &Value, fixtures: &Map<String, Value>) -> Option<Value> {
+    let schema = resolve(spec, operation["requestBody"]["content"].get("application/json")?.get("schema")?);
+    let body = schema["required"].as_array().into_iter().flatten()
+        .filter_map(Value::as_str)
+        .map(|field| (field.to_string(), fixtures.get(field).cloned().unwrap_or_else(|| panic!("No fixture for request field {field}"))))
+        .collect::<Map<_, _>>();
+    return Some(Value::Object(body));
+}
+
+// Sends every operation in the document to the real filters, with no body and placeholder path
+//   parameters. Handlers and body filters may still turn those down, but no route matching the
+//   path and method would answer "No such endpoint" or "Method not allowed"
+#[tokio::test]
+async fn every_documented_route_is_served() {
+    let config = TempConfig::new();
+    let store: Store = Arc::new(MemoryStorage::new());
+    let routes = warp::path("api")
+        .and(routes::get_routes(store.clone(), config.arc()))
+        .or(routes::metrics(store.clone(), config.arc()))
+        .recover(handle_rejection);
+    let spec = serde_json::to_value(openapi::spec()).unwrap();
+
+    let mut unserved = Vec::new();
+    for (path, item) in spec["paths"].as_object().unwrap() {
+        let uri = path.replace("{id}", &Uuid::nil().to_string()).replace("{hash}", "main").replace("{branch}", "main");
+        for method in item.as_object().unwrap().keys() {
+            let response = warp::test::request().method(&method.to_uppercase()).path(&uri).reply(&routes).await;
+            let code = serde_json::from_slice::<Value>(response.body()).ok().and_then(|body| body["code"].as_str().map(str::to_string));
+            if matches!(code.as_deref(), Some("NOT_FOUND" | "METHOD_NOT_ALLOWED")) {
+                unserved.push(format!("{} {path}: {}", method.to_uppercase(), response.status()));
+            }
+        }
+    }
+
+    assert!(unserved.is_empty(), "{unserved:?}");
+}
+
+#[tokio::test]
+async fn every_documented_operation_matches_the_handlers() {
+    let config = TempConfig::new();
+    let memory = Arc::new(MemoryStorage::new());
+    let store: Store = memory.clone();
+    let routes = warp::path("api")
+        .and(routes::get_routes(store.clone(), config.arc()))
+        .or(routes::metrics(store.clone(), config.arc()))
+        .recover(handle_rejection);
+    let spec = serde_json::to_value(openapi::spec()).unwrap();
+    let created = memory.create_file("doc.md".to_string(), Some("hello".to_string()), None).unwrap();
+    let fixtures = json!({
+        "id": created.id,
+        "hash": created.hash,
+        "parent": created.hash,
+        "name": "doc.md",
+        "content": "hello again",
+        "branch": "draft",
+    });
+
+    let mut operations = spec["paths"].as_object().unwrap().iter()
+        .flat_map(|(path, item)| item.as_object().unwrap().iter().map(move |(method, operation)| (path.clone(), method.clone(), operation)))
+        .collect::<Vec<_>>();
+    // Deleting goes last, so the other calls have a file to work on
+    operations.sort_by_key(|(path, method, _)| method == "delete" || path.ends_with("deleteFile"));
+
+    for (path, method, operation) in operations {
+        let mut fixtures = fixtures.as_object().unwrap().clone();
+        if method == "delete" || path.ends_with("deleteFile") {
+            let doomed = memory.create_file("doomed.md".to_string(), None, None).unwrap();
+            fixtures.insert("id".to_string(), json!(doomed.id));
+        }
+        // A save moves the branch on from `parent`, so each one needs a file of its own
+        if path.ends_with("saveFile") || path.contains("/branches/") {
+            let saved = memory.create_file("saved.md".to_string(), None, None).unwrap();
+            fixtures.insert("id".to_string(), json!(saved.id));
+            fixtures.insert("parent".to_string(), json!(saved.hash));
+        }
+        let uri = fixtures.iter().fold(path.clone(), |uri, (name, value)| uri.replace(&format!("{{{name}}}"), value.as_str().unwrap()));
+        let mut request = warp::test::request().method(&method.to_uppercase()).path(&uri);
+        if let Some(body) = request_body(&spec, operation, &fixtures) {
+            request = request.json(&body);
+        }
+        let response = request.reply(&routes).await;
+        let status = response.status();
+        let call = format!("{} {uri} ({status})", method.to_uppercase());
+
+        let Some(documented) = operation["responses"].get(status.as_str()) else {
+            panic!("{call} isn't a documented response: {:?}", response.body());
+        };
+        // Compiling needs latexmk or pandoc, and readiness needs them on PATH too
+        let needs_tools = ["/previewFile", "/getPreview", "/preview", "/ready"].iter().any(|end| path.ends_with(end));
+        assert!(needs_tools || status.is_success(), "{call}: {:?}", response.body());
+        let Some(content) = documented["content"].as_object() else {
+            assert!(response.body().is_empty(), "{call} has a body, but none is documented");
+            continue;
+        };
+        let media_type = response.headers().get("content-type")
+            .map(|value| value.to_str().unwrap().split(';').next().unwrap().trim().to_string());
+        if let Some(media_type) = &media_type {
+            assert!(content.contains_key(media_type), "{call} sent {media_type}, which isn't documented");
+        }
+        if media_type.as_deref() == Some("application/json") {
+            let body: Value = serde_json::from_slice(response.body()).unwrap();
+            if let Err(err) = validate(&spec, &content["application/json"]["schema"], &body, "body") {
+                panic!("{call} doesn't match the spec: {err}");
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn spec_is_served() {
+    let config = TempConfig::new();
+    let store: Store = Arc::new(MemoryStorage::new());
+    let response = warp::test::request()
+        .method("GET")
+        .path("/openapi.json")
+        .reply(&routes::get_routes(store, config.arc()))
+        .await;
+
+    assert_eq!(response.status(), 200);
+    let spec: Value = serde_json::from_slice(response.body()).unwrap();
+    assert!(spec["openapi"].as_str().unwrap().starts_with("3."));
+    assert_eq!(spec["info"]["version"], env!("CARGO_PKG_VERSION"));
+    assert!(spec["paths"]["/api/v2/files/{id}/commits/{hash}"]["get"].is_object());
+}
@@ -0,0 +1,57 @@
+use std::fs;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::handlers::lock_preview;
+use crate::memory::MemoryStorage;
+use crate::storage::{Storage, Store};
+use crate::tests::{call, TempConfig};
+
+#[tokio::test]
+async fn only_one_request_previews_a_commit_at_a_time() {
+    let (id, hash) = (Uuid::new_v4(), "0123456789abcdef0123456789abcdef01234567");
+    let first = lock_preview(id, hash).await;
+
+    let same = tokio::time::timeout(Duration::from_millis(100), lock_preview(id, hash)).await;
+    let other_commit = tokio::time::timeout(Duration::from_millis(100), lock_preview(id, "89abcdef0123456789abcdef0123456789abcdef")).await;
+    let other_file = tokio::time::timeout(Duration::from_millis(100), lock_preview(Uuid::new_v4(), hash)).await;
+    drop(first);
+    let after = tokio::time::timeout(Duration::from_millis(100), lock_preview(id, hash)).await;
+
+    assert!(same.is_err());
+    assert!(other_commit.is_ok());
+    assert!(other_file.is_ok());
+    assert!(after.is_ok());
+}
+
+#[tokio::test]
+async fn a_second_preview_leaves_the_first_ones_output_alone() {
+    let config = TempConfig::new();
+    let memory = Arc::new(MemoryStorage::new());
+    let created = memory.create_file("doc.md".to_string(), Some("hello".to_string()), None).unwrap();
+    let store: Store = memory;
+    // What a request that's still compiling has written so far: no status file yet
+    let compiling = config.previews_dir.join(created.id.to_string()).join(&created.hash);
+    fs::create_dir_all(&compiling).unwrap();
+    fs::write(compiling.join("doc.log"), "compiling").unwrap();
+
+    let first = lock_preview(created.id, &created.hash).await;
+    let second = tokio::spawn({
+        let (store, config) = (store.clone(), config.arc());
+        let body = json!({ "id": created.id, "hash": created.hash });
+        async move { call(&store, &config, "POST", "/previewFile", Some(body)).await }
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let waited = !second.is_finished();
+    let untouched = compiling.join("doc.log").exists();
+    drop(first);
+    let (status, body) = second.await.unwrap();
+
+    assert!(waited);
+    assert!(untouched);
+    // Whether pandoc is installed depends on the machine running the tests
+    assert!(status == 200 || body["code"] == "COMPILER_UNAVAILABLE");
+}
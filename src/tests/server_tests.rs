@@ -0,0 +1,19 @@
+use warp::Filter;
+use warp::hyper::{Body, Request};
+
+use crate::server::call_catching_panics;
+
+#[tokio::test]
+async fn panicking_requests_get_500_and_the_service_keeps_answering() {
+    let panics = warp::path("panic").map(|| -> &'static str { panic!("handler panicked") });
+    let fine = warp::path("fine").map(|| "fine");
+    let mut service = warp::service(panics.or(fine));
+
+    let response = call_catching_panics(&mut service, Request::get("/panic").body(Body::empty()).unwrap()).await;
+    assert_eq!(response.status(), 500);
+
+    let response = call_catching_panics(&mut service, Request::get("/fine").body(Body::empty()).unwrap()).await;
+    assert_eq!(response.status(), 200);
+    let body = warp::hyper::body::to_bytes(response.into_body()).await.unwrap();
+    assert_eq!(&body[..], b"fine");
+}
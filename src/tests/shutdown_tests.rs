@@ -0,0 +1,70 @@
+use std::fs;
+use std::time::Duration;
+
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::files;
+use crate::shutdown::Shutdown;
+
+#[tokio::test]
+async fn triggered_completes_after_trigger() {
+    let shutdown = Shutdown::new();
+    assert!(!shutdown.is_triggered());
+
+    let waiter = tokio::spawn({
+        let shutdown = shutdown.clone();
+        async move { shutdown.triggered().await }
+    });
+    shutdown.trigger();
+
+    tokio::time::timeout(Duration::from_secs(1), waiter).await.unwrap().unwrap();
+    assert!(shutdown.is_triggered());
+}
+
+#[tokio::test]
+async fn in_flight_requests_are_counted() {
+    let shutdown = Shutdown::new();
+
+    let first = shutdown.track();
+    let second = shutdown.track();
+    assert_eq!(shutdown.in_flight(), 2);
+
+    drop(first);
+    assert_eq!(shutdown.in_flight(), 1);
+    drop(second);
+    assert_eq!(shutdown.in_flight(), 0);
+}
+
+#[test]
+fn partial_previews_are_removed() {
+    let config = Config {
+        previews_dir: std::env::temp_dir().join(format!("remote-text-previews-{}", Uuid::new_v4())),
+        ..Config::default()
+    };
+    let file_dir = config.previews_dir.join(Uuid::new_v4().to_string());
+    let finished = file_dir.join("0123456789abcdef0123456789abcdef01234567");
+    let partial = file_dir.join("89abcdef0123456789abcdef0123456789abcdef");
+    fs::create_dir_all(&finished).unwrap();
+    fs::create_dir_all(&partial).unwrap();
+    fs::write(finished.join("doc.log"), "log").unwrap();
+    fs::write(finished.join("doc.status"), "SUCCESS").unwrap();
+    fs::write(partial.join("doc.log"), "log").unwrap();
+
+    let removed = files::remove_partial_previews(&config);
+
+    assert_eq!(removed, 1);
+    assert!(finished.exists());
+    assert!(!partial.exists());
+    fs::remove_dir_all(&config.previews_dir).unwrap();
+}
+
+#[test]
+fn missing_previews_dir_is_not_an_error() {
+    let config = Config {
+        previews_dir: std::env::temp_dir().join(format!("remote-text-previews-{}", Uuid::new_v4())),
+        ..Config::default()
+    };
+
+    assert_eq!(files::remove_partial_previews(&config), 0);
+}
@@ -0,0 +1,142 @@
+use std::fs;
+use std::sync::Arc;
+
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::config::{Config, StorageKind};
+use crate::files;
+use crate::memory::MemoryStorage;
+use crate::storage::{GitStorage, Storage, Store};
+use crate::tests::{call, TempConfig};
+
+// Every storage should behave the same through the API
+async fn create_save_read_delete(store: Store, config: &Config) {
+    let (status, created) = call(&store, config, "POST", "/createFile", Some(json!({ "name": "doc.md", "content": "one" }))).await;
+    assert_eq!(status, 200);
+    let (id, first) = (created["id"].clone(), created["hash"].as_str().unwrap().to_string());
+
+    let (status, saved) = call(&store, config, "POST", "/saveFile", Some(json!({ "id": id, "name": "doc.md", "content": "two", "parent": first, "branch": "draft" }))).await;
+    assert_eq!(status, 200);
+    assert_eq!(saved["parent"], first.as_str());
+    let second = saved["hash"].as_str().unwrap().to_string();
+
+    let (_, old) = call(&store, config, "POST", "/getFile", Some(json!({ "id": id, "hash": first }))).await;
+    let (_, new) = call(&store, config, "POST", "/getFile", Some(json!({ "id": id, "hash": second }))).await;
+    assert_eq!((old["content"].as_str(), new["content"].as_str()), (Some("one"), Some("two")));
+
+    let (_, history) = call(&store, config, "POST", "/getHistory", Some(json!({ "id": id }))).await;
+    assert_eq!(history["commits"].as_array().unwrap().len(), 2);
+    let mut refs = history["refs"].as_array().unwrap().iter()
+        .map(|git_ref| (git_ref["name"].as_str().unwrap().to_string(), git_ref["hash"].as_str().unwrap().to_string()))
+        .collect::<Vec<_>>();
+    refs.sort();
+    assert_eq!(refs, vec![("draft".to_string(), second.clone()), ("master".to_string(), first.clone())]);
+
+    let (_, list) = call(&store, config, "GET", "/v2/files", None).await;
+    assert_eq!(list["files"].as_array().unwrap().len(), 1);
+    assert_eq!(list["files"][0]["name"], "doc.md");
+
+    let (status, _) = call(&store, config, "POST", "/saveFile", Some(json!({ "id": id, "name": "doc.md", "content": "three", "parent": "0".repeat(40), "branch": "draft" }))).await;
+    assert_eq!(status, 400);
+
+    // draft has moved on from `first`, so saving on top of it again would discard `second`
+    let stale = json!({ "id": id, "name": "doc.md", "content": "stale", "parent": first, "branch": "draft" });
+    let (status, conflict) = call(&store, config, "POST", "/saveFile", Some(stale.clone())).await;
+    assert_eq!(status, 409);
+    assert_eq!(conflict["code"], "BRANCH_CONFLICT");
+    assert_eq!(conflict["details"]["head"], second.as_str());
+    let mut force = stale;
+    force["force"] = json!(true);
+    let (status, forced) = call(&store, config, "POST", "/saveFile", Some(force)).await;
+    assert_eq!(status, 200);
+    let (_, history) = call(&store, config, "POST", "/getHistory", Some(json!({ "id": id }))).await;
+    let draft = history["refs"].as_array().unwrap().iter().find(|git_ref| git_ref["name"] == "draft").unwrap();
+    assert_eq!(draft["hash"], forced["hash"]);
+
+    let (status, _) = call(&store, config, "POST", "/getFile", Some(json!({ "id": Uuid::new_v4(), "hash": first }))).await;
+    assert_eq!(status, 404);
+
+    let (status, _) = call(&store, config, "POST", "/deleteFile", Some(json!({ "id": id }))).await;
+    assert_eq!(status, 200);
+    let (_, list) = call(&store, config, "GET", "/v2/files", None).await;
+    assert!(list["files"].as_array().unwrap().is_empty());
+    let (status, _) = call(&store, config, "POST", "/getHistory", Some(json!({ "id": id }))).await;
+    assert_eq!(status, 404);
+}
+
+// Every storage refuses the same names, on create and on save, before writing anything
+async fn invalid_names_are_refused(store: Store, config: &Config) {
+    let (_, created) = call(&store, config, "POST", "/createFile", Some(json!({ "name": "doc.md" }))).await;
+    for name in ["", ".", "..", ".git", ".GIT", "a/b", "../escaped.md", "nul\0.md"] {
+        let (status, body) = call(&store, config, "POST", "/createFile", Some(json!({ "name": name, "content": "x" }))).await;
+        assert_eq!((status, body["code"].as_str()), (400, Some("INVALID_FILE_NAME")), "creating {name:?}");
+        let save = json!({ "id": created["id"], "name": name, "content": "x", "parent": created["hash"], "branch": "master" });
+        let (status, body) = call(&store, config, "POST", "/saveFile", Some(save)).await;
+        assert_eq!((status, body["code"].as_str()), (400, Some("INVALID_FILE_NAME")), "saving {name:?}");
+    }
+
+    let (_, list) = call(&store, config, "GET", "/v2/files", None).await;
+    assert_eq!(list["total"], 1);
+    let repositories = fs::read_dir(&config.files_dir).map(|entries| entries.filter(|entry| entry.as_ref().unwrap().path().is_dir()).count()).unwrap_or(0);
+    assert!(repositories <= 1);
+    assert!(!config.files_dir.join("escaped.md").exists());
+}
+
+#[tokio::test]
+async fn git_storage_through_the_api() {
+    let config = TempConfig::new();
+    let store: Store = Arc::new(GitStorage::new(files::repos(&config), config.arc()));
+
+    create_save_read_delete(store.clone(), &config).await;
+    invalid_names_are_refused(store, &config).await;
+}
+
+#[tokio::test]
+async fn bare_storage_through_the_api() {
+    let config = TempConfig::with(|config| config.storage = StorageKind::Bare);
+    let repos = files::repos(&config);
+    let store: Store = Arc::new(GitStorage::new(repos.clone(), config.arc()));
+
+    let created = store.create_file("doc.md".to_string(), Some("one".to_string()), None).unwrap();
+    let bare = git2::Repository::open(config.files_dir.join(created.id.to_string())).unwrap().is_bare();
+    store.delete_file(&created.id).unwrap();
+    create_save_read_delete(store.clone(), &config).await;
+    invalid_names_are_refused(store, &config).await;
+
+    assert!(bare);
+}
+
+#[tokio::test]
+async fn memory_storage_through_the_api() {
+    let config = TempConfig::new();
+
+    create_save_read_delete(Arc::new(MemoryStorage::new()), &config).await;
+    invalid_names_are_refused(Arc::new(MemoryStorage::new()), &config).await;
+    let wrote_files = config.files_dir.exists();
+
+    assert!(!wrote_files);
+}
+
+#[test]
+fn memory_export_holds_the_file_at_that_commit() {
+    let store = MemoryStorage::new();
+    let created = store.create_file("doc.tex".to_string(), Some("hello".to_string()), None).unwrap();
+
+    let export = store.export(&created.id, &created.hash).unwrap();
+    let content = fs::read_to_string(export.dir().join("doc.tex")).unwrap();
+    let dir = export.dir().to_path_buf();
+    drop(export);
+
+    assert_eq!(content, "hello");
+    assert!(!dir.exists());
+}
+
+#[test]
+fn memory_storage_has_nothing_open() {
+    let store = MemoryStorage::new();
+    store.create_file("doc.md".to_string(), None, None).unwrap();
+
+    assert_eq!(store.file_count(), 1);
+    assert_eq!(store.open_count(), 0);
+}
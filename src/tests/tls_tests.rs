@@ -0,0 +1,88 @@
+use std::fs;
+use std::path::PathBuf;
+
+use uuid::Uuid;
+
+use crate::config::{Config, ConfigArgs, TlsConfig};
+use crate::tls::ReloadableCert;
+
+// Writes a fresh self-signed certificate and key, returning the DER certificate for comparison
+fn write_self_signed(config: &TlsConfig) -> Vec<u8> {
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let pem = cert.serialize_pem().unwrap();
+    fs::write(&config.cert_path, &pem).unwrap();
+    fs::write(&config.key_path, cert.serialize_private_key_pem()).unwrap();
+    rustls_pemfile::certs(&mut pem.as_bytes()).unwrap().remove(0)
+}
+
+fn temp_tls_config() -> TlsConfig {
+    let dir = std::env::temp_dir().join(format!("remote-text-tls-{}", Uuid::new_v4()));
+    fs::create_dir_all(&dir).unwrap();
+    TlsConfig { cert_path: dir.join("cert.pem"), key_path: dir.join("key.pem") }
+}
+
+fn remove_tls_config(config: &TlsConfig) {
+    fs::remove_dir_all(config.cert_path.parent().unwrap()).unwrap();
+}
+
+#[test]
+fn loads_certificate_and_key() {
+    let config = temp_tls_config();
+    let der = write_self_signed(&config);
+
+    let cert = ReloadableCert::load(&config).unwrap();
+    remove_tls_config(&config);
+
+    assert_eq!(cert.current().cert[0].0, der);
+}
+
+#[test]
+fn reload_swaps_certificate() {
+    let config = temp_tls_config();
+    let first = write_self_signed(&config);
+    let cert = ReloadableCert::load(&config).unwrap();
+
+    let second = write_self_signed(&config);
+    cert.reload().unwrap();
+    remove_tls_config(&config);
+
+    assert_ne!(first, second);
+    assert_eq!(cert.current().cert[0].0, second);
+}
+
+#[test]
+fn failed_reload_keeps_previous_certificate() {
+    let config = temp_tls_config();
+    let der = write_self_signed(&config);
+    let cert = ReloadableCert::load(&config).unwrap();
+
+    fs::write(&config.cert_path, "not a certificate").unwrap();
+    let result = cert.reload();
+    remove_tls_config(&config);
+
+    assert!(result.is_err());
+    assert_eq!(cert.current().cert[0].0, der);
+}
+
+#[test]
+fn missing_key_is_an_error() {
+    let config = temp_tls_config();
+    write_self_signed(&config);
+    fs::remove_file(&config.key_path).unwrap();
+
+    let result = ReloadableCert::load(&config);
+    remove_tls_config(&config);
+
+    assert!(result.is_err());
+}
+
+#[test]
+fn tls_needs_both_cert_and_key() {
+    let args = ConfigArgs { tls_cert: Some(PathBuf::from("cert.pem")), ..ConfigArgs::default() };
+    assert!(Config::load(&args).is_err());
+
+    let args = ConfigArgs { tls_cert: Some(PathBuf::from("cert.pem")), tls_key: Some(PathBuf::from("key.pem")), ..ConfigArgs::default() };
+    let tls = Config::load(&args).unwrap().tls.unwrap();
+    assert_eq!(tls.cert_path, PathBuf::from("cert.pem"));
+    assert_eq!(tls.key_path, PathBuf::from("key.pem"));
+}
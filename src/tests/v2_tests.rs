@@ -0,0 +1,51 @@
+use std::sync::Arc;
+
+use serde_json::json;
+use warp::http::header;
+
+use crate::memory::MemoryStorage;
+use crate::storage::Store;
+use crate::tests::{call, reply, TempConfig};
+
+#[tokio::test]
+async fn v2_routes_share_files_with_the_legacy_routes() {
+    let config = TempConfig::new();
+    let store: Store = Arc::new(MemoryStorage::new());
+
+    let (status, created) = call(&store, &config, "POST", "/v2/files", Some(json!({ "name": "doc.md", "content": "one" }))).await;
+    assert_eq!(status, 200);
+    let (id, first) = (created["id"].as_str().unwrap().to_string(), created["hash"].as_str().unwrap().to_string());
+
+    // Saved through v2, with a branch name that needs escaping, and read through the legacy routes
+    let (status, saved) = call(&store, &config, "PUT", &format!("/v2/files/{id}/branches/feature%2Fx"), Some(json!({ "name": "doc.md", "content": "two", "parent": first }))).await;
+    assert_eq!(status, 200);
+    let second = saved["hash"].as_str().unwrap().to_string();
+    let (_, history) = call(&store, &config, "POST", "/getHistory", Some(json!({ "id": id }))).await;
+    assert!(history["refs"].as_array().unwrap().contains(&json!({ "name": "feature/x", "hash": second })));
+
+    let (status, file) = call(&store, &config, "GET", &format!("/v2/files/{id}/commits/{second}"), None).await;
+    assert_eq!((status, file["content"].as_str()), (200, Some("two")));
+    let cache_control = |response: &warp::http::Response<_>| response.headers().get(header::CACHE_CONTROL).map(|value| value.to_str().unwrap().to_string());
+    let found = reply(&store, &config, warp::test::request().method("GET").path(&format!("/v2/files/{id}/commits/{second}"))).await;
+    assert_eq!(cache_control(&found).as_deref(), Some("public, max-age=31536000, immutable"));
+    let (status, error) = call(&store, &config, "GET", &format!("/v2/files/{id}/commits/nope"), None).await;
+    assert_eq!((status, error["code"].as_str()), (400, Some("INVALID_HASH")));
+    let missing = reply(&store, &config, warp::test::request().method("GET").path(&format!("/v2/files/{id}/commits/nope"))).await;
+    assert_eq!(cache_control(&missing), None);
+
+    let (_, history) = call(&store, &config, "GET", &format!("/v2/files/{id}/history"), None).await;
+    assert_eq!(history["commits"].as_array().unwrap().len(), 2);
+    let (_, list) = call(&store, &config, "GET", "/v2/files", None).await;
+    assert_eq!(list["files"][0]["id"], id.as_str());
+    let (status, error) = call(&store, &config, "GET", &format!("/v2/files/{id}/commits/{first}/preview"), None).await;
+    assert_eq!((status, error["code"].as_str()), (404, Some("PREVIEW_NOT_FOUND")));
+    let (status, error) = call(&store, &config, "GET", &format!("/v2/files/{id}/commits/../preview"), None).await;
+    assert_eq!((status, error["code"].as_str()), (400, Some("INVALID_HASH")));
+
+    let (status, _) = call(&store, &config, "DELETE", &format!("/v2/files/{id}"), None).await;
+    assert_eq!(status, 200);
+    let (status, _) = call(&store, &config, "POST", "/getFile", Some(json!({ "id": id, "hash": first }))).await;
+    assert_eq!(status, 404);
+    let (status, error) = call(&store, &config, "DELETE", &format!("/v2/files/{id}"), None).await;
+    assert_eq!((status, error["code"].as_str()), (404, Some("FILE_NOT_FOUND")));
+}
@@ -0,0 +1,110 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use rustls_pemfile::Item;
+use tokio_rustls::rustls::{Certificate, PrivateKey, ServerConfig};
+use tokio_rustls::rustls::server::{ClientHello, ResolvesServerCert};
+use tokio_rustls::rustls::sign::{self, CertifiedKey};
+use tokio_rustls::TlsAcceptor;
+
+use crate::config::TlsConfig;
+
+/// A certificate resolver whose certificate can be swapped at runtime
+///
+/// Every new handshake picks up whichever certificate was loaded most recently. Connections that
+/// have already completed their handshake are unaffected by a reload.
+pub(crate) struct ReloadableCert {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+impl ReloadableCert {
+    pub(crate) fn load(config: &TlsConfig) -> Result<ReloadableCert, String> {
+        let key = load_certified_key(&config.cert_path, &config.key_path)?;
+        Ok(ReloadableCert {
+            cert_path: config.cert_path.clone(),
+            key_path: config.key_path.clone(),
+            current: RwLock::new(Arc::new(key)),
+        })
+    }
+
+    /// Re-reads the certificate and key from disk. On failure, the previous certificate stays in use
+    pub(crate) fn reload(&self) -> Result<(), String> {
+        let key = load_certified_key(&self.cert_path, &self.key_path)?;
+        *self.current.write().unwrap() = Arc::new(key);
+        Ok(())
+    }
+
+    pub(crate) fn current(&self) -> Arc<CertifiedKey> {
+        self.current.read().unwrap().clone()
+    }
+}
+
+impl ResolvesServerCert for ReloadableCert {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.current())
+    }
+}
+
+fn load_certified_key(cert_path: &Path, key_path: &Path) -> Result<CertifiedKey, String> {
+    let cert_file = File::open(cert_path)
+        .map_err(|err| format!("Unable to open TLS certificate {:?}: {err}", cert_path))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(cert_file))
+        .map_err(|err| format!("Unable to parse TLS certificate {:?}: {err}", cert_path))?;
+    if certs.is_empty() {
+        return Err(format!("No certificates found in {:?}", cert_path));
+    }
+
+    let key_file = File::open(key_path)
+        .map_err(|err| format!("Unable to open TLS key {:?}: {err}", key_path))?;
+    let mut key_reader = BufReader::new(key_file);
+    let key = loop {
+        match rustls_pemfile::read_one(&mut key_reader) {
+            Ok(Some(Item::RSAKey(key))) | Ok(Some(Item::PKCS8Key(key))) | Ok(Some(Item::ECKey(key))) => break key,
+            Ok(Some(_)) => continue,
+            Ok(None) => return Err(format!("No private key found in {:?}", key_path)),
+            Err(err) => return Err(format!("Unable to parse TLS key {:?}: {err}", key_path))
+        }
+    };
+    let signing_key = sign::any_supported_type(&PrivateKey(key))
+        .map_err(|err| format!("Unsupported TLS key {:?}: {err}", key_path))?;
+
+    Ok(CertifiedKey::new(certs.into_iter().map(Certificate).collect(), signing_key))
+}
+
+pub(crate) fn acceptor(cert: Arc<ReloadableCert>) -> TlsAcceptor {
+    let mut config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_cert_resolver(cert);
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    TlsAcceptor::from(Arc::new(config))
+}
+
+/// Reloads the certificate whenever the process receives SIGHUP
+#[cfg(unix)]
+pub(crate) fn reload_on_sighup(cert: Arc<ReloadableCert>) -> Result<(), String> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangups = signal(SignalKind::hangup())
+        .map_err(|err| format!("Unable to listen for SIGHUP: {err}"))?;
+    tokio::spawn(async move {
+        while hangups.recv().await.is_some() {
+            log::info!(target: "remote_text_server::tls", "Received SIGHUP; reloading certificate");
+            match cert.reload() {
+                Ok(_) => log::info!(target: "remote_text_server::tls", "Reloaded certificate"),
+                Err(msg) => log::error!(target: "remote_text_server::tls", "Unable to reload certificate, keeping the old one: {msg}")
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub(crate) fn reload_on_sighup(_cert: Arc<ReloadableCert>) -> Result<(), String> {
+    log::warn!(target: "remote_text_server::tls", "Certificate reloading is only supported on Unix");
+    Ok(())
+}